@@ -4,9 +4,12 @@ fn main() {
     let mut config = prost_build::Config::new();
     config.bytes(&["."]);
     config.type_attribute(".", "#[derive(PartialOrd)]");
-    config
+    config.type_attribute(".", "#[derive(::serde::Serialize, ::serde::Deserialize)]");
+    config.message_attribute(".", "#[serde(default)]");
+    config.enum_attribute(".", "#[serde(rename_all = \"snake_case\")]");
+    tonic_build::configure()
         .out_dir("src/pb")
-        .compile_protos(&["abi.proto"], &["."])
+        .compile_with_config(config, &["abi.proto"], &["."])
         .unwrap();
     Command::new("cargo")
         .args(&["fmt", "--", "src/*.rs"])
@@ -1,14 +1,7 @@
 use anyhow::Result;
-
-use bytes::BytesMut;
-use futures::prelude::*;
-use kv::{CommandRequest, CommandResponse};
-use prost::Message;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use kv::{CommandRequest, ProstClientStream};
+use tokio::net::TcpStream;
+use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -17,40 +10,18 @@ async fn main() -> Result<()> {
     let addr = "127.0.0.1:9527";
     // 连接服务器
     let stream = TcpStream::connect(addr).await?;
-    let mut stream = Framed::new(stream, LengthDelimitedCodec::new());
-
-    // // 使用 AsyncProstStream 来处理 TCP Frame
-    // let mut client =
-    //     AsyncBincodeStream::<_, CommandResponse, CommandRequest, _>::from(stream).for_async();
+    let mut client = ProstClientStream::new(stream);
 
     // 生成一个 HSET 命令
-    let cmd = CommandRequest::new_hset("table2", "heetrtllo", "yrtyytytrytr".into());
-    println!("1: {}", cmd.encoded_len());
-
-    let mut buf = BytesMut::new();
-
-    let _ = cmd.encode(&mut buf)?;
-    println!("2: {}", buf.len());
-
-    stream.send(buf.freeze()).await?;
-
-    // // 发送 HSET 命令
-    // client.send(cmd).await?;
-    // if let Some(Ok(data)) = client.next().await {
-    //     info!("Got response {:?}", data);
-    // }
-
-    // buf.clear();
-
-    // buf.resize(1024, 0u8);
-
-    // let n = stream.read_buffer(&mut buf).await.unwrap();
-
-    // buf.truncate(n);
+    let cmd = CommandRequest::new_hset("table1", "hello", "world".into());
 
-    // let resp = CommandResponse::decode(buf)?;
+    // 发送 HSET 命令
+    let data = client.execute(&cmd).await?;
+    info!("Got response {:?}", data);
 
-    // println!("{}", resp.status);
+    let cmd = CommandRequest::new_hget("table1", "hello");
+    let data = client.execute(&cmd).await?;
+    info!("Got response {:?}", data);
 
     Ok(())
 }
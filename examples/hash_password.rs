@@ -0,0 +1,13 @@
+use anyhow::Result;
+use kv::hash_password;
+
+/// 生成凭证文件的一行：cargo run --example hash_password -- <username> <password>
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(user), Some(password)) = (args.next(), args.next()) else {
+        eprintln!("Usage: hash_password <username> <password>");
+        std::process::exit(1);
+    };
+    println!("{}:{}", user, hash_password(&password)?);
+    Ok(())
+}
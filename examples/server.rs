@@ -1,13 +1,6 @@
 use anyhow::Result;
-
-use bytes::BytesMut;
-use futures::prelude::*;
-use kv::{CommandRequest, MemTable, Service};
-use prost::Message;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpListener,
-};
+use kv::{MemTable, ProstServerStream, ServerRunner, Service};
+use tokio::net::TcpListener;
 use tracing::info;
 
 #[tokio::main]
@@ -17,25 +10,11 @@ async fn main() -> Result<()> {
     let addr = "127.0.0.1:9527";
     let listener = TcpListener::bind(addr).await?;
     info!("Start listening on {}", addr);
-    loop {
-        let (mut stream, addr) = listener.accept().await?;
-        info!("Client {:?} connected", addr);
-        let svc = service.clone();
-        tokio::spawn(async move {
-            //
-            let mut buf = vec![0u8; 1024];
-            let n = stream.read(&mut buf).await.unwrap();
-            buf.truncate(n);
-            let cmd = CommandRequest::decode(buf.as_slice()).unwrap();
-            println!("{:?}", cmd.request_data);
-            let res = svc.execute(cmd);
-            info!("{}, {}", res.status, res.message);
-
-            let mut resp = BytesMut::new();
-
-            let _ = res.encode(&mut resp).unwrap();
-
-            stream.write(&resp).await.unwrap();
-        });
-    }
+    let report = ServerRunner::new(service)
+        .run(listener, tokio::signal::ctrl_c(), |stream, svc, token| {
+            ProstServerStream::new(stream).shutdown(token).process(svc)
+        })
+        .await?;
+    info!("Server stopped: {:?}", report);
+    Ok(())
 }
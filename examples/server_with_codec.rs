@@ -16,7 +16,7 @@ async fn main() -> Result<()> {
     loop {
         let (stream, addr) = listener.accept().await?;
         info!("Client {:?} connected", addr);
-        let svc = service.clone();
+        let svc = service.new_connection();
         tokio::spawn(async move {
             let mut stream = Framed::new(stream, LengthDelimitedCodec::new());
             while let Some(Ok(mut buf)) = stream.next().await {
@@ -1,41 +1,30 @@
 use anyhow::Result;
-
-use bytes::BytesMut;
-use futures::prelude::*;
-use kv::{CommandRequest, Service, SledDb};
-use prost::Message;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpListener,
-};
+use kv::{ProstServerStream, ServerRunner, Service, SledDb};
+use tokio::net::TcpListener;
 use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
-    let service = Service::new(SledDb::new("/Users/aji/Documents/projects/kv/examples"));
+    let service = Service::new(SledDb::try_new(std::env::temp_dir().join("kvserver"))?);
     let addr = "127.0.0.1:9527";
     let listener = TcpListener::bind(addr).await?;
     info!("Start listening on {}", addr);
-    loop {
-        let (mut stream, addr) = listener.accept().await?;
-        info!("Client {:?} connected", addr);
-        let svc = service.clone();
-        tokio::spawn(async move {
-            //
-            let mut buf = vec![0u8; 1024];
-            let n = stream.read(&mut buf).await.unwrap();
-            buf.truncate(n);
-            let cmd = CommandRequest::decode(buf.as_slice()).unwrap();
-            println!("{:?}", cmd.request_data);
-            let res = svc.execute(cmd);
-            info!("{}, {}", res.status, res.message);
-
-            let mut resp = BytesMut::new();
-
-            let _ = res.encode(&mut resp).unwrap();
-
-            stream.write(&resp).await.unwrap();
-        });
-    }
+    // 按 Ctrl-C 后等待连接处理完，再把 sled 的数据 flush 到磁盘
+    let runner = ServerRunner::new(service);
+    let requests = runner.graceful_shutdown().requests();
+    let report = runner
+        .run(
+            listener,
+            tokio::signal::ctrl_c(),
+            move |stream, svc, token| {
+                ProstServerStream::new(stream)
+                    .shutdown(token)
+                    .tracker(requests.clone())
+                    .process(svc)
+            },
+        )
+        .await?;
+    info!("Server stopped: {:?}", report);
+    Ok(())
 }
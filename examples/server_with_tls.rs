@@ -0,0 +1,42 @@
+use anyhow::Result;
+use kv::{client_identity, MemTable, ProstServerStream, Service, TlsServerAcceptor};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let service = Service::new(MemTable::new());
+    // 开启 mTLS，客户端证书的 CN 会成为连接的身份
+    let acceptor = TlsServerAcceptor::new(
+        "fixtures/server.cert",
+        "fixtures/server.key",
+        Some("fixtures/ca.cert"),
+    )?;
+    let addr = "127.0.0.1:9527";
+    let listener = TcpListener::bind(addr).await?;
+    info!("Start listening on {}", addr);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let svc = service.new_connection();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => return warn!("TLS handshake with {:?} failed: {}", addr, e),
+            };
+            if let Some(identity) = client_identity(&stream) {
+                svc.context().set_peer_identity(identity);
+            }
+            info!(
+                "Client {:?} connected as {:?}",
+                addr,
+                svc.context().identity()
+            );
+            if let Err(e) = ProstServerStream::new(stream).process(svc).await {
+                warn!("Failed to serve client {:?}: {}", addr, e);
+            }
+            info!("Client {:?} disconnected", addr);
+        });
+    }
+}
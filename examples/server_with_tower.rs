@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use kv::{MemTable, ProstServerStream, Service};
+use tokio::net::TcpListener;
+use tower::ServiceBuilder;
+use tracing::{info, warn};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let service = Service::new(MemTable::new());
+    let addr = "127.0.0.1:9527";
+    let listener = TcpListener::bind(addr).await?;
+    info!("Start listening on {}", addr);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        info!("Client {:?} connected", addr);
+        // 每个连接一个 layer 栈：超时 + 过载保护 + 并发限制
+        let svc = ServiceBuilder::new()
+            .timeout(Duration::from_secs(5))
+            .load_shed()
+            .concurrency_limit(64)
+            .service(service.new_connection());
+        tokio::spawn(async move {
+            if let Err(e) = ProstServerStream::new(stream).process(svc).await {
+                warn!("Failed to serve client {:?}: {}", addr, e);
+            }
+            info!("Client {:?} disconnected", addr);
+        });
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    io::{self, BufRead, IsTerminal},
+    path::PathBuf,
+};
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use kv::{
+    format_response, parse_args, parse_command, CommandRequest, Compression, KvError,
+    ProstClientStream, TlsClientConnector, COMMAND_NAMES,
+};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, Context, Editor, Helper,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+/// KV 命令行客户端
+///
+/// 不带 COMMAND 且 stdin 是终端时进入交互模式；否则执行 COMMAND，或者逐行执行 stdin 中的命令。
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// 服务器地址，Unix domain socket 使用 unix:///path/to/kv.sock
+    #[arg(short, long, default_value = "127.0.0.1:9527")]
+    addr: String,
+    /// 使用 TLS 连接服务器
+    #[arg(long)]
+    tls: bool,
+    /// 服务器证书中的域名
+    #[arg(long, default_value = "kvserver.acme.inc")]
+    domain: String,
+    /// 签署服务器证书的 CA
+    #[arg(long)]
+    ca: Option<String>,
+    /// mTLS 使用的客户端证书
+    #[arg(long, requires = "key")]
+    cert: Option<String>,
+    /// mTLS 使用的客户端私钥
+    #[arg(long, requires = "cert")]
+    key: Option<String>,
+    /// 要执行的命令，如 `hget users alice`
+    command: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let tls = args.tls || args.ca.is_some() || args.cert.is_some();
+    #[cfg(unix)]
+    if let Some(path) = args.addr.strip_prefix(kv::UNIX_SCHEME) {
+        if tls {
+            bail!("TLS is not supported over Unix domain sockets");
+        }
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        return run(&args, ProstClientStream::new(stream)).await;
+    }
+    let stream = TcpStream::connect(&args.addr).await?;
+
+    if tls {
+        let identity = args.cert.as_deref().zip(args.key.as_deref());
+        let connector = TlsClientConnector::new(&args.domain, identity, args.ca.as_deref())?;
+        let stream = connector.connect(stream).await?;
+        run(&args, ProstClientStream::new(stream)).await
+    } else {
+        run(&args, ProstClientStream::new(stream)).await
+    }
+}
+
+async fn run<S>(args: &Args, client: ProstClientStream<S>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let mut client = client.client_name("kv-cli");
+    client.negotiate(&Compression::SUPPORTED).await?;
+
+    if !args.command.is_empty() {
+        // 参数已经被 shell 拆开了，直接解析，和 REPL 中一样推断 value 的类型
+        let ok = execute(&mut client, parse_args(&args.command)).await?;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    if !io::stdin().is_terminal() {
+        let mut ok = true;
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                ok &= execute(&mut client, parse_command(&line)).await?;
+            }
+        }
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    repl(&args.addr, &mut client).await
+}
+
+async fn repl<S>(addr: &str, client: &mut ProstClientStream<S>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let mut rl: Editor<CommandHelper, DefaultHistory> = Editor::new()?;
+    rl.set_helper(Some(CommandHelper));
+    let history = history_file();
+    if let Some(path) = &history {
+        let _ = rl.load_history(path);
+    }
+
+    let prompt = format!("{}> ", addr);
+    loop {
+        match rl.readline(&prompt) {
+            Ok(line) => {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                rl.add_history_entry(line)?;
+                if line == "quit" || line == "exit" {
+                    break;
+                }
+                execute(client, parse_command(line)).await?;
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    if let Some(path) = &history {
+        rl.save_history(path)?;
+    }
+    Ok(())
+}
+
+/// 执行解析好的命令并打印结果，返回命令是否成功
+///
+/// 命令解析失败只打印错误，不中断 REPL；网络错误直接返回。
+async fn execute<S>(
+    client: &mut ProstClientStream<S>,
+    cmd: Result<CommandRequest, KvError>,
+) -> Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let cmd = match cmd {
+        Ok(cmd) => cmd,
+        Err(e) => {
+            eprintln!("(error) {}", e);
+            return Ok(false);
+        }
+    };
+    let res = client.execute(&cmd).await?;
+    println!("{}", format_response(&res));
+    Ok((200..300).contains(&res.status))
+}
+
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".kv_history"))
+}
+
+/// 提供命令名的 tab 补全
+struct CommandHelper;
+
+impl Helper for CommandHelper {}
+impl Highlighter for CommandHelper {}
+impl Validator for CommandHelper {}
+
+impl Hinter for CommandHelper {
+    type Hint = String;
+}
+
+impl Completer for CommandHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let prefix = &line[..pos];
+        // 只补全第一个单词
+        if prefix.contains(char::is_whitespace) {
+            return Ok((pos, vec![]));
+        }
+        let prefix = prefix.to_ascii_lowercase();
+        let candidates = COMMAND_NAMES
+            .iter()
+            .filter(|name| name.starts_with(&prefix))
+            .map(|name| format!("{} ", name))
+            .collect();
+        Ok((0, candidates))
+    }
+}
@@ -0,0 +1,734 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use bytes::BytesMut;
+use clap::Parser;
+use futures::{stream, Stream};
+use kv::{
+    client_identity, quic_endpoint, serve_http, Acl, AuthConfig, Authenticator, CommandResponse,
+    ConnectionLimiter, ConnectionMetrics, ConnectionStats, FrameCoder, GracefulShutdown,
+    GrpcConnection, GrpcService, KvError, LimitsConfig, MemTable, ProstServerStream,
+    QuicServerConnection, RespServerStream, ServerConfig, ServerRunner, Service, ServiceInner,
+    SledDb, Storage, StorageConfig, TlsServerAcceptor, YamuxServerConnection, MAX_STREAMS,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    signal,
+    sync::mpsc,
+    time,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{debug, info, warn};
+
+/// KV server
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// 配置文件路径
+    #[arg(short, long, default_value = "kv-server.toml")]
+    config: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let config = ServerConfig::load(&args.config)?;
+    tracing_subscriber::fmt()
+        .with_max_level(config.log_level()?)
+        .init();
+
+    match &config.storage {
+        StorageConfig::Memory => run(config.clone(), MemTable::new()).await,
+        StorageConfig::Sled { path } => run(config.clone(), SledDb::try_new(path)?).await,
+    }
+}
+
+async fn run<Store>(config: ServerConfig, store: Store) -> Result<()>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    let mut inner =
+        ServiceInner::new(store).script_max_operations(config.limits.script_max_operations);
+    if let Some(auth) = &config.auth {
+        inner = inner.authenticator(authenticator(auth)?);
+    }
+    if !config.acl.is_empty() {
+        inner = inner.acl(Arc::new(Acl::new(config.acl_rules()?)));
+    }
+    let service: Service<Store> = inner.into();
+    let acceptor = match &config.tls {
+        Some(tls) => {
+            let acceptor = TlsServerAcceptor::new(&tls.cert, &tls.key, tls.client_ca.as_deref())?;
+            acceptor.watch(Duration::from_millis(tls.reload_interval_ms));
+            Some(acceptor)
+        }
+        None => None,
+    };
+
+    // 所有 listener 共用连接数的限制和统计
+    let limiter = ConnectionLimiter::new(
+        config.limits.max_connections,
+        config.limits.max_connections_per_ip,
+    );
+    let metrics = limiter.metrics().clone();
+    tokio::spawn(log_metrics(metrics.clone()));
+
+    // 所有 listener 上的连接都由 runner 跟踪，退出时一起等待
+    let runner = ServerRunner::new(service.clone())
+        .drain_timeout(Duration::from_millis(config.limits.drain_timeout_ms));
+    let shutdown = runner.graceful_shutdown().clone();
+
+    if let Some(addr) = config.resp_addr()? {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Start listening for RESP on {}", addr);
+        tokio::spawn(serve_resp(
+            listener,
+            service.clone(),
+            acceptor.clone(),
+            config.clone(),
+            shutdown.clone(),
+            limiter.clone(),
+        ));
+    }
+
+    if let Some(addr) = config.http_addr()? {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Start listening for HTTP on {}", addr);
+        tokio::spawn(serve_http_gateway(
+            listener,
+            service.clone(),
+            acceptor.as_ref().map(|a| a.alpn(&["http/1.1"])),
+            config.clone(),
+            shutdown.clone(),
+            limiter.clone(),
+        ));
+    }
+
+    // gRPC 的请求都很短，收到退出信号后由 tonic 自己等待请求处理完，runner 等 server 退出后才 flush 存储
+    if let Some(addr) = config.grpc_addr()? {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Start listening for gRPC on {}", addr);
+        let limits = &config.limits;
+        let incoming = grpc_incoming(
+            listener,
+            acceptor.as_ref().map(|a| a.alpn(&["h2"])),
+            shutdown.token(),
+            limiter.clone(),
+        );
+        let server = tonic::transport::Server::builder()
+            .concurrency_limit_per_connection(limits.max_concurrent_requests)
+            .add_service(
+                GrpcService::new(service.clone())
+                    .limits(limits)
+                    .into_server(),
+            )
+            .serve_with_incoming_shutdown(incoming, shutdown.token().cancelled_owned());
+        shutdown.spawn_server(async move {
+            if let Err(e) = server.await {
+                warn!("gRPC server stopped: {}", e);
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    if let Some(unix) = &config.unix {
+        let listener = kv::bind_unix(&unix.path, unix.mode)?;
+        info!("Start listening on {}", unix.path.display());
+        tokio::spawn(serve_unix(
+            listener,
+            service.clone(),
+            config.clone(),
+            shutdown.clone(),
+            limiter.clone(),
+        ));
+    }
+
+    if let (Some(addr), Some(acceptor)) = (config.quic_addr()?, &acceptor) {
+        let endpoint = quic_endpoint(addr, acceptor)?;
+        info!("Start listening for QUIC on {}", addr);
+        tokio::spawn(serve_quic(
+            endpoint,
+            service.clone(),
+            config.clone(),
+            shutdown.clone(),
+            limiter.clone(),
+        ));
+    }
+
+    if let Some(addr) = config.multiplex_addr()? {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Start listening for multiplexed connections on {}", addr);
+        tokio::spawn(serve_multiplex(
+            listener,
+            service.clone(),
+            acceptor.clone(),
+            config.clone(),
+            shutdown.clone(),
+            limiter.clone(),
+        ));
+    }
+
+    let addr = config.addr()?;
+    let listener = TcpListener::bind(addr).await?;
+    info!("Start listening on {}", addr);
+
+    let config = Arc::new(config);
+    let conn_metrics = metrics.clone();
+    let requests = shutdown.requests();
+    // 收到退出信号后，runner 会等待连接处理完并 flush 存储
+    runner
+        .run(listener, shutdown_signal(), move |stream, conn, token| {
+            // 超过连接数限制的连接在 TLS 握手之前就关闭，没有 TLS 时先写回 503，runner 会把错误打到日志里
+            let permit = limiter.try_acquire(stream.peer_addr().ok().map(|addr| addr.ip()));
+            let ctx = conn.context().clone();
+            let svc = conn.layered(&config.limits);
+            let acceptor = acceptor.clone();
+            let config = config.clone();
+            let metrics = conn_metrics.clone();
+            let requests = requests.clone();
+            async move {
+                let _permit = match permit {
+                    Ok(permit) => permit,
+                    Err(e) if acceptor.is_none() => return Err(reject(stream, e).await),
+                    Err(e) => return Err(e),
+                };
+                match acceptor {
+                    Some(acceptor) => {
+                        let stream = acceptor.accept(stream).await?;
+                        if let Some(identity) = client_identity(&stream) {
+                            ctx.set_peer_identity(identity);
+                        }
+                        prost_stream(stream, &config, token, requests, metrics)
+                            .process(svc)
+                            .await
+                    }
+                    None => {
+                        prost_stream(stream, &config, token, requests, metrics)
+                            .process(svc)
+                            .await
+                    }
+                }
+            }
+        })
+        .await?;
+    info!("Connections: {:?}", metrics.snapshot());
+    Ok(())
+}
+
+/// 按配置加载凭证；使用凭证文件时定期检查，文件修改后不需要重启就能更换凭证
+fn authenticator(config: &AuthConfig) -> Result<Arc<Authenticator>> {
+    let auth = match &config.credentials {
+        Some(path) => Authenticator::from_file(path)?,
+        None => Authenticator::new(config.users.clone().into_iter().collect()),
+    };
+    let auth = Arc::new(auth);
+    auth.watch(Duration::from_millis(config.reload_interval_ms));
+    info!("Authentication is enabled");
+    Ok(auth)
+}
+
+/// 给超过连接数限制的客户端写回 503 的响应后关闭连接，返回同样的错误
+async fn reject<S>(mut stream: S, e: KvError) -> KvError
+where
+    S: AsyncWrite + Unpin,
+{
+    let res = CommandResponse::from(e);
+    let mut buf = BytesMut::new();
+    if res.encode_frame(&mut buf).is_ok() {
+        let _ = stream.write_all(&buf).await;
+        let _ = stream.shutdown().await;
+    }
+    res.into_result().unwrap_err()
+}
+
+/// 按配置创建 ProstServerStream，TCP 和 Unix socket 共用
+fn prost_stream<S>(
+    stream: S,
+    config: &ServerConfig,
+    token: CancellationToken,
+    requests: TaskTracker,
+    metrics: Arc<ConnectionMetrics>,
+) -> ProstServerStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let limits = &config.limits;
+    let mut stream = ProstServerStream::new(stream)
+        .compressions(config.compressions().unwrap_or_default())
+        .compression_limit(config.compression.limit)
+        .max_frame_size(limits.max_frame_size, limits.max_decompressed_size)
+        .shutdown(token)
+        .tracker(requests)
+        .metrics(metrics);
+    if let Some(timeout) = limits.idle_timeout() {
+        stream = stream.idle_timeout(timeout);
+    }
+    if let Some(interval) = limits.ping_interval() {
+        stream = stream.ping_interval(interval);
+    }
+    stream
+}
+
+/// 定期把连接的统计打到日志里，没有变化时不打
+async fn log_metrics(metrics: Arc<ConnectionMetrics>) {
+    let mut last = ConnectionStats::default();
+    let mut interval = time::interval(METRICS_INTERVAL);
+    loop {
+        interval.tick().await;
+        let stats = metrics.snapshot();
+        if stats != last {
+            info!("Connections: {:?}", stats);
+            last = stats;
+        }
+    }
+}
+
+/// 接受 RESP 连接，让 Redis 客户端也可以访问
+async fn serve_resp<Store>(
+    listener: TcpListener,
+    service: Service<Store>,
+    acceptor: Option<TlsServerAcceptor>,
+    config: ServerConfig,
+    shutdown: GracefulShutdown,
+    limiter: ConnectionLimiter,
+) where
+    Store: Storage + Send + Sync + 'static,
+{
+    let limits = config.limits;
+    let token = shutdown.token();
+    loop {
+        let res = tokio::select! {
+            res = listener.accept() => res,
+            _ = token.cancelled() => break,
+        };
+        let (stream, addr) = match res {
+            Ok(res) => res,
+            Err(e) => {
+                warn!("Failed to accept RESP connection: {}", e);
+                time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let permit = match limiter.try_acquire(Some(addr.ip())) {
+            Ok(permit) => permit,
+            Err(e) => {
+                warn!("Rejected RESP client {:?}: {}", addr, e);
+                continue;
+            }
+        };
+        info!("RESP client {:?} connected", addr);
+        let conn = service.new_connection();
+        let ctx = conn.context().clone();
+        let svc = conn.layered(&limits);
+        let acceptor = acceptor.clone();
+        let limits = limits.clone();
+        let metrics = limiter.metrics().clone();
+        let token = token.clone();
+        shutdown.spawn(async move {
+            let _permit = permit;
+            let res = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        if let Some(identity) = client_identity(&stream) {
+                            ctx.set_peer_identity(identity);
+                        }
+                        resp_stream(stream, &limits, token, metrics)
+                            .process(svc)
+                            .await
+                    }
+                    Err(e) => Err(e),
+                },
+                None => {
+                    resp_stream(stream, &limits, token, metrics)
+                        .process(svc)
+                        .await
+                }
+            };
+            if let Err(e) = res {
+                warn!("Failed to serve RESP client {:?}: {}", addr, e);
+            }
+            info!("RESP client {:?} disconnected", addr);
+        });
+    }
+}
+
+/// 接受多路复用的连接，一个连接上的所有逻辑 stream 共用连接的上下文（mTLS 身份、Auth 登录的状态）
+///
+/// 每个 Command stream 和 TCP 连接一样处理，超时、限流和 frame 的配置都相同。
+async fn serve_multiplex<Store>(
+    listener: TcpListener,
+    service: Service<Store>,
+    acceptor: Option<TlsServerAcceptor>,
+    config: ServerConfig,
+    shutdown: GracefulShutdown,
+    limiter: ConnectionLimiter,
+) where
+    Store: Storage + Send + Sync + 'static,
+{
+    let config = Arc::new(config);
+    let token = shutdown.token();
+    loop {
+        let res = tokio::select! {
+            res = listener.accept() => res,
+            _ = token.cancelled() => break,
+        };
+        let (stream, addr) = match res {
+            Ok(res) => res,
+            Err(e) => {
+                warn!("Failed to accept multiplexed connection: {}", e);
+                time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let permit = match limiter.try_acquire(Some(addr.ip())) {
+            Ok(permit) => permit,
+            Err(e) => {
+                warn!("Rejected multiplexed client {:?}: {}", addr, e);
+                continue;
+            }
+        };
+        info!("Multiplexed client {:?} connected", addr);
+        let conn = service.new_connection();
+        let acceptor = acceptor.clone();
+        let config = config.clone();
+        let token = token.clone();
+        let requests = shutdown.requests();
+        let metrics = limiter.metrics().clone();
+        shutdown.spawn(async move {
+            let _permit = permit;
+            let max_streams = config
+                .multiplex
+                .as_ref()
+                .map_or(MAX_STREAMS, |m| m.max_streams);
+            let serve = {
+                let (config, token, requests) = (config.clone(), token.clone(), requests.clone());
+                move |stream, svc: Service<Store>| {
+                    let stream = prost_stream(
+                        stream,
+                        &config,
+                        token.clone(),
+                        requests.clone(),
+                        metrics.clone(),
+                    );
+                    stream.process(svc.layered(&config.limits))
+                }
+            };
+            let res = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        if let Some(identity) = client_identity(&stream) {
+                            conn.context().set_peer_identity(identity);
+                        }
+                        YamuxServerConnection::new(stream)
+                            .max_streams(max_streams)
+                            .shutdown(token)
+                            .tracker(requests)
+                            .process(conn, serve)
+                            .await
+                    }
+                    Err(e) => Err(e),
+                },
+                None => {
+                    YamuxServerConnection::new(stream)
+                        .max_streams(max_streams)
+                        .shutdown(token)
+                        .tracker(requests)
+                        .process(conn, serve)
+                        .await
+                }
+            };
+            if let Err(e) = res {
+                warn!("Failed to serve multiplexed client {:?}: {}", addr, e);
+            }
+            info!("Multiplexed client {:?} disconnected", addr);
+        });
+    }
+}
+
+/// 接受 HTTP 网关的连接，和 RESP 一样支持 TLS，受连接数限制
+async fn serve_http_gateway<Store>(
+    listener: TcpListener,
+    service: Service<Store>,
+    acceptor: Option<TlsServerAcceptor>,
+    config: ServerConfig,
+    shutdown: GracefulShutdown,
+    limiter: ConnectionLimiter,
+) where
+    Store: Storage + Send + Sync + 'static,
+{
+    let limits = config.limits;
+    let token = shutdown.token();
+    loop {
+        let res = tokio::select! {
+            res = listener.accept() => res,
+            _ = token.cancelled() => break,
+        };
+        let (stream, addr) = match res {
+            Ok(res) => res,
+            Err(e) => {
+                warn!("Failed to accept HTTP connection: {}", e);
+                time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let permit = match limiter.try_acquire(Some(addr.ip())) {
+            Ok(permit) => permit,
+            Err(e) => {
+                warn!("Rejected HTTP client {:?}: {}", addr, e);
+                continue;
+            }
+        };
+        debug!("HTTP client {:?} connected", addr);
+        let service = service.clone();
+        let acceptor = acceptor.clone();
+        let limits = limits.clone();
+        let token = token.clone();
+        shutdown.spawn(async move {
+            let _permit = permit;
+            let res = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        let identity = client_identity(&stream);
+                        serve_http(stream, service, &limits, identity, token).await
+                    }
+                    Err(e) => Err(e),
+                },
+                None => serve_http(stream, service, &limits, None, token).await,
+            };
+            if let Err(e) = res {
+                warn!("Failed to serve HTTP client {:?}: {}", addr, e);
+            }
+            debug!("HTTP client {:?} disconnected", addr);
+        });
+    }
+}
+
+/// 接受 gRPC 的连接交给 tonic，和其他 listener 一样支持 TLS，受连接数限制
+///
+/// TLS 握手在单独的任务里进行，慢的客户端不会挡住其他连接。
+fn grpc_incoming(
+    listener: TcpListener,
+    acceptor: Option<TlsServerAcceptor>,
+    token: CancellationToken,
+    limiter: ConnectionLimiter,
+) -> impl Stream<Item = Result<GrpcConnection, std::io::Error>> {
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(async move {
+        loop {
+            let res = tokio::select! {
+                res = listener.accept() => res,
+                _ = token.cancelled() => break,
+            };
+            let (stream, addr) = match res {
+                Ok(res) => res,
+                Err(e) => {
+                    warn!("Failed to accept gRPC connection: {}", e);
+                    time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            let permit = match limiter.try_acquire(Some(addr.ip())) {
+                Ok(permit) => permit,
+                Err(e) => {
+                    warn!("Rejected gRPC client {:?}: {}", addr, e);
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let conn = match acceptor {
+                    Some(acceptor) => match acceptor.accept(stream).await {
+                        Ok(stream) => {
+                            let identity = client_identity(&stream);
+                            GrpcConnection::new(stream, identity)
+                        }
+                        Err(e) => {
+                            warn!("Failed to accept gRPC client {:?}: {}", addr, e);
+                            return;
+                        }
+                    },
+                    None => GrpcConnection::new(stream, None),
+                };
+                let _ = tx.send(Ok(conn.permit(permit))).await;
+            });
+        }
+    });
+    stream::unfold(rx, |mut rx| async move {
+        let conn = rx.recv().await?;
+        Some((conn, rx))
+    })
+}
+
+fn resp_stream<S>(
+    stream: S,
+    limits: &LimitsConfig,
+    token: CancellationToken,
+    metrics: Arc<ConnectionMetrics>,
+) -> RespServerStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let stream = RespServerStream::new(stream)
+        .max_request_size(limits.max_frame_size)
+        .shutdown(token)
+        .metrics(metrics);
+    match limits.idle_timeout() {
+        Some(timeout) => stream.idle_timeout(timeout),
+        None => stream,
+    }
+}
+
+/// 接受 QUIC 连接，每个请求使用一个 stream
+async fn serve_quic<Store>(
+    endpoint: quinn::Endpoint,
+    service: Service<Store>,
+    config: ServerConfig,
+    shutdown: GracefulShutdown,
+    limiter: ConnectionLimiter,
+) where
+    Store: Storage + Send + Sync + 'static,
+{
+    let limits = config.limits;
+    let token = shutdown.token();
+    loop {
+        let incoming = tokio::select! {
+            incoming = endpoint.accept() => incoming,
+            _ = token.cancelled() => break,
+        };
+        let Some(incoming) = incoming else {
+            break;
+        };
+        let addr = incoming.remote_address();
+        let permit = match limiter.try_acquire(Some(addr.ip())) {
+            Ok(permit) => permit,
+            Err(e) => {
+                warn!("Rejected QUIC client {:?}: {}", addr, e);
+                incoming.refuse();
+                continue;
+            }
+        };
+        let svc = service.new_connection();
+        let limits = limits.clone();
+        let token = token.clone();
+        let requests = shutdown.requests();
+        shutdown.spawn(async move {
+            let _permit = permit;
+            let conn = match incoming.await {
+                Ok(conn) => QuicServerConnection::new(conn),
+                Err(e) => {
+                    warn!("Failed to accept QUIC connection from {:?}: {}", addr, e);
+                    return;
+                }
+            };
+            info!("QUIC client {:?} connected", addr);
+            if let Some(identity) = conn.peer_identity() {
+                svc.context().set_peer_identity(identity);
+            }
+            let res = conn
+                .max_frame_size(limits.max_frame_size, limits.max_decompressed_size)
+                .shutdown(token)
+                .tracker(requests)
+                .process(svc.layered(&limits))
+                .await;
+            if let Err(e) = res {
+                warn!("Failed to serve QUIC client {:?}: {}", addr, e);
+            }
+            info!("QUIC client {:?} disconnected", addr);
+        });
+    }
+}
+
+/// 接受 Unix domain socket 连接，和 TCP 使用同样的协议，但不使用 TLS
+#[cfg(unix)]
+async fn serve_unix<Store>(
+    listener: tokio::net::UnixListener,
+    service: Service<Store>,
+    config: ServerConfig,
+    shutdown: GracefulShutdown,
+    limiter: ConnectionLimiter,
+) where
+    Store: Storage + Send + Sync + 'static,
+{
+    let token = shutdown.token();
+    loop {
+        let res = tokio::select! {
+            res = listener.accept() => res,
+            _ = token.cancelled() => break,
+        };
+        let stream = match res {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Failed to accept unix socket connection: {}", e);
+                time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let peer = stream
+            .peer_cred()
+            .map(|cred| cred.uid().to_string())
+            .unwrap_or_else(|_| "unknown".into());
+        // Unix socket 没有 IP，只算总连接数
+        let permit = match limiter.try_acquire(None) {
+            Ok(permit) => permit,
+            Err(e) => {
+                warn!("Rejected unix socket client (uid {}): {}", peer, e);
+                tokio::spawn(reject(stream, e));
+                continue;
+            }
+        };
+        info!("Unix socket client (uid {}) connected", peer);
+        let svc = service.new_connection().layered(&config.limits);
+        let stream = prost_stream(
+            stream,
+            &config,
+            token.clone(),
+            shutdown.requests(),
+            limiter.metrics().clone(),
+        );
+        shutdown.spawn(async move {
+            let _permit = permit;
+            if let Err(e) = stream.process(svc).await {
+                warn!("Failed to serve unix socket client (uid {}): {}", peer, e);
+            }
+            info!("Unix socket client (uid {}) disconnected", peer);
+        });
+    }
+}
+
+/// accept 失败（如文件描述符用完）之后等一会儿再继续，避免空转
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// 多久打一次连接的统计
+const METRICS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 等待 SIGTERM 或 Ctrl-C
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received Ctrl-C"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
@@ -0,0 +1,384 @@
+use std::fmt::Write;
+
+use crate::{command_request::RequestData, value, *};
+
+/// kv-cli 支持补全的命令名
+pub const COMMAND_NAMES: &[&str] = &[
+    "hget", "hgetall", "hmget", "hset", "hmset", "hdel", "hmdel", "hexist", "hmexist", "eval",
+    "auth", "ping",
+];
+
+/// 把一行文本解析成 CommandRequest
+///
+/// 参数以空格分隔，可以用单引号或双引号包含空格，双引号内支持 `\"`、`\\`、`\n`、`\t` 转义。
+/// 未加引号的 value 会依次尝试解析为整数、浮点数、布尔值，否则作为字符串；加了引号的 value 总是字符串。
+/// 不认识的命令名会作为自定义命令发给服务器，如 `incr counters visits 1`。
+///
+/// eval 的格式为：`eval <script> <ntables> <table>... <nkeys> <key>... [arg...]`
+pub fn parse_command(line: &str) -> Result<CommandRequest, KvError> {
+    parse_tokens(&tokenize(line)?)
+}
+
+/// 把已经拆好的参数（如 shell 传进来的命令行参数）解析成 CommandRequest
+///
+/// 每个参数都当作没有引号的 value，同样会推断类型；参数里的引号和转义不做处理。
+pub fn parse_args(args: &[String]) -> Result<CommandRequest, KvError> {
+    let tokens: Vec<_> = args
+        .iter()
+        .map(|text| Token {
+            text: text.clone(),
+            quoted: false,
+        })
+        .collect();
+    parse_tokens(&tokens)
+}
+
+fn parse_tokens(tokens: &[Token]) -> Result<CommandRequest, KvError> {
+    let (name, args) = tokens
+        .split_first()
+        .ok_or_else(|| KvError::InvalidCommand("Empty command".into()))?;
+    let name = name.text.to_ascii_lowercase();
+
+    let data = match name.as_str() {
+        "hget" => {
+            let [table, key] = exact::<2>(&name, args)?;
+            RequestData::Hget(Hget { table, key })
+        }
+        "hgetall" => {
+            let [table] = exact::<1>(&name, args)?;
+            RequestData::Hgetall(Hgetall {
+                table,
+                chunk_size: 0,
+            })
+        }
+        "hset" => {
+            let (table, key, value) = match args {
+                [table, key, value] => (table.text.clone(), key.text.clone(), value.to_value()),
+                _ => return Err(usage(&name, "<table> <key> <value>")),
+            };
+            RequestData::Hset(Hset {
+                table,
+                pair: Some(Kvpair::new(key, value)),
+            })
+        }
+        "hmset" => {
+            let (table, rest) = match args {
+                [table, rest @ ..] if !rest.is_empty() && rest.len() % 2 == 0 => (table, rest),
+                _ => return Err(usage(&name, "<table> <key> <value> [<key> <value>...]")),
+            };
+            let pairs = rest
+                .chunks(2)
+                .map(|kv| Kvpair::new(kv[0].text.clone(), kv[1].to_value()))
+                .collect();
+            RequestData::Hmset(Hmset {
+                table: table.text.clone(),
+                pairs,
+            })
+        }
+        "hdel" => {
+            let [table, key] = exact::<2>(&name, args)?;
+            RequestData::Hdel(Hdel { table, key })
+        }
+        "hexist" => {
+            let [table, key] = exact::<2>(&name, args)?;
+            RequestData::Hexist(Hexist { table, key })
+        }
+        "hmget" | "hmdel" | "hmexist" => {
+            let (table, keys) = match args {
+                [table, keys @ ..] if !keys.is_empty() => (
+                    table.text.clone(),
+                    keys.iter().map(|k| k.text.clone()).collect(),
+                ),
+                _ => return Err(usage(&name, "<table> <key> [<key>...]")),
+            };
+            match name.as_str() {
+                "hmget" => RequestData::Hmget(Hmget { table, keys }),
+                "hmdel" => RequestData::Hmdel(Hmdel { table, keys }),
+                _ => RequestData::Hmexist(Hmexist { table, keys }),
+            }
+        }
+        "auth" => {
+            let [username, password] = exact::<2>(&name, args)?;
+            RequestData::Auth(Auth { username, password })
+        }
+        "eval" => parse_eval(args)?,
+        "ping" => {
+            let [] = exact::<0>(&name, args)?;
+            RequestData::Ping(Ping {})
+        }
+        _ => RequestData::Custom(Custom {
+            name: name.to_ascii_uppercase(),
+            args: args.iter().map(Token::to_value).collect(),
+        }),
+    };
+
+    Ok(CommandRequest {
+        request_data: Some(data),
+        ..Default::default()
+    })
+}
+
+/// 把 CommandResponse 格式化成适合人阅读的文本
+pub fn format_response(res: &CommandResponse) -> String {
+    if !(200..300).contains(&res.status) {
+        return format!("(error {}) {}", res.status, res.message);
+    }
+
+    let mut out = String::new();
+    match (res.values.as_slice(), res.pairs.as_slice()) {
+        ([], []) => out.push_str("OK"),
+        ([v], []) => out.push_str(&format_value(v)),
+        (values, pairs) => {
+            for (i, v) in values.iter().enumerate() {
+                let _ = writeln!(out, "{}) {}", i + 1, format_value(v));
+            }
+            for (i, pair) in pairs.iter().enumerate() {
+                let value = pair.value.as_ref().map(format_value).unwrap_or_default();
+                let _ = writeln!(out, "{}) {} => {}", i + 1, pair.key, value);
+            }
+            out.truncate(out.trim_end().len());
+        }
+    }
+    out
+}
+
+/// 格式化一个 Value：字符串带引号，二进制显示为 `b"..."`，空值显示为 `(nil)`
+pub fn format_value(v: &Value) -> String {
+    match &v.value {
+        None => "(nil)".into(),
+        Some(value::Value::String(s)) => format!("{:?}", s),
+        Some(value::Value::Binary(b)) => format!("b\"{}\"", b.escape_ascii()),
+        Some(value::Value::Integer(i)) => i.to_string(),
+        Some(value::Value::Float(f)) => format!("{:?}", f),
+        Some(value::Value::Bool(b)) => b.to_string(),
+    }
+}
+
+/// 命令行中的一个参数，记录它是否带了引号
+#[derive(Debug)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+impl Token {
+    fn to_value(&self) -> Value {
+        if self.quoted {
+            return self.text.as_str().into();
+        }
+        if let Ok(i) = self.text.parse::<i64>() {
+            return i.into();
+        }
+        if let Ok(f) = self.text.parse::<f64>() {
+            return Value {
+                value: Some(value::Value::Float(f)),
+            };
+        }
+        match self.text.as_str() {
+            "true" => true.into(),
+            "false" => false.into(),
+            s => s.into(),
+        }
+    }
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, KvError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&c) = chars.peek() else {
+            return Ok(tokens);
+        };
+
+        let mut text = String::new();
+        if c == '"' || c == '\'' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some(ch) if ch == c => break,
+                    Some('\\') if c == '"' => match chars.next() {
+                        Some('n') => text.push('\n'),
+                        Some('t') => text.push('\t'),
+                        Some(ch) => text.push(ch),
+                        None => break,
+                    },
+                    Some(ch) => text.push(ch),
+                    None => {
+                        return Err(KvError::InvalidCommand(format!(
+                            "Unterminated quote in: {}",
+                            line
+                        )))
+                    }
+                }
+            }
+            tokens.push(Token { text, quoted: true });
+        } else {
+            while let Some(ch) = chars.next_if(|c| !c.is_whitespace()) {
+                text.push(ch);
+            }
+            tokens.push(Token {
+                text,
+                quoted: false,
+            });
+        }
+    }
+}
+
+fn parse_eval(args: &[Token]) -> Result<RequestData, KvError> {
+    let err = || {
+        usage(
+            "eval",
+            "<script> <ntables> <table>... <nkeys> <key>... [arg...]",
+        )
+    };
+    let (script, rest) = args.split_first().ok_or_else(err)?;
+    let (tables, rest) = take_counted(rest).ok_or_else(err)?;
+    let (keys, rest) = take_counted(rest).ok_or_else(err)?;
+    Ok(RequestData::Eval(Eval {
+        script: script.text.clone(),
+        tables,
+        keys,
+        args: rest.iter().map(Token::to_value).collect(),
+    }))
+}
+
+/// 读取 `<n> <item>...` 形式的参数
+fn take_counted(args: &[Token]) -> Option<(Vec<String>, &[Token])> {
+    let (n, rest) = args.split_first()?;
+    let n: usize = n.text.parse().ok()?;
+    if rest.len() < n {
+        return None;
+    }
+    let (items, rest) = rest.split_at(n);
+    Some((items.iter().map(|t| t.text.clone()).collect(), rest))
+}
+
+fn exact<const N: usize>(name: &str, args: &[Token]) -> Result<[String; N], KvError> {
+    let args: Vec<_> = args.iter().map(|t| t.text.clone()).collect();
+    args.try_into().map_err(|args: Vec<String>| {
+        KvError::InvalidCommand(format!(
+            "{} expects {} arguments, got {}",
+            name,
+            N,
+            args.len()
+        ))
+    })
+}
+
+fn usage(name: &str, args: &str) -> KvError {
+    KvError::InvalidCommand(format!("Usage: {} {}", name, args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_command_should_work() {
+        let cmd = parse_command(r#"hset users alice "{\"age\": 18}""#).unwrap();
+        assert_eq!(
+            cmd,
+            CommandRequest::new_hset("users", "alice", r#"{"age": 18}"#.into())
+        );
+
+        let cmd = parse_command("HGET users alice").unwrap();
+        assert_eq!(cmd, CommandRequest::new_hget("users", "alice"));
+
+        let cmd = parse_command("hmset t1 k1 10 k2 true k3 '10'").unwrap();
+        let pairs = vec![
+            Kvpair::new("k1", 10.into()),
+            Kvpair::new("k2", true.into()),
+            Kvpair::new("k3", "10".into()),
+        ];
+        assert_eq!(
+            cmd.request_data,
+            Some(RequestData::Hmset(Hmset {
+                table: "t1".into(),
+                pairs
+            }))
+        );
+
+        let cmd = parse_command(r#"eval 'set("t1", "k1", ARGS[0])' 1 t1 1 k1 v1"#).unwrap();
+        assert_eq!(
+            cmd,
+            CommandRequest::new_eval(
+                r#"set("t1", "k1", ARGS[0])"#,
+                vec!["t1".into()],
+                vec!["k1".into()],
+                vec!["v1".into()]
+            )
+        );
+        // 脚本里只能用 ARGS，确认例子可以执行
+        let service = Service::new(MemTable::new());
+        assert_eq!(service.execute(cmd).status, 200);
+        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_eq!(res.values, ["v1".into()]);
+
+        let cmd = parse_command("incr counters 1").unwrap();
+        assert_eq!(
+            cmd,
+            CommandRequest::new_custom("INCR", vec!["counters".into(), 1.into()])
+        );
+
+        assert_eq!(parse_command("PING").unwrap(), CommandRequest::new_ping());
+    }
+
+    #[test]
+    fn parse_args_should_keep_type_inference() {
+        let args: Vec<String> = ["hset", "t1", "k 1", "10"].map(Into::into).to_vec();
+        assert_eq!(
+            parse_args(&args).unwrap(),
+            CommandRequest::new_hset("t1", "k 1", 10.into())
+        );
+
+        // 参数里的字符原样保留，不会被当作转义
+        let args: Vec<String> = ["hset", "t1", "k1", r#"a\tb"c"#].map(Into::into).to_vec();
+        assert_eq!(
+            parse_args(&args).unwrap(),
+            CommandRequest::new_hset("t1", "k1", r#"a\tb"c"#.into())
+        );
+    }
+
+    #[test]
+    fn parse_command_should_reject_bad_input() {
+        for line in [
+            "",
+            "hget users",
+            "hset users alice",
+            "hmset t1 k1",
+            "hset t1 k1 \"v1",
+            "eval script 2 t1",
+            "ping pong",
+        ] {
+            assert!(
+                matches!(parse_command(line), Err(KvError::InvalidCommand(_))),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn format_response_should_work() {
+        let res: CommandResponse = Value::from("v1").into();
+        assert_eq!(format_response(&res), "\"v1\"");
+
+        let res: CommandResponse = Value::default().into();
+        assert_eq!(format_response(&res), "(nil)");
+
+        let res: CommandResponse = vec![
+            Kvpair::new("k1", 1.into()),
+            Kvpair::new("k2", b"\x01a".into()),
+        ]
+        .into();
+        assert_eq!(format_response(&res), "1) k1 => 1\n2) k2 => b\"\\x01a\"");
+
+        let res: CommandResponse = KvError::NotFound("t1".into(), "k1".into()).into();
+        assert_eq!(
+            format_response(&res),
+            "(error 404) Not found for table: t1, key: k1"
+        );
+    }
+}
@@ -0,0 +1,793 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use argon2::PasswordHash;
+use serde::Deserialize;
+
+use crate::{
+    AclRule, Compression, KvError, Permissions, COMPRESSION_LIMIT, DEFAULT_MAX_OPERATIONS,
+    DRAIN_TIMEOUT, MAX_FRAME_SIZE, MAX_STREAMS,
+};
+
+/// kv-server 的配置，从 TOML 文件读取
+///
+/// ```toml
+/// [general]
+/// addr = "127.0.0.1:9527"
+///
+/// [storage]
+/// backend = "sled"
+/// path = "/var/lib/kv"
+///
+/// [tls]
+/// cert = "fixtures/server.cert"
+/// key = "fixtures/server.key"
+/// reload_interval_ms = 5000
+///
+/// [log]
+/// level = "info"
+///
+/// [auth]
+/// credentials = "/etc/kv/credentials"
+/// reload_interval_ms = 5000
+///
+/// [[acl]]
+/// user = "alice"
+/// table = "users*"
+/// permissions = "read,write"
+///
+/// [limits]
+/// request_timeout_ms = 5000
+/// drain_timeout_ms = 30000
+/// max_connections = 10000
+/// max_connections_per_ip = 256
+/// idle_timeout_ms = 300000
+/// ping_interval_ms = 30000
+///
+/// [compression]
+/// algorithms = ["zstd", "lz4", "gzip"]
+/// limit = 1436
+///
+/// [resp]
+/// addr = "127.0.0.1:6379"
+///
+/// [http]
+/// addr = "127.0.0.1:8080"
+///
+/// [grpc]
+/// addr = "127.0.0.1:50051"
+///
+/// [unix]
+/// path = "/run/kv/kv.sock"
+/// mode = 0o660
+///
+/// [quic]
+/// addr = "127.0.0.1:9528"
+///
+/// [multiplex]
+/// addr = "127.0.0.1:9529"
+/// max_streams = 256
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub general: GeneralConfig,
+    pub storage: StorageConfig,
+    /// 不配置时使用明文 TCP
+    pub tls: Option<ServerTlsConfig>,
+    #[serde(default)]
+    pub log: LogConfig,
+    /// 不配置时不需要认证，任何能连上服务器的客户端都可以访问
+    pub auth: Option<AuthConfig>,
+    /// 不配置时不检查权限，认证过的连接可以访问所有的表
+    #[serde(default)]
+    pub acl: Vec<AclConfig>,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// 不配置时不开启 RESP（Redis 协议）监听
+    pub resp: Option<RespConfig>,
+    /// 不配置时不开启 HTTP/JSON 网关
+    pub http: Option<HttpConfig>,
+    /// 不配置时不开启 gRPC 服务
+    pub grpc: Option<GrpcConfig>,
+    /// 不配置时不监听 Unix domain socket
+    pub unix: Option<UnixConfig>,
+    /// 不配置时不开启 QUIC，开启时必须配置 tls
+    pub quic: Option<QuicConfig>,
+    /// 不配置时不开启多路复用（yamux）的监听
+    pub multiplex: Option<MultiplexConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeneralConfig {
+    /// 监听地址，如 "127.0.0.1:9527"
+    pub addr: String,
+}
+
+/// 存储后端
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase", deny_unknown_fields)]
+pub enum StorageConfig {
+    /// 内存存储，重启后数据丢失
+    Memory,
+    /// sled 存储，数据保存在 path 目录下
+    Sled { path: PathBuf },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerTlsConfig {
+    pub cert: String,
+    pub key: String,
+    /// 配置后开启 mTLS，要求客户端提供这个 CA 签发的证书
+    pub client_ca: Option<String>,
+    /// 每隔多久检查一次证书文件，修改后自动重新加载，所有 listener（包括 QUIC）的新连接都使用新证书
+    #[serde(default = "default_reload_interval_ms")]
+    pub reload_interval_ms: u64,
+}
+
+/// 开启认证后，连接要先用 Auth 命令登录，HTTP 网关和 gRPC 使用 Basic 认证
+///
+/// 凭证放在 credentials 文件里（每行 `username:argon2 hash`，见 `Authenticator`），
+/// 或者直接写在 `[auth.users]` 里，两者只能选一个。
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    /// 凭证文件，修改后每隔 reload_interval_ms 自动重新加载，不需要重启
+    pub credentials: Option<PathBuf>,
+    /// 用户名 -> argon2 hash（用 `kv::hash_password` 生成），修改后需要重启
+    #[serde(default)]
+    pub users: BTreeMap<String, String>,
+    /// 多久检查一次凭证文件是否修改
+    #[serde(default = "default_reload_interval_ms")]
+    pub reload_interval_ms: u64,
+}
+
+fn default_reload_interval_ms() -> u64 {
+    5000
+}
+
+/// 一条 ACL 规则，见 `AclRule`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AclConfig {
+    /// Auth 登录的用户名或者 mTLS 客户端证书中的身份，`*` 表示所有身份
+    pub user: String,
+    /// 表名，支持 `*` 和 `?` 通配符
+    pub table: String,
+    /// read / write / admin / all，多个用逗号分隔
+    pub permissions: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RespConfig {
+    /// RESP 监听地址，如 "127.0.0.1:6379"，TLS 配置和主监听地址一样
+    pub addr: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    /// HTTP 监听地址，如 "127.0.0.1:8080"；配置了 [tls] 时使用 HTTPS，TLS 配置和主监听地址一样
+    pub addr: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcConfig {
+    /// gRPC 监听地址，如 "127.0.0.1:50051"；和 HTTP 网关一样，配置了 [tls] 时使用 TLS
+    pub addr: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuicConfig {
+    /// QUIC 监听的 UDP 地址，如 "127.0.0.1:9528"，使用 [tls] 中的证书
+    pub addr: String,
+}
+
+/// 一个连接上复用多个逻辑 stream，见 `YamuxServerConnection`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MultiplexConfig {
+    /// 监听地址，如 "127.0.0.1:9529"；配置了 [tls] 时使用 TLS，TLS 配置和主监听地址一样
+    pub addr: String,
+    /// 一个连接上同时打开的逻辑 stream 数，最多 4096
+    #[serde(default = "default_max_streams")]
+    pub max_streams: usize,
+}
+
+fn default_max_streams() -> usize {
+    MAX_STREAMS
+}
+
+/// 同一台机器上的 sidecar 通过 Unix domain socket 访问，协议和 TCP 一样，但不使用 TLS
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnixConfig {
+    pub path: PathBuf,
+    /// socket 文件的权限，只有有写权限的用户才能连接
+    #[serde(default = "default_socket_mode")]
+    pub mode: u32,
+}
+
+fn default_socket_mode() -> u32 {
+    0o600
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// trace / debug / info / warn / error
+    pub level: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// 单个请求的超时时间
+    pub request_timeout_ms: u64,
+    /// 退出时等待连接处理完已收到请求的时间，超时后强制关闭
+    pub drain_timeout_ms: u64,
+    /// 服务器的总连接数，所有 listener 一起计算
+    ///
+    /// 超过限制时，没有 TLS 的 TCP 和 Unix socket 连接收到 503 的响应后被关闭，其他连接在握手之前直接关闭
+    pub max_connections: usize,
+    /// 同一个 IP 的连接数
+    pub max_connections_per_ip: usize,
+    /// 超过这个时间没有收到请求就关闭连接，0 表示不限制
+    pub idle_timeout_ms: u64,
+    /// 超过这个时间没有收到客户端的数据就发送 ping，0 表示不发送
+    pub ping_interval_ms: u64,
+    /// 单个连接上同时处理的请求数
+    pub max_concurrent_requests: usize,
+    /// Eval 脚本最多执行的操作数
+    pub script_max_operations: u64,
+    /// 单个 frame 的最大字节数，超过时断开连接
+    pub max_frame_size: usize,
+    /// 压缩的 frame 解压后的最大字节数
+    pub max_decompressed_size: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    /// 握手时允许客户端选择的压缩算法：none / gzip / lz4 / zstd
+    pub algorithms: Vec<String>,
+    /// payload 超过多少字节才压缩
+    pub limit: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            request_timeout_ms: 5000,
+            drain_timeout_ms: DRAIN_TIMEOUT.as_millis() as u64,
+            max_connections: 10000,
+            max_connections_per_ip: 256,
+            idle_timeout_ms: 300_000,
+            ping_interval_ms: 30_000,
+            max_concurrent_requests: 64,
+            script_max_operations: DEFAULT_MAX_OPERATIONS,
+            max_frame_size: MAX_FRAME_SIZE,
+            max_decompressed_size: MAX_FRAME_SIZE,
+        }
+    }
+}
+
+impl LimitsConfig {
+    /// 配置为 0 时不检查空闲连接
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        (self.idle_timeout_ms > 0).then(|| Duration::from_millis(self.idle_timeout_ms))
+    }
+
+    /// 配置为 0 时不发送 ping
+    pub fn ping_interval(&self) -> Option<Duration> {
+        (self.ping_interval_ms > 0).then(|| Duration::from_millis(self.ping_interval_ms))
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithms: Compression::SUPPORTED
+                .iter()
+                .map(|c| c.as_str_name().to_ascii_lowercase())
+                .collect(),
+            limit: COMPRESSION_LIMIT,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// 读取并校验配置文件
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| {
+            KvError::ConfigError(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        content.parse()
+    }
+
+    /// 启动前检查配置是否可用，尽早把错误暴露出来
+    pub fn validate(&self) -> Result<(), KvError> {
+        self.addr()?;
+        self.resp_addr()?;
+        self.http_addr()?;
+        self.grpc_addr()?;
+        self.multiplex_addr()?;
+        if let Some(multiplex) = &self.multiplex {
+            if !(1..=4096).contains(&multiplex.max_streams) {
+                return Err(KvError::ConfigError(
+                    "multiplex.max_streams must be between 1 and 4096".into(),
+                ));
+            }
+        }
+        if self.quic_addr()?.is_some() && self.tls.is_none() {
+            return Err(KvError::ConfigError(
+                "QUIC requires [tls] to be configured".into(),
+            ));
+        }
+        self.log_level()?;
+
+        if let StorageConfig::Sled { path } = &self.storage {
+            if path.as_os_str().is_empty() {
+                return Err(KvError::ConfigError("Sled path must not be empty".into()));
+            }
+            if path.exists() && !path.is_dir() {
+                return Err(KvError::ConfigError(format!(
+                    "Sled path {} is not a directory",
+                    path.display()
+                )));
+            }
+        }
+
+        if let Some(unix) = &self.unix {
+            if unix.path.as_os_str().is_empty() {
+                return Err(KvError::ConfigError(
+                    "Unix socket path must not be empty".into(),
+                ));
+            }
+            if unix.mode > 0o777 {
+                return Err(KvError::ConfigError(format!(
+                    "Invalid unix socket mode: {:o}",
+                    unix.mode
+                )));
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            let files = [Some(&tls.cert), Some(&tls.key), tls.client_ca.as_ref()];
+            for file in files.into_iter().flatten() {
+                if !Path::new(file).is_file() {
+                    return Err(KvError::ConfigError(format!(
+                        "TLS file {} does not exist",
+                        file
+                    )));
+                }
+            }
+        }
+
+        if let Some(auth) = &self.auth {
+            match (&auth.credentials, auth.users.is_empty()) {
+                (Some(path), true) if !path.is_file() => {
+                    return Err(KvError::ConfigError(format!(
+                        "Credentials file {} does not exist",
+                        path.display()
+                    )));
+                }
+                (Some(_), true) => {}
+                (None, false) => {
+                    for (user, hash) in &auth.users {
+                        if PasswordHash::new(hash).is_err() {
+                            return Err(KvError::ConfigError(format!(
+                                "Invalid argon2 hash for user {}",
+                                user
+                            )));
+                        }
+                    }
+                }
+                _ => {
+                    return Err(KvError::ConfigError(
+                        "auth requires exactly one of credentials and users".into(),
+                    ))
+                }
+            }
+            if auth.reload_interval_ms == 0 {
+                return Err(KvError::ConfigError(
+                    "auth.reload_interval_ms must be greater than 0".into(),
+                ));
+            }
+        }
+        if self
+            .tls
+            .as_ref()
+            .is_some_and(|tls| tls.reload_interval_ms == 0)
+        {
+            return Err(KvError::ConfigError(
+                "tls.reload_interval_ms must be greater than 0".into(),
+            ));
+        }
+
+        if !self.acl.is_empty() {
+            let mtls = self.tls.as_ref().is_some_and(|tls| tls.client_ca.is_some());
+            if self.auth.is_none() && !mtls {
+                return Err(KvError::ConfigError(
+                    "[[acl]] requires [auth] or tls.client_ca to identify clients".into(),
+                ));
+            }
+            self.acl_rules()?;
+        }
+
+        let limits = &self.limits;
+        if limits.request_timeout_ms == 0 {
+            return Err(KvError::ConfigError(
+                "limits.request_timeout_ms must be greater than 0".into(),
+            ));
+        }
+        if limits.max_connections == 0 || limits.max_connections_per_ip == 0 {
+            return Err(KvError::ConfigError(
+                "limits.max_connections and limits.max_connections_per_ip must be greater than 0"
+                    .into(),
+            ));
+        }
+        if limits.max_concurrent_requests == 0 {
+            return Err(KvError::ConfigError(
+                "limits.max_concurrent_requests must be greater than 0".into(),
+            ));
+        }
+        if limits.script_max_operations == 0 {
+            return Err(KvError::ConfigError(
+                "limits.script_max_operations must be greater than 0".into(),
+            ));
+        }
+        // 帧头只有 30 bit 表示长度
+        for (name, size) in [
+            ("max_frame_size", limits.max_frame_size),
+            ("max_decompressed_size", limits.max_decompressed_size),
+        ] {
+            if size == 0 || size >= 1 << 30 {
+                return Err(KvError::ConfigError(format!(
+                    "limits.{} must be between 1 and {}",
+                    name,
+                    (1 << 30) - 1
+                )));
+            }
+        }
+        self.compressions()?;
+        Ok(())
+    }
+
+    pub fn addr(&self) -> Result<SocketAddr, KvError> {
+        self.general.addr.parse().map_err(|_| {
+            KvError::ConfigError(format!("Invalid listen address: {}", self.general.addr))
+        })
+    }
+
+    pub fn resp_addr(&self) -> Result<Option<SocketAddr>, KvError> {
+        self.resp
+            .as_ref()
+            .map(|resp| {
+                resp.addr.parse().map_err(|_| {
+                    KvError::ConfigError(format!("Invalid RESP listen address: {}", resp.addr))
+                })
+            })
+            .transpose()
+    }
+
+    pub fn http_addr(&self) -> Result<Option<SocketAddr>, KvError> {
+        self.http
+            .as_ref()
+            .map(|http| {
+                http.addr.parse().map_err(|_| {
+                    KvError::ConfigError(format!("Invalid HTTP listen address: {}", http.addr))
+                })
+            })
+            .transpose()
+    }
+
+    pub fn grpc_addr(&self) -> Result<Option<SocketAddr>, KvError> {
+        self.grpc
+            .as_ref()
+            .map(|grpc| {
+                grpc.addr.parse().map_err(|_| {
+                    KvError::ConfigError(format!("Invalid gRPC listen address: {}", grpc.addr))
+                })
+            })
+            .transpose()
+    }
+
+    pub fn quic_addr(&self) -> Result<Option<SocketAddr>, KvError> {
+        self.quic
+            .as_ref()
+            .map(|quic| {
+                quic.addr.parse().map_err(|_| {
+                    KvError::ConfigError(format!("Invalid QUIC listen address: {}", quic.addr))
+                })
+            })
+            .transpose()
+    }
+
+    pub fn multiplex_addr(&self) -> Result<Option<SocketAddr>, KvError> {
+        self.multiplex
+            .as_ref()
+            .map(|multiplex| {
+                multiplex.addr.parse().map_err(|_| {
+                    KvError::ConfigError(format!(
+                        "Invalid multiplex listen address: {}",
+                        multiplex.addr
+                    ))
+                })
+            })
+            .transpose()
+    }
+
+    pub fn log_level(&self) -> Result<tracing::Level, KvError> {
+        self.log
+            .level
+            .parse()
+            .map_err(|_| KvError::ConfigError(format!("Invalid log level: {}", self.log.level)))
+    }
+
+    /// [[acl]] 中配置的规则
+    pub fn acl_rules(&self) -> Result<Vec<AclRule>, KvError> {
+        self.acl
+            .iter()
+            .map(|acl| {
+                if acl.user.is_empty() || acl.table.is_empty() {
+                    return Err(KvError::ConfigError(
+                        "acl.user and acl.table must not be empty".into(),
+                    ));
+                }
+                let permissions: Permissions = acl.permissions.parse().map_err(|_| {
+                    KvError::ConfigError(format!("Invalid ACL permissions: {}", acl.permissions))
+                })?;
+                if permissions == Permissions::NONE {
+                    return Err(KvError::ConfigError(format!(
+                        "ACL rule for {} on {} grants no permissions",
+                        acl.user, acl.table
+                    )));
+                }
+                Ok(AclRule::new(&acl.user, &acl.table, permissions))
+            })
+            .collect()
+    }
+
+    /// 握手时允许使用的压缩算法
+    pub fn compressions(&self) -> Result<Vec<Compression>, KvError> {
+        self.compression
+            .algorithms
+            .iter()
+            .map(|name| {
+                name.parse().map_err(|_| {
+                    KvError::ConfigError(format!("Invalid compression algorithm: {}", name))
+                })
+            })
+            .collect()
+    }
+}
+
+impl FromStr for ServerConfig {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config: ServerConfig =
+            toml::from_str(s).map_err(|e| KvError::ConfigError(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_config_should_load_fixture() {
+        let config = ServerConfig::load("fixtures/server.toml").unwrap();
+        assert_eq!(config.addr().unwrap(), "127.0.0.1:9527".parse().unwrap());
+        assert_eq!(config.storage, StorageConfig::Memory);
+        assert_eq!(config.log_level().unwrap(), tracing::Level::INFO);
+        assert_eq!(config.limits, LimitsConfig::default());
+        assert_eq!(config.tls.unwrap().reload_interval_ms, 5000);
+        assert!(config.auth.is_none());
+    }
+
+    #[test]
+    fn server_config_should_parse_auth() {
+        let hash = crate::hash_password("secret").unwrap();
+        let config: ServerConfig = format!(
+            "[general]\naddr = \"127.0.0.1:9527\"\n[storage]\nbackend = \"memory\"\n\
+             [auth.users]\nalice = \"{}\"\n",
+            hash
+        )
+        .parse()
+        .unwrap();
+        let auth = config.auth.unwrap();
+        assert_eq!(auth.credentials, None);
+        assert_eq!(auth.users.get("alice"), Some(&hash));
+        assert_eq!(auth.reload_interval_ms, 5000);
+    }
+
+    #[test]
+    fn server_config_should_parse_acl() {
+        let config: ServerConfig = r#"
+            [general]
+            addr = "127.0.0.1:9527"
+
+            [storage]
+            backend = "memory"
+
+            [tls]
+            cert = "fixtures/server.cert"
+            key = "fixtures/server.key"
+            client_ca = "fixtures/ca.cert"
+
+            [[acl]]
+            user = "alice"
+            table = "users*"
+            permissions = "read, write"
+
+            [[acl]]
+            user = "*"
+            table = "public"
+            permissions = "read"
+            "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            config.acl_rules().unwrap(),
+            vec![
+                AclRule::new("alice", "users*", Permissions::READ | Permissions::WRITE),
+                AclRule::new("*", "public", Permissions::READ),
+            ]
+        );
+    }
+
+    #[test]
+    fn server_config_should_parse_sled_storage() {
+        let config: ServerConfig = r#"
+            [general]
+            addr = "0.0.0.0:9527"
+
+            [storage]
+            backend = "sled"
+            path = "/tmp/kv"
+
+            [log]
+            level = "debug"
+
+            [limits]
+            request_timeout_ms = 1000
+            drain_timeout_ms = 10000
+            max_connections_per_ip = 8
+            idle_timeout_ms = 0
+
+            [compression]
+            algorithms = ["LZ4", "gzip"]
+            limit = 4096
+
+            [resp]
+            addr = "127.0.0.1:6379"
+
+            [http]
+            addr = "127.0.0.1:8080"
+
+            [grpc]
+            addr = "127.0.0.1:50051"
+
+            [unix]
+            path = "/tmp/kv.sock"
+
+            [multiplex]
+            addr = "127.0.0.1:9529"
+            "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            config.storage,
+            StorageConfig::Sled {
+                path: "/tmp/kv".into()
+            }
+        );
+        assert_eq!(config.limits.request_timeout_ms, 1000);
+        assert_eq!(config.limits.drain_timeout_ms, 10000);
+        assert_eq!(config.limits.max_connections, 10000);
+        assert_eq!(config.limits.max_connections_per_ip, 8);
+        assert_eq!(config.limits.idle_timeout(), None);
+        assert_eq!(config.limits.ping_interval(), Some(Duration::from_secs(30)));
+        assert_eq!(config.limits.max_concurrent_requests, 64);
+        assert!(config.tls.is_none());
+        assert_eq!(
+            config.compressions().unwrap(),
+            vec![Compression::Lz4, Compression::Gzip]
+        );
+        assert_eq!(config.compression.limit, 4096);
+        assert_eq!(
+            config.resp_addr().unwrap(),
+            Some("127.0.0.1:6379".parse().unwrap())
+        );
+        assert_eq!(
+            config.http_addr().unwrap(),
+            Some("127.0.0.1:8080".parse().unwrap())
+        );
+        assert_eq!(
+            config.grpc_addr().unwrap(),
+            Some("127.0.0.1:50051".parse().unwrap())
+        );
+        assert_eq!(
+            config.multiplex_addr().unwrap(),
+            Some("127.0.0.1:9529".parse().unwrap())
+        );
+        assert_eq!(config.multiplex.as_ref().unwrap().max_streams, MAX_STREAMS);
+        let unix = config.unix.unwrap();
+        assert_eq!(unix.path, PathBuf::from("/tmp/kv.sock"));
+        assert_eq!(unix.mode, 0o600);
+    }
+
+    #[test]
+    fn server_config_should_reject_invalid_values() {
+        let base = "[general]\naddr = \"127.0.0.1:9527\"\n[storage]\nbackend = \"memory\"\n";
+        let cases = [
+            "[general]\naddr = \"localhost\"\n[storage]\nbackend = \"memory\"\n".to_string(),
+            "[general]\naddr = \"127.0.0.1:9527\"\n[storage]\nbackend = \"rocksdb\"\n".into(),
+            format!("{}[log]\nlevel = \"loud\"\n", base),
+            format!("{}[limits]\nrequest_timeout_ms = 0\n", base),
+            format!("{}[limits]\nmax_connections_per_ip = 0\n", base),
+            format!("{}[limits]\nmax_frame_size = 2000000000\n", base),
+            format!("{}[tls]\ncert = \"no.cert\"\nkey = \"no.key\"\n", base),
+            format!(
+                "{}[tls]\ncert = \"fixtures/server.cert\"\nkey = \"fixtures/server.key\"\n\
+                 reload_interval_ms = 0\n",
+                base
+            ),
+            format!("{}[compression]\nalgorithms = [\"brotli\"]\n", base),
+            format!("{}[resp]\naddr = \"redis\"\n", base),
+            format!("{}[http]\naddr = \"8080\"\n", base),
+            format!("{}[grpc]\naddr = \"grpc\"\n", base),
+            format!("{}[multiplex]\naddr = \"mux\"\n", base),
+            format!(
+                "{}[multiplex]\naddr = \"127.0.0.1:9529\"\nmax_streams = 0\n",
+                base
+            ),
+            format!("{}[unix]\npath = \"\"\n", base),
+            format!("{}[unix]\npath = \"kv.sock\"\nmode = 0o1777\n", base),
+            format!("{}[quic]\naddr = \"127.0.0.1:9528\"\n", base),
+            format!("{}[auth]\n", base),
+            format!("{}[auth]\ncredentials = \"no-such-file\"\n", base),
+            format!("{}[auth.users]\nalice = \"secret\"\n", base),
+            format!(
+                "{}[[acl]]\nuser = \"alice\"\ntable = \"*\"\npermissions = \"read\"\n",
+                base
+            ),
+            format!(
+                "{}[auth]\ncredentials = \"fixtures/server.toml\"\n\
+                 [[acl]]\nuser = \"alice\"\ntable = \"*\"\npermissions = \"delete\"\n",
+                base
+            ),
+            format!("{}[unknown]\n", base),
+        ];
+        for case in cases {
+            let res = case.parse::<ServerConfig>();
+            assert!(matches!(res, Err(KvError::ConfigError(_))), "{}", case);
+        }
+    }
+}
@@ -31,9 +31,39 @@ pub enum KvError {
     #[error("I/O error")]
     IoError(#[from] std::io::Error),
 
+    #[error("Unauthenticated: {0}")]
+    Unauthenticated(String),
+
+    #[error("Permission denied for user {0} on table {1}, requires {2}")]
+    PermissionDenied(String, String, String),
+
+    #[error("Script error: {0}")]
+    ScriptError(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 
     #[error("Certificate parse error: {0}, {1}")]
-    CertifcateParseError(String, String)
+    CertifcateParseError(String, String),
+
+    #[error("TLS error: {0}")]
+    TlsError(String),
+
+    #[error("Config error: {0}")]
+    ConfigError(String),
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    #[error("Unsupported protocol version {0}, supported versions are {1} to {2}")]
+    UnsupportedProtocol(u32, u32, u32),
+
+    #[error("Too many connections: {0}")]
+    TooManyConnections(String),
+
+    #[error("Server is overloaded: {0}")]
+    Overloaded(String),
+
+    #[error("Server returned {0}: {1}")]
+    ServerError(u32, String),
 }
@@ -1,11 +1,16 @@
+mod cli;
+mod config;
 mod error;
 mod network;
 mod pb;
 mod service;
 mod storage;
 
+pub use cli::{format_response, format_value, parse_args, parse_command, COMMAND_NAMES};
+pub use config::*;
 pub use error::KvError;
 pub use network::*;
 pub use pb::abi::*;
+pub use pb::{CAPABILITIES, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
 pub use service::*;
 pub use storage::*;
@@ -0,0 +1,679 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::{stream, Stream, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time,
+};
+use tracing::{debug, warn};
+
+use super::stream::check_server_hello;
+use crate::{
+    command_request::RequestData, CommandRequest, CommandResponse, Compression, Eval, Hexist,
+    Hmdel, Hmexist, Hmget, Hmset, KvError, Kvpair, PipelinedClient, TlsClientConnector, Value,
+};
+
+/// 地址以它开头时通过 Unix domain socket 连接，如 "unix:///run/kv/kv.sock"
+pub const UNIX_SCHEME: &str = "unix://";
+
+/// KvClient 的配置
+#[derive(Clone)]
+pub struct KvClientBuilder {
+    addr: String,
+    tls: Option<TlsClientConnector>,
+    credentials: Option<(String, String)>,
+    client_name: String,
+    pool_size: usize,
+    connect_timeout: Duration,
+    request_timeout: Duration,
+    health_check_interval: Duration,
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+/// 带连接池的 KV 客户端
+///
+/// clone 出来的 KvClient 共享同一个连接池。池中的每个连接都支持 pipelining，请求轮流分配到
+/// 各个连接上，同一个连接上可以同时有多个请求。连接是按需建立的，断开后下次使用时自动重连；
+/// 超过 `health_check_interval` 没有使用的连接，使用前会先检查一下是否还活着。
+#[derive(Clone)]
+pub struct KvClient {
+    inner: Arc<ClientInner>,
+}
+
+struct ClientInner {
+    options: KvClientBuilder,
+    slots: Vec<Mutex<Option<Slot>>>,
+    next: AtomicUsize,
+}
+
+struct Slot {
+    conn: PipelinedClient,
+    last_used: Instant,
+}
+
+impl KvClientBuilder {
+    /// addr 是 "host:port"，或者 "unix://" 开头的 Unix domain socket 路径
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            tls: None,
+            credentials: None,
+            client_name: "kv-client".into(),
+            pool_size: 8,
+            connect_timeout: Duration::from_secs(3),
+            request_timeout: Duration::from_secs(5),
+            health_check_interval: Duration::from_secs(30),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+
+    /// 使用 TLS 连接服务器；Unix domain socket 只在本机通信，不使用 TLS
+    pub fn tls(mut self, connector: TlsClientConnector) -> Self {
+        self.tls = Some(connector);
+        self
+    }
+
+    /// 每个新建的连接都会先用这组用户名密码发送 Auth
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// 握手时发给服务器的客户端名字，服务器会把它记在日志里
+    pub fn client_name(mut self, name: impl Into<String>) -> Self {
+        self.client_name = name.into();
+        self
+    }
+
+    /// 连接池中的连接数
+    pub fn pool_size(mut self, n: usize) -> Self {
+        self.pool_size = n.max(1);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    pub fn health_check_interval(mut self, interval: Duration) -> Self {
+        self.health_check_interval = interval;
+        self
+    }
+
+    /// 建立连接失败后最多重试几次
+    pub fn max_retries(mut self, n: u32) -> Self {
+        self.max_retries = n;
+        self
+    }
+
+    /// 重试之间的等待时间从 initial 开始翻倍，最多到 max
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn build(self) -> KvClient {
+        KvClient {
+            inner: Arc::new(ClientInner {
+                slots: (0..self.pool_size).map(|_| Mutex::new(None)).collect(),
+                next: AtomicUsize::new(0),
+                options: self,
+            }),
+        }
+    }
+}
+
+impl KvClient {
+    /// 使用默认配置创建一个客户端
+    pub fn new(addr: impl Into<String>) -> Self {
+        KvClientBuilder::new(addr).build()
+    }
+
+    pub fn builder(addr: impl Into<String>) -> KvClientBuilder {
+        KvClientBuilder::new(addr)
+    }
+
+    /// 发送任意命令，非 2xx 的响应会转换成 KvError
+    pub async fn execute(&self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        let conn = self.connection().await?;
+        let options = &self.inner.options;
+        // 连接出错时 PipelinedClient 会标记自己已关闭，下次使用时重连
+        match time::timeout(options.request_timeout, conn.execute(cmd)).await {
+            Ok(res) => res?.into_result(),
+            Err(_) => Err(KvError::Timeout(format!(
+                "No response from {} in {:?}",
+                options.addr, options.request_timeout
+            ))),
+        }
+    }
+
+    /// 获取一个 key，key 不存在时返回 None
+    pub async fn hget(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<Option<Value>, KvError> {
+        match self.execute(CommandRequest::new_hget(table, key)).await {
+            Ok(res) => Ok(res.values.into_iter().next()),
+            Err(KvError::NotFound(..)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn hgetall(&self, table: impl Into<String>) -> Result<Vec<Kvpair>, KvError> {
+        let res = self.execute(CommandRequest::new_hgetall(table)).await?;
+        Ok(res.pairs)
+    }
+
+    /// 分块获取整个 table，服务器每次最多返回 chunk_size 个 kv pair
+    ///
+    /// 适合放不进一个 frame 的大 table。request_timeout 限制请求发出的时间和等待每一块响应的时间，
+    /// 不限制整个 stream 的读取时间。出错（包括连接断开、超时）时 stream 返回一个 Err 然后结束。
+    pub async fn hgetall_stream(
+        &self,
+        table: impl Into<String>,
+        chunk_size: u32,
+    ) -> Result<impl Stream<Item = Result<Kvpair, KvError>> + Send + 'static, KvError> {
+        let cmd = CommandRequest::new_hgetall_chunked(table, chunk_size);
+        let conn = self.connection().await?;
+        let options = &self.inner.options;
+        let timeout = options.request_timeout;
+        let chunks = match time::timeout(timeout, conn.execute_stream(cmd)).await {
+            Ok(chunks) => chunks?,
+            Err(_) => {
+                return Err(KvError::Timeout(format!(
+                    "Failed to send request to {} in {:?}",
+                    options.addr, timeout
+                )))
+            }
+        };
+
+        let addr = options.addr.clone();
+        let chunks = stream::unfold(Some(Box::pin(chunks)), move |chunks| {
+            let addr = addr.clone();
+            async move {
+                let mut chunks = chunks?;
+                match time::timeout(timeout, chunks.next()).await {
+                    Ok(Some(res)) => Some((res, Some(chunks))),
+                    Ok(None) => None,
+                    Err(_) => {
+                        let msg = format!("No response from {} in {:?}", addr, timeout);
+                        Some((Err(KvError::Timeout(msg)), None))
+                    }
+                }
+            }
+        });
+        Ok(chunks.flat_map(|res| {
+            let pairs = match res.and_then(CommandResponse::into_result) {
+                Ok(res) => res.pairs.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(pairs)
+        }))
+    }
+
+    /// 获取一组 key，不存在的 key 对应 None
+    pub async fn hmget(
+        &self,
+        table: impl Into<String>,
+        keys: Vec<String>,
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        let cmd = request(RequestData::Hmget(Hmget {
+            table: table.into(),
+            keys,
+        }));
+        let res = self.execute(cmd).await?;
+        Ok(res.values.into_iter().map(non_empty).collect())
+    }
+
+    /// 设置一个 key，返回旧的 value
+    pub async fn hset(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        let res = self
+            .execute(CommandRequest::new_hset(table, key, value.into()))
+            .await?;
+        Ok(res.values.into_iter().next().and_then(non_empty))
+    }
+
+    /// 设置一组 key，返回它们旧的 value
+    pub async fn hmset(
+        &self,
+        table: impl Into<String>,
+        pairs: Vec<Kvpair>,
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        let cmd = request(RequestData::Hmset(Hmset {
+            table: table.into(),
+            pairs,
+        }));
+        let res = self.execute(cmd).await?;
+        Ok(res.values.into_iter().map(non_empty).collect())
+    }
+
+    /// 删除一个 key，返回被删除的 value
+    pub async fn hdel(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<Option<Value>, KvError> {
+        let res = self.execute(CommandRequest::new_hdel(table, key)).await?;
+        Ok(res.values.into_iter().next().and_then(non_empty))
+    }
+
+    pub async fn hmdel(
+        &self,
+        table: impl Into<String>,
+        keys: Vec<String>,
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        let cmd = request(RequestData::Hmdel(Hmdel {
+            table: table.into(),
+            keys,
+        }));
+        let res = self.execute(cmd).await?;
+        Ok(res.values.into_iter().map(non_empty).collect())
+    }
+
+    pub async fn hexist(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<bool, KvError> {
+        let cmd = request(RequestData::Hexist(Hexist {
+            table: table.into(),
+            key: key.into(),
+        }));
+        let res = self.execute(cmd).await?;
+        res.values.into_iter().next().unwrap_or_default().try_into()
+    }
+
+    pub async fn hmexist(
+        &self,
+        table: impl Into<String>,
+        keys: Vec<String>,
+    ) -> Result<Vec<bool>, KvError> {
+        let cmd = request(RequestData::Hmexist(Hmexist {
+            table: table.into(),
+            keys,
+        }));
+        let res = self.execute(cmd).await?;
+        res.values.into_iter().map(bool::try_from).collect()
+    }
+
+    /// 执行一个脚本，返回脚本的返回值
+    pub async fn eval(
+        &self,
+        script: impl Into<String>,
+        tables: Vec<String>,
+        keys: Vec<String>,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>, KvError> {
+        let cmd = request(RequestData::Eval(Eval {
+            script: script.into(),
+            tables,
+            keys,
+            args,
+        }));
+        let res = self.execute(cmd).await?;
+        Ok(res.values)
+    }
+
+    /// 检查服务器是否可用
+    pub async fn ping(&self) -> Result<(), KvError> {
+        self.execute(CommandRequest::new_ping()).await.map(|_| ())
+    }
+
+    /// 执行服务器上注册的自定义命令
+    pub async fn custom(
+        &self,
+        name: impl Into<String>,
+        args: Vec<Value>,
+    ) -> Result<CommandResponse, KvError> {
+        self.execute(CommandRequest::new_custom(name, args)).await
+    }
+
+    /// 轮流取池中的一个连接，连接不存在或者已经断开时重新建立
+    ///
+    /// 健康检查和重连时不持有这个位置的锁，分到同一个位置的其他请求不用等待整个重试过程；
+    /// 同时重连的请求各自建立连接，最后建立的那个留在池中。
+    async fn connection(&self) -> Result<PipelinedClient, KvError> {
+        let idx = self.inner.next.fetch_add(1, Ordering::Relaxed) % self.inner.slots.len();
+        let cached = self
+            .slot(idx)
+            .as_ref()
+            .map(|s| (s.conn.clone(), s.last_used));
+
+        if let Some((conn, last_used)) = cached {
+            let healthy = if conn.is_closed() {
+                false
+            } else if last_used.elapsed() < self.inner.options.health_check_interval {
+                true
+            } else {
+                match self.health_check(&conn).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        debug!("Dropping unhealthy connection: {}", e);
+                        false
+                    }
+                }
+            };
+            if healthy {
+                self.put(idx, conn.clone());
+                return Ok(conn);
+            }
+        }
+
+        let conn = self.connect_with_backoff().await?;
+        self.put(idx, conn.clone());
+        Ok(conn)
+    }
+
+    fn slot(&self, idx: usize) -> MutexGuard<'_, Option<Slot>> {
+        self.inner.slots[idx]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 把连接放回池中，并更新它最后使用的时间
+    fn put(&self, idx: usize, conn: PipelinedClient) {
+        *self.slot(idx) = Some(Slot {
+            conn,
+            last_used: Instant::now(),
+        });
+    }
+
+    /// 发一个 Ping 看连接是否还活着
+    ///
+    /// 只要收到响应就认为连接是好的，哪怕是不认识 Ping 的老服务器返回的错误。
+    async fn health_check(&self, conn: &PipelinedClient) -> Result<(), KvError> {
+        let cmd = CommandRequest::new_ping();
+        match time::timeout(self.inner.options.connect_timeout, conn.execute(cmd)).await {
+            Ok(res) => res.map(|_| ()),
+            Err(_) => Err(KvError::Timeout("Health check".into())),
+        }
+    }
+
+    async fn connect_with_backoff(&self) -> Result<PipelinedClient, KvError> {
+        let options = &self.inner.options;
+        let mut backoff = options.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            let res = match time::timeout(options.connect_timeout, self.connect()).await {
+                Ok(res) => res,
+                Err(_) => Err(KvError::Timeout(format!("Connecting to {}", options.addr))),
+            };
+            match res {
+                Ok(conn) => return Ok(conn),
+                // 认证失败、协议版本不兼容，重试也没用
+                Err(e @ (KvError::Unauthenticated(_) | KvError::UnsupportedProtocol(..))) => {
+                    return Err(e)
+                }
+                Err(e) if attempt >= options.max_retries => return Err(e),
+                Err(e) => {
+                    attempt += 1;
+                    warn!(
+                        "Failed to connect to {} ({}), retry {}/{} in {:?}",
+                        options.addr, e, attempt, options.max_retries, backoff
+                    );
+                    time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(options.max_backoff);
+                }
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<PipelinedClient, KvError> {
+        let options = &self.inner.options;
+        let conn = match options.addr.strip_prefix(UNIX_SCHEME) {
+            Some(path) => PipelinedClient::new(connect_unix(path).await?),
+            None => self.wrap(TcpStream::connect(&options.addr).await?).await?,
+        };
+        // 先握手检查协议版本。连接池里的连接固定使用 gzip；不认识 Hello 的老服务器会返回错误，忽略即可
+        // PipelinedClient 一直在读取连接，空闲时也能回复服务器的 ping
+        let hello =
+            CommandRequest::new_hello(&options.client_name, &[Compression::Gzip]).with_ping();
+        let res = conn.execute(hello).await?;
+        check_server_hello(&res)?;
+        if let Err(e) = res.into_result() {
+            debug!("Server does not support Hello: {}", e);
+        }
+        // 握手和 Auth 完成之后才会有其他请求用到这个连接
+        if let Some((username, password)) = &options.credentials {
+            let cmd = CommandRequest::new_auth(username, password);
+            conn.execute(cmd).await?.into_result()?;
+        }
+        Ok(conn)
+    }
+
+    /// 配置了 TLS 时在 stream 上做 TLS 握手
+    async fn wrap<S>(&self, stream: S) -> Result<PipelinedClient, KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        match &self.inner.options.tls {
+            Some(connector) => Ok(PipelinedClient::new(connector.connect(stream).await?)),
+            None => Ok(PipelinedClient::new(stream)),
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn connect_unix(path: &str) -> io::Result<tokio::net::UnixStream> {
+    tokio::net::UnixStream::connect(path).await
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(path: &str) -> io::Result<TcpStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "Unix domain socket {} is not supported on this platform",
+            path
+        ),
+    ))
+}
+
+fn request(data: RequestData) -> CommandRequest {
+    CommandRequest {
+        request_data: Some(data),
+        ..Default::default()
+    }
+}
+
+/// 服务器用空的 Value 表示不存在
+fn non_empty(v: Value) -> Option<Value> {
+    v.value.is_some().then_some(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{hash_password, Authenticator, MemTable, ProstServerStream, Service, ServiceInner};
+
+    async fn start_server(service: Service) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let svc = service.new_connection();
+                tokio::spawn(ProstServerStream::new(stream).process(svc));
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn kv_client_typed_methods_should_work() {
+        let addr = start_server(Service::new(MemTable::new())).await;
+        let client = KvClient::new(addr);
+
+        assert_eq!(client.hset("t1", "k1", "v1").await.unwrap(), None);
+        assert_eq!(
+            client.hset("t1", "k1", "v2").await.unwrap(),
+            Some("v1".into())
+        );
+        assert_eq!(client.hget("t1", "k1").await.unwrap(), Some("v2".into()));
+        assert_eq!(client.hget("t1", "k2").await.unwrap(), None);
+
+        let pairs = vec![Kvpair::new("k2", 2.into()), Kvpair::new("k3", 3.into())];
+        client.hmset("t1", pairs).await.unwrap();
+        let values = client
+            .hmget("t1", vec!["k2".into(), "k4".into()])
+            .await
+            .unwrap();
+        assert_eq!(values, vec![Some(2.into()), None]);
+        assert_eq!(client.hgetall("t1").await.unwrap().len(), 3);
+        let pairs: Vec<_> = client
+            .hgetall_stream("t1", 2)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(pairs.len(), 3);
+        assert!(pairs.iter().all(Result::is_ok));
+
+        assert!(client.hexist("t1", "k3").await.unwrap());
+        assert_eq!(client.hdel("t1", "k3").await.unwrap(), Some(3.into()));
+        let exists = client
+            .hmexist("t1", vec!["k2".into(), "k3".into()])
+            .await
+            .unwrap();
+        assert_eq!(exists, vec![true, false]);
+
+        let res = client.custom("INCR", vec![]).await;
+        assert!(matches!(res, Err(KvError::InvalidCommand(_))));
+        client.ping().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn kv_client_should_authenticate_each_connection() {
+        let auth = Authenticator::default();
+        auth.set_user("alice", hash_password("secret").unwrap());
+        let service: Service = ServiceInner::new(MemTable::new())
+            .authenticator(Arc::new(auth))
+            .into();
+        let addr = start_server(service).await;
+
+        let client = KvClient::builder(&addr)
+            .credentials("alice", "secret")
+            .pool_size(2)
+            .build();
+        let (r1, r2) = tokio::join!(client.hset("t1", "k1", "v1"), client.hget("t1", "k1"));
+        assert!(r1.is_ok() && r2.is_ok());
+
+        let client = KvClient::new(&addr);
+        let res = client.hget("t1", "k1").await;
+        assert!(matches!(res, Err(KvError::Unauthenticated(_))));
+
+        let client = KvClient::builder(&addr)
+            .credentials("alice", "wrong")
+            .build();
+        let res = client.hget("t1", "k1").await;
+        assert!(matches!(res, Err(KvError::Unauthenticated(_))));
+    }
+
+    #[tokio::test]
+    async fn kv_client_should_reconnect_with_backoff() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let client = KvClient::builder(addr.to_string())
+            .max_retries(10)
+            .backoff(Duration::from_millis(20), Duration::from_millis(100))
+            .health_check_interval(Duration::ZERO)
+            .build();
+
+        // 服务器晚一点才启动，记录每个连接的 task，方便之后把连接断掉
+        let conns = Arc::new(Mutex::new(Vec::new()));
+        let handles = conns.clone();
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(100)).await;
+            let listener = TcpListener::bind(addr).await.unwrap();
+            let service = Service::new(MemTable::new());
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let svc = service.new_connection();
+                let handle = tokio::spawn(ProstServerStream::new(stream).process(svc));
+                handles.lock().unwrap().push(handle);
+            }
+        });
+
+        assert_eq!(client.hset("t1", "k1", "v1").await.unwrap(), None);
+
+        // 服务器断开所有连接，池中的空闲连接在健康检查时被丢弃，然后重新连接
+        for handle in conns.lock().unwrap().drain(..) {
+            handle.abort();
+        }
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(client.hget("t1", "k1").await.unwrap(), Some("v1".into()));
+        assert_eq!(conns.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn into_result_should_not_depend_on_error_message() {
+        for e in [
+            KvError::NotFound("t1".into(), "k1".into()),
+            KvError::PermissionDenied("alice".into(), "t1".into(), "read, write".into()),
+            KvError::UnsupportedProtocol(3, 1, 2),
+            KvError::ScriptError("oops".into()),
+        ] {
+            let expected = e.to_string();
+            let mut res = CommandResponse::from(e);
+            // 错误信息改了措辞，客户端仍然能还原出同样的错误
+            res.message = "reworded".into();
+            let err = res.into_result().unwrap_err();
+            assert!(!matches!(err, KvError::ServerError(..)));
+            assert_eq!(err.to_string(), expected);
+        }
+
+        let res = CommandResponse::from(KvError::Internal("oops".into()));
+        let err = res.into_result().unwrap_err();
+        assert!(matches!(err, KvError::ServerError(500, _)));
+    }
+
+    #[tokio::test]
+    async fn kv_client_should_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // 只接受连接，从不响应
+        tokio::spawn(async move {
+            let mut conns = vec![];
+            loop {
+                conns.push(listener.accept().await.unwrap());
+            }
+        });
+
+        let client = KvClient::builder(addr)
+            .request_timeout(Duration::from_millis(100))
+            .build();
+        let res = client.hget("t1", "k1").await;
+        assert!(matches!(res, Err(KvError::Timeout(_))));
+    }
+}
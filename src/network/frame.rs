@@ -1,8 +1,7 @@
 use crate::*;
 use bytes::BytesMut;
 use bytes::{Buf, BufMut};
-use flate2::{read::GzDecoder, write::GzEncoder, Compression};
-use http::header;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression as GzLevel};
 use prost::Message;
 use std::io::{Read, Write};
 use tokio::io::{AsyncRead, AsyncReadExt};
@@ -12,64 +11,186 @@ use tracing::debug;
 pub const LEN_LEN: usize = 4;
 /// 最大帧长度为 30bit，1G
 const MAX_FRAME: usize = 1 * 1024 * 1024 * 1024;
-/// 当 payload 大于 1436 字节就做压缩
-const COMPRESSION_LIMIT: usize = 1436;
-
-/// 代表压缩的 bit，4 字节的最高两位， 00: 不压缩, 01: gzip压缩, 10: xx压缩, 11: xx压缩
+/// 默认当 payload 大于 1436 字节就做压缩
+pub const COMPRESSION_LIMIT: usize = 1436;
+/// 默认每个 frame 最大 64MB，压缩的 frame 解压后也不能超过这个大小
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+/// 读取 frame 时每次最多预留的内存，实际内存随着数据到达而增长
+const READ_CHUNK: usize = 64 * 1024;
+
+/// 代表压缩的 bit，4 字节的最高两位，00: 不压缩, 11: gzip 压缩, 01: lz4 压缩, 10: zstd 压缩
+///
+/// 已有的客户端一直把 gzip 编码成 11，为了兼容保持不变，lz4 和 zstd 使用剩下的 01 和 10。
 const COMPRESSION_BIT: usize = 3 << 30;
+const GZIP_BIT: usize = 3 << 30;
+const LZ4_BIT: usize = 1 << 30;
+const ZSTD_BIT: usize = 2 << 30;
+
+/// 帧编解码的配置：用什么算法压缩，payload 多大时才压缩，以及读取时的大小限制
+///
+/// 解码时根据帧头自动选择算法，不需要配置。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameConfig {
+    pub compression: Compression,
+    pub compression_limit: usize,
+    /// 帧头声明的长度超过它时，直接拒绝，不会为它分配内存
+    pub max_frame_size: usize,
+    /// 压缩的 frame 解压后的最大长度，防止解压炸弹
+    pub max_decompressed_size: usize,
+}
+
+impl Default for FrameConfig {
+    /// 没有握手协商过的连接使用 gzip，老的客户端只支持它
+    fn default() -> Self {
+        Self {
+            compression: Compression::Gzip,
+            compression_limit: COMPRESSION_LIMIT,
+            max_frame_size: MAX_FRAME_SIZE,
+            max_decompressed_size: MAX_FRAME_SIZE,
+        }
+    }
+}
+
+impl Compression {
+    /// 本端支持的所有压缩算法，按优先级排列
+    pub const SUPPORTED: [Compression; 4] = [
+        Compression::Zstd,
+        Compression::Lz4,
+        Compression::Gzip,
+        Compression::None,
+    ];
+
+    fn header_bit(self) -> usize {
+        match self {
+            Compression::None => 0,
+            Compression::Gzip => GZIP_BIT,
+            Compression::Lz4 => LZ4_BIT,
+            Compression::Zstd => ZSTD_BIT,
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>, KvError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Compression::Zstd => Ok(zstd::bulk::compress(data, 0)?),
+        }
+    }
+
+    /// 解压 data，解压后超过 limit 字节时返回 FrameError
+    fn decompress(self, data: &[u8], limit: usize) -> Result<Vec<u8>, KvError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => read_limited(GzDecoder::new(data), data.len(), limit),
+            Compression::Lz4 => {
+                let lz4_error =
+                    |e| KvError::Internal(format!("Failed to decompress lz4 frame: {}", e));
+                // lz4 在数据前面记录了解压后的长度，先检查它再分配内存
+                let (size, block) = lz4_flex::block::uncompressed_size(data).map_err(lz4_error)?;
+                if size > limit {
+                    return Err(KvError::FrameError);
+                }
+                lz4_flex::block::decompress(block, size).map_err(lz4_error)
+            }
+            Compression::Zstd => read_limited(zstd::Decoder::new(data)?, data.len(), limit),
+        }
+    }
+}
+
+/// 从解压器中最多读出 limit 个字节，超过时返回 FrameError
+fn read_limited(reader: impl Read, compressed: usize, limit: usize) -> Result<Vec<u8>, KvError> {
+    let mut proto = Vec::with_capacity((compressed * 2).min(limit));
+    reader.take(limit as u64 + 1).read_to_end(&mut proto)?;
+    if proto.len() > limit {
+        return Err(KvError::FrameError);
+    }
+    Ok(proto)
+}
+
+impl std::str::FromStr for Compression {
+    type Err = KvError;
+
+    /// 不区分大小写，如 "zstd"、"LZ4"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Compression::from_str_name(&s.to_ascii_uppercase())
+            .ok_or_else(|| KvError::InvalidCommand(format!("Unknown compression: {}", s)))
+    }
+}
 
 pub trait FrameCoder
 where
     Self: Message + Sized + Default,
 {
-    /// 把一个 Message encode 成一个 frame
+    /// 把一个 Message encode 成一个 frame，使用默认的 gzip 压缩
     fn encode_frame(&self, buf: &mut BytesMut) -> Result<(), KvError> {
+        self.encode_frame_with(buf, &FrameConfig::default())
+    }
+
+    /// 按 config 指定的算法和阈值把一个 Message encode 成一个 frame
+    fn encode_frame_with(&self, buf: &mut BytesMut, config: &FrameConfig) -> Result<(), KvError> {
         let size = self.encoded_len();
         if size >= MAX_FRAME {
             return Err(KvError::FrameError);
         }
 
-        buf.put_u32(size as _);
-
-        if size > COMPRESSION_LIMIT {
+        if config.compression != Compression::None && size > config.compression_limit {
             let mut proto = Vec::with_capacity(size);
             self.encode(&mut proto)?;
-            let payload = buf.split_off(LEN_LEN);
-            buf.clear();
-
-            let mut encoder = GzEncoder::new(payload.writer(), Compression::default());
-            encoder.write_all(&proto)?;
 
-            let payload = encoder.finish()?.into_inner();
-            debug!("Encode a frame: size {}({})", size, payload.len());
-
-            buf.put_u32((payload.len() | COMPRESSION_BIT) as _);
-
-            buf.unsplit(payload);
+            let payload = config.compression.compress(&proto)?;
+            debug!(
+                "Encode a frame: size {}({}) with {:?}",
+                size,
+                payload.len(),
+                config.compression
+            );
+            if payload.len() >= MAX_FRAME {
+                return Err(KvError::FrameError);
+            }
 
+            buf.put_u32((payload.len() | config.compression.header_bit()) as _);
+            buf.put_slice(&payload);
             Ok(())
         } else {
+            buf.put_u32(size as _);
             self.encode(buf)?;
             Ok(())
         }
     }
 
-    /// 把一个完整的 frame decode 成一个 Message
+    /// 把一个完整的 frame decode 成一个 Message，使用默认的大小限制
     fn decode_frame(buf: &mut BytesMut) -> Result<Self, KvError> {
-        let header = buf.get_u32() as usize;
-        let (len, compressed) = decode_header(header);
-        debug!("Got a frame: msg len {}, compressed {}", len, compressed);
+        Self::decode_frame_with(buf, &FrameConfig::default())
+    }
 
-        if compressed {
-            let mut decoder = GzDecoder::new(&buf[..len]);
+    /// 把一个完整的 frame decode 成一个 Message，解压后的长度不能超过 config 的限制
+    fn decode_frame_with(buf: &mut BytesMut, config: &FrameConfig) -> Result<Self, KvError> {
+        // 数据不完整时返回错误，而不是 panic
+        if buf.len() < LEN_LEN {
+            return Err(KvError::FrameError);
+        }
+        let header = buf.get_u32() as usize;
+        let (len, compression) = decode_header(header);
+        if buf.len() < len {
+            return Err(KvError::FrameError);
+        }
+        debug!(
+            "Got a frame: msg len {}, compression {:?}",
+            len, compression
+        );
 
-            let mut proto = Vec::with_capacity(len * 2);
-            decoder.read_to_end(&mut proto)?;
+        if compression != Compression::None {
+            let proto = compression.decompress(&buf[..len], config.max_decompressed_size)?;
             buf.advance(len);
 
             Ok(Self::decode(proto.as_ref())?)
         } else {
-            let msg = Self::decode(buf.as_ref())?;
+            let msg = Self::decode(&buf[..len])?;
             buf.advance(len);
             Ok(msg)
         }
@@ -78,29 +199,57 @@ where
 
 impl FrameCoder for CommandRequest {}
 impl FrameCoder for CommandResponse {}
+impl FrameCoder for WatchRequest {}
+impl FrameCoder for Change {}
 
-/// 从 stream 中读取一个完整的 frame
+/// 从 stream 中读取一个完整的 frame，使用默认的大小限制
 pub async fn read_frame<S>(stream: &mut S, buf: &mut BytesMut) -> Result<(), KvError>
+where
+    S: AsyncRead + Unpin + Send,
+{
+    read_frame_with(stream, buf, &FrameConfig::default()).await
+}
+
+/// 从 stream 中读取一个完整的 frame
+///
+/// 帧头声明的长度超过 config.max_frame_size 时直接返回 FrameError，不会分配内存。
+/// 内存随着数据到达按块分配，伪造的帧头无法让我们一次性预留大量内存。
+pub async fn read_frame_with<S>(
+    stream: &mut S,
+    buf: &mut BytesMut,
+    config: &FrameConfig,
+) -> Result<(), KvError>
 where
     S: AsyncRead + Unpin + Send,
 {
     let header = stream.read_u32().await? as usize;
-    let (len, _compressed) = decode_header(header);
-    // 如果没有这么大的内存，就分配至少一个 frame 的内存，保证它可用
-    buf.reserve(LEN_LEN + len);
+    let (len, _compression) = decode_header(header);
+    if len > config.max_frame_size {
+        return Err(KvError::FrameError);
+    }
+
+    buf.reserve(LEN_LEN + len.min(READ_CHUNK));
     buf.put_u32(header as _);
-    // advance_mut 是 unsafe 的原因是，从当前位置 pos 到 pos + len，
-    // 这段内存目前没有初始化。我们就是为了 reserve 这段内存，然后从 stream
-    // 里读取，读取完，它就是初始化的。所以，我们这么用是安全的
-    unsafe { buf.advance_mut(len) };
-    stream.read_exact(&mut buf[LEN_LEN..]).await?;
+    let end = buf.len() + len;
+    let mut body = stream.take(len as u64);
+    while buf.len() < end {
+        buf.reserve((end - buf.len()).min(READ_CHUNK));
+        if body.read_buf(buf).await? == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+    }
     Ok(())
 }
 
-fn decode_header(header: usize) -> (usize, bool) {
+fn decode_header(header: usize) -> (usize, Compression) {
     let len = header & !COMPRESSION_BIT;
-    let compressd = header & COMPRESSION_BIT == COMPRESSION_BIT;
-    (len, compressd)
+    let compression = match header & COMPRESSION_BIT {
+        GZIP_BIT => Compression::Gzip,
+        LZ4_BIT => Compression::Lz4,
+        ZSTD_BIT => Compression::Zstd,
+        _ => Compression::None,
+    };
+    (len, compression)
 }
 
 #[cfg(test)]
@@ -108,6 +257,7 @@ mod tests {
     use super::*;
     use crate::Value;
     use bytes::Bytes;
+    use tokio::io::AsyncWriteExt;
 
     #[test]
     fn should_decode_header_work() {
@@ -121,23 +271,23 @@ mod tests {
 
         let header = encode_header(1);
         // println!("{}", header);
-        assert_eq!(decode_header(header), (1, false));
+        assert_eq!(decode_header(header), (1, Compression::None));
 
         let header = encode_header(0);
         // println!("{}", header);
-        assert_eq!(decode_header(header), (0, false));
+        assert_eq!(decode_header(header), (0, Compression::None));
 
         let header = encode_header(1000);
         // println!("{}", header);
-        assert_eq!(decode_header(header), (1000, false));
+        assert_eq!(decode_header(header), (1000, Compression::None));
 
         let header = encode_header(1436);
         // println!("{}", header);
-        assert_eq!(decode_header(header), (1436, true));
+        assert_eq!(decode_header(header), (1436, Compression::Gzip));
 
         let header = encode_header(10000);
         // println!("{}", header);
-        assert_eq!(decode_header(header), (10000, true));
+        assert_eq!(decode_header(header), (10000, Compression::Gzip));
     }
 
     #[test]
@@ -184,6 +334,45 @@ mod tests {
         assert_eq!(res, res1);
     }
 
+    #[test]
+    fn frame_should_support_all_compressions() {
+        let value: Value = Bytes::from(b"hello world ".repeat(1000)).into();
+        let res: CommandResponse = value.into();
+
+        for compression in Compression::SUPPORTED {
+            let config = FrameConfig {
+                compression,
+                compression_limit: 100,
+                ..Default::default()
+            };
+            let mut buf = BytesMut::new();
+            res.encode_frame_with(&mut buf, &config).unwrap();
+
+            let header = u32::from_be_bytes(buf[..LEN_LEN].try_into().unwrap()) as usize;
+            let (len, used) = decode_header(header);
+            assert_eq!(used, compression);
+            assert_eq!(len, buf.len() - LEN_LEN);
+
+            let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
+            assert_eq!(res, res1);
+        }
+    }
+
+    #[test]
+    fn frame_should_respect_compression_limit() {
+        let value: Value = Bytes::from(vec![0u8; 2000]).into();
+        let res: CommandResponse = value.into();
+        let config = FrameConfig {
+            compression: Compression::Zstd,
+            compression_limit: 4096,
+            ..Default::default()
+        };
+        let mut buf = BytesMut::new();
+        res.encode_frame_with(&mut buf, &config).unwrap();
+        assert!(!is_compressed(&buf));
+        assert_eq!(CommandResponse::decode_frame(&mut buf).unwrap(), res);
+    }
+
     fn is_compressed(data: &[u8]) -> bool {
         if let &[v] = &data[..1] {
             v >> 7 == 1
@@ -229,4 +418,87 @@ mod tests {
         let cmd1 = CommandRequest::decode_frame(&mut data).unwrap();
         assert_eq!(cmd, cmd1);
     }
+
+    #[tokio::test]
+    async fn read_frame_should_work_when_data_arrives_in_pieces() {
+        let value: Value = Bytes::from(vec![1u8; 200 * 1024]).into();
+        let res: CommandResponse = value.into();
+        let config = FrameConfig {
+            compression: Compression::None,
+            ..Default::default()
+        };
+        let mut buf = BytesMut::new();
+        res.encode_frame_with(&mut buf, &config).unwrap();
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        tokio::spawn(async move { server.write_all(&buf).await });
+
+        let mut data = BytesMut::new();
+        read_frame(&mut client, &mut data).await.unwrap();
+        assert_eq!(CommandResponse::decode_frame(&mut data).unwrap(), res);
+    }
+
+    #[tokio::test]
+    async fn read_frame_should_reject_oversized_header() {
+        // 帧头声明了将近 1G 的数据，但后面什么都没有
+        let mut stream = DummyStream {
+            buf: BytesMut::from(&((MAX_FRAME - 1) as u32).to_be_bytes()[..]),
+        };
+        let mut data = BytesMut::new();
+        let res = read_frame(&mut stream, &mut data).await;
+        assert!(matches!(res, Err(KvError::FrameError)));
+        assert_eq!(data.capacity(), 0);
+
+        let mut buf = BytesMut::new();
+        CommandRequest::new_hget("t1", "k1")
+            .encode_frame(&mut buf)
+            .unwrap();
+        let config = FrameConfig {
+            max_frame_size: 8,
+            ..Default::default()
+        };
+        let mut stream = DummyStream { buf };
+        let res = read_frame_with(&mut stream, &mut data, &config).await;
+        assert!(matches!(res, Err(KvError::FrameError)));
+    }
+
+    #[test]
+    fn decode_frame_should_reject_truncated_input() {
+        let mut buf = BytesMut::new();
+        CommandRequest::new_hget("t1", "k1")
+            .encode_frame(&mut buf)
+            .unwrap();
+
+        for len in [0, LEN_LEN - 1, LEN_LEN, buf.len() - 1] {
+            let mut truncated = BytesMut::from(&buf[..len]);
+            let res = CommandRequest::decode_frame(&mut truncated);
+            assert!(matches!(res, Err(KvError::FrameError)), "len {}", len);
+        }
+    }
+
+    #[test]
+    fn decode_frame_should_reject_decompression_bombs() {
+        // 1MB 的 0 压缩后只有很小的体积
+        let value: Value = Bytes::from(vec![0u8; 1024 * 1024]).into();
+        let res: CommandResponse = value.into();
+        let limit = FrameConfig {
+            max_decompressed_size: 64 * 1024,
+            ..Default::default()
+        };
+
+        for compression in [Compression::Gzip, Compression::Lz4, Compression::Zstd] {
+            let config = FrameConfig {
+                compression,
+                ..Default::default()
+            };
+            let mut buf = BytesMut::new();
+            res.encode_frame_with(&mut buf, &config).unwrap();
+            assert!(buf.len() < 64 * 1024);
+
+            let mut bomb = buf.clone();
+            let err = CommandResponse::decode_frame_with(&mut bomb, &limit).unwrap_err();
+            assert!(matches!(err, KvError::FrameError), "{:?}", compression);
+            assert_eq!(CommandResponse::decode_frame(&mut buf).unwrap(), res);
+        }
+    }
 }
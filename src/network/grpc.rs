@@ -0,0 +1,368 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{stream, Stream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::broadcast::{error::RecvError, Receiver},
+};
+use tonic::{transport::server::Connected, Request, Response, Status};
+use tower::{timeout::Timeout, ServiceExt};
+use tracing::warn;
+
+use super::http::{authenticate, parse_basic_auth};
+use crate::{
+    kv_server::{Kv, KvServer},
+    service::layer_error,
+    Change, CommandRequest, CommandResponse, ConnectionPermit, ConnectionService, ErrorInfo,
+    KvError, LimitsConfig, Service, Storage, WatchRequest,
+};
+
+/// 用 gRPC 提供 `Service`，和自定义 frame 的 TCP 协议并存
+///
+/// 每个 gRPC 请求都是一个新的连接。服务器开启认证时，请求的 metadata 里要带上
+/// `authorization: Basic base64(username:password)`，认证失败时返回 UNAUTHENTICATED。
+/// 命令和其他 listener 一样有超时，分块的 Hgetall 会合并成一个响应。
+/// 每个连接上同时处理的请求数由 tonic 的 `Server::concurrency_limit_per_connection` 限制。
+pub struct GrpcService<Store> {
+    service: Service<Store>,
+    timeout: Timeout<ConnectionService>,
+}
+
+impl<Store> GrpcService<Store>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    pub fn new(service: Service<Store>) -> Self {
+        Self::with_limits(service, &LimitsConfig::default())
+    }
+
+    /// 请求的超时等配置
+    pub fn limits(self, limits: &LimitsConfig) -> Self {
+        Self::with_limits(self.service, limits)
+    }
+
+    fn with_limits(service: Service<Store>, limits: &LimitsConfig) -> Self {
+        let timeout = Duration::from_millis(limits.request_timeout_ms);
+        Self {
+            service,
+            timeout: Timeout::new(ConnectionService, timeout),
+        }
+    }
+
+    /// 生成可以交给 `tonic::transport::Server` 的 gRPC 服务
+    pub fn into_server(self) -> KvServer<Self> {
+        KvServer::new(self)
+    }
+
+    /// 为请求创建一个连接，带上 mTLS 的客户端身份；有 authorization metadata 时先认证
+    async fn connect<T>(&self, request: &Request<T>) -> Result<Service<Store>, KvError> {
+        let conn = self.service.new_connection();
+        if let Some(PeerIdentity(Some(identity))) = request.extensions().get::<PeerIdentity>() {
+            conn.context().set_peer_identity(identity.clone());
+        }
+        match request.metadata().get("authorization") {
+            Some(value) => {
+                let value = value
+                    .to_str()
+                    .map_err(|_| KvError::Unauthenticated("Invalid Authorization header".into()))?;
+                let (username, password) = parse_basic_auth(value)?;
+                authenticate(conn, username, password).await
+            }
+            None => Ok(conn),
+        }
+    }
+}
+
+/// 交给 tonic 的一个连接（`serve_with_incoming`），TCP 和 TLS 都可以
+///
+/// 连接关闭时释放连接数限制的 permit；mTLS 的客户端身份会放进每个请求的 extensions 里。
+pub struct GrpcConnection {
+    inner: Box<dyn Io>,
+    identity: Option<String>,
+    _permit: Option<ConnectionPermit>,
+}
+
+trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+/// tonic 放进请求 extensions 里的连接信息：mTLS 的客户端身份
+#[derive(Clone, Debug)]
+pub struct PeerIdentity(Option<String>);
+
+impl GrpcConnection {
+    pub fn new<S>(stream: S, identity: Option<String>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        Self {
+            inner: Box::new(stream),
+            identity,
+            _permit: None,
+        }
+    }
+
+    /// 连接关闭时才释放 permit
+    pub fn permit(mut self, permit: ConnectionPermit) -> Self {
+        self._permit = Some(permit);
+        self
+    }
+}
+
+impl Connected for GrpcConnection {
+    type ConnectInfo = PeerIdentity;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        PeerIdentity(self.identity.clone())
+    }
+}
+
+impl AsyncRead for GrpcConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for GrpcConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+type WatchStream = Pin<Box<dyn Stream<Item = Result<Change, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl<Store> Kv for GrpcService<Store>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    async fn execute(
+        &self,
+        request: Request<CommandRequest>,
+    ) -> Result<Response<CommandResponse>, Status> {
+        let conn = self.connect(&request).await.map_err(to_status)?;
+        let res = match self
+            .timeout
+            .clone()
+            .oneshot((conn, request.into_inner()))
+            .await
+        {
+            Ok(responses) => responses.merge(),
+            Err(e) => {
+                let e = layer_error(e);
+                warn!("Failed to execute request: {}", e);
+                e.into()
+            }
+        };
+        // 认证、权限的错误和 watch 一样返回 Status，其他错误（如 404）放在 CommandResponse 里
+        let denied = res
+            .error
+            .as_ref()
+            .and_then(ErrorInfo::to_error)
+            .filter(|e| {
+                matches!(
+                    e,
+                    KvError::Unauthenticated(_) | KvError::PermissionDenied(..)
+                )
+            });
+        match denied {
+            Some(e) => Err(to_status(e)),
+            None => Ok(Response::new(res)),
+        }
+    }
+
+    type WatchStream = WatchStream;
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let conn = self.connect(&request).await.map_err(to_status)?;
+        let table = request.into_inner().table;
+        let rx = conn.watch(&table).map_err(to_status)?;
+        Ok(Response::new(Box::pin(changes(rx, table))))
+    }
+}
+
+/// 只保留 table 的变化；订阅者落后太多时以 DATA_LOSS 结束，客户端需要重新订阅
+fn changes(
+    rx: Receiver<Change>,
+    table: String,
+) -> impl Stream<Item = Result<Change, Status>> + Send {
+    stream::unfold(Some((rx, table)), |state| async move {
+        let (mut rx, table) = state?;
+        loop {
+            match rx.recv().await {
+                Ok(change) if change.table == table => {
+                    return Some((Ok(change), Some((rx, table))))
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Watcher of table {} lagged behind by {} changes", table, n);
+                    let status = Status::data_loss(format!("Missed {} changes", n));
+                    return Some((Err(status), None));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+fn to_status(e: KvError) -> Status {
+    let msg = e.to_string();
+    match e {
+        KvError::NotFound(..) => Status::not_found(msg),
+        KvError::InvalidCommand(_) => Status::invalid_argument(msg),
+        KvError::Unauthenticated(_) => Status::unauthenticated(msg),
+        KvError::PermissionDenied(..) => Status::permission_denied(msg),
+        KvError::Timeout(_) => Status::deadline_exceeded(msg),
+        KvError::TooManyConnections(_) | KvError::Overloaded(_) => Status::unavailable(msg),
+        _ => Status::internal(msg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+    use super::*;
+    use crate::{hash_password, kv_client::KvClient, Authenticator, MemTable, ServiceInner, Value};
+    use futures::StreamExt;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{transport::Channel, Code};
+
+    async fn start_server(service: Service) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(GrpcService::new(service).into_server())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        addr
+    }
+
+    async fn connect(addr: SocketAddr) -> KvClient<Channel> {
+        KvClient::connect(format!("http://{}", addr)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn grpc_execute_should_work() {
+        let addr = start_server(Service::new(MemTable::new())).await;
+        let mut client = connect(addr).await;
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let res = client.execute(cmd).await.unwrap().into_inner();
+        assert_eq!(res.status, 200);
+
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let res = client.execute(cmd).await.unwrap().into_inner();
+        assert_eq!(res.values, [Value::from("v1")]);
+
+        let cmd = CommandRequest::new_hget("t1", "k2");
+        let res = client.execute(cmd).await.unwrap().into_inner();
+        assert_eq!(res.status, 404);
+    }
+
+    #[tokio::test]
+    async fn grpc_chunked_hgetall_should_return_all_pairs() {
+        let service = Service::new(MemTable::new());
+        for i in 0..5 {
+            service.execute(CommandRequest::new_hset("t1", format!("k{}", i), i.into()));
+        }
+        let addr = start_server(service).await;
+        let mut client = connect(addr).await;
+
+        let cmd = CommandRequest::new_hgetall_chunked("t1", 2);
+        let res = client.execute(cmd).await.unwrap().into_inner();
+        assert_eq!(res.status, 200);
+        assert_eq!(res.pairs.len(), 5);
+        assert!(!res.more);
+    }
+
+    #[tokio::test]
+    async fn grpc_watch_should_stream_changes() {
+        let addr = start_server(Service::new(MemTable::new())).await;
+        let mut client = connect(addr).await;
+
+        let req = WatchRequest { table: "t1".into() };
+        let mut changes = client.watch(req).await.unwrap().into_inner();
+
+        for (table, key) in [("t2", "k1"), ("t1", "k2")] {
+            let cmd = CommandRequest::new_hset(table, key, "v".into());
+            client.execute(cmd).await.unwrap();
+        }
+        client
+            .execute(CommandRequest::new_hdel("t1", "k2"))
+            .await
+            .unwrap();
+
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!((change.key.as_str(), change.deleted), ("k2", false));
+        assert_eq!(change.value, Some("v".into()));
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!((change.key.as_str(), change.deleted), ("k2", true));
+    }
+
+    #[tokio::test]
+    async fn grpc_should_authenticate_with_metadata() {
+        let users = HashMap::from([("alice".to_string(), hash_password("secret").unwrap())]);
+        let service: Service = ServiceInner::new(MemTable::new())
+            .authenticator(Arc::new(Authenticator::new(users)))
+            .into();
+        let addr = start_server(service).await;
+        let mut client = connect(addr).await;
+
+        let err = client
+            .execute(CommandRequest::new_hgetall("t1"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::Unauthenticated);
+
+        let err = client
+            .watch(WatchRequest { table: "t1".into() })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::Unauthenticated);
+
+        // base64("alice:secret")
+        let mut req = Request::new(CommandRequest::new_hgetall("t1"));
+        req.metadata_mut()
+            .insert("authorization", "Basic YWxpY2U6c2VjcmV0".parse().unwrap());
+        let res = client.execute(req).await.unwrap().into_inner();
+        assert_eq!(res.status, 200);
+    }
+
+    #[tokio::test]
+    async fn grpc_should_use_peer_identity_of_the_connection() {
+        let grpc = GrpcService::new(Service::new(MemTable::new()));
+        let mut req = Request::new(CommandRequest::new_hgetall("t1"));
+        req.extensions_mut()
+            .insert(PeerIdentity(Some("alice".into())));
+
+        let conn = grpc.connect(&req).await.unwrap();
+        assert_eq!(conn.context().peer_identity().as_deref(), Some("alice"));
+    }
+}
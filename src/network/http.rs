@@ -0,0 +1,404 @@
+use std::io;
+
+use axum::{
+    extract::{rejection::JsonRejection, Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::server::conn::http1;
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+use crate::{
+    service::layer_error, CommandRequest, CommandResponse, ConnectionService, KvError,
+    LayeredConnections, LimitsConfig, Service, Storage, Value,
+};
+
+/// HTTP/JSON 网关，给不方便处理 protobuf frame 的工具（shell 脚本、浏览器等）使用
+///
+/// - `POST /v1/command`：body 是 JSON 格式的 `CommandRequest`，
+///   如 `{"request_data": {"hget": {"table": "t1", "key": "k1"}}}`
+/// - `GET /tables/:table/keys`：Hgetall
+/// - `GET /tables/:table/keys/:key`：Hget
+/// - `PUT /tables/:table/keys/:key`：Hset，body 是 JSON 格式的 `Value`，如 `{"value": {"string": "v1"}}`
+/// - `DELETE /tables/:table/keys/:key`：Hdel
+///
+/// 响应都是 JSON 格式的 `CommandResponse`，HTTP 状态码就是 `CommandResponse.status`；
+/// 分块的 Hgetall 会合并成一个响应。
+/// 服务器开启认证时，每个请求都要带上 `Authorization: Basic ...` 头。
+/// 命令和其他 listener 一样经过超时、限流，同一个 router 上的请求共用一个并发限制。
+pub fn http_router<Store>(service: Service<Store>, limits: &LimitsConfig) -> Router
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    router(HttpState {
+        service,
+        layers: ConnectionService::layered(limits),
+        identity: None,
+    })
+}
+
+/// 在一个连接上处理 HTTP/1.1 请求，直到客户端断开；shutdown 取消后处理完当前请求就关闭
+///
+/// 和 RESP、protobuf 的连接一样，accept、TLS 握手和连接数限制由调用者负责，
+/// 连接上同时处理的请求数由 limits 限制。
+/// identity 是 mTLS 客户端证书中的身份，会设置到这个连接上每个请求的上下文里。
+pub async fn serve_http<S, Store>(
+    stream: S,
+    service: Service<Store>,
+    limits: &LimitsConfig,
+    identity: Option<String>,
+    shutdown: CancellationToken,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    Store: Storage + Send + Sync + 'static,
+{
+    let router = router(HttpState {
+        service,
+        layers: ConnectionService::layered(limits),
+        identity,
+    });
+    let conn = http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), TowerToHyperService::new(router));
+    tokio::pin!(conn);
+
+    let res = tokio::select! {
+        res = conn.as_mut() => res,
+        _ = shutdown.cancelled() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    res.map_err(|e| KvError::IoError(io::Error::other(e)))
+}
+
+/// 一个 HTTP 连接上所有请求共用的状态；每个请求仍然是 Service 的一个新连接，单独认证，
+/// 但共用同一套 layer
+struct HttpState<Store> {
+    service: Service<Store>,
+    layers: LayeredConnections,
+    identity: Option<String>,
+}
+
+impl<Store> Clone for HttpState<Store> {
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+            layers: self.layers.clone(),
+            identity: self.identity.clone(),
+        }
+    }
+}
+
+fn router<Store>(state: HttpState<Store>) -> Router
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/v1/command", post(command::<Store>))
+        .route("/tables/:table/keys", get(hgetall::<Store>))
+        .route(
+            "/tables/:table/keys/:key",
+            get(hget::<Store>).put(hset::<Store>).delete(hdel::<Store>),
+        )
+        .with_state(state)
+}
+
+async fn command<Store>(
+    State(state): State<HttpState<Store>>,
+    headers: HeaderMap,
+    cmd: Result<Json<CommandRequest>, JsonRejection>,
+) -> Response
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    match cmd {
+        Ok(Json(cmd)) => execute(&state, &headers, cmd).await,
+        Err(e) => reply(KvError::InvalidCommand(e.body_text()).into()),
+    }
+}
+
+async fn hgetall<Store>(
+    State(state): State<HttpState<Store>>,
+    headers: HeaderMap,
+    Path(table): Path<String>,
+) -> Response
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    execute(&state, &headers, CommandRequest::new_hgetall(table)).await
+}
+
+async fn hget<Store>(
+    State(state): State<HttpState<Store>>,
+    headers: HeaderMap,
+    Path((table, key)): Path<(String, String)>,
+) -> Response
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    execute(&state, &headers, CommandRequest::new_hget(table, key)).await
+}
+
+async fn hset<Store>(
+    State(state): State<HttpState<Store>>,
+    headers: HeaderMap,
+    Path((table, key)): Path<(String, String)>,
+    value: Result<Json<Value>, JsonRejection>,
+) -> Response
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    match value {
+        Ok(Json(value)) => {
+            execute(
+                &state,
+                &headers,
+                CommandRequest::new_hset(table, key, value),
+            )
+            .await
+        }
+        Err(e) => reply(KvError::InvalidCommand(e.body_text()).into()),
+    }
+}
+
+async fn hdel<Store>(
+    State(state): State<HttpState<Store>>,
+    headers: HeaderMap,
+    Path((table, key)): Path<(String, String)>,
+) -> Response
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    execute(&state, &headers, CommandRequest::new_hdel(table, key)).await
+}
+
+/// 每个 HTTP 请求都是一个新的连接，有 Authorization 头时先认证再执行命令
+async fn execute<Store>(
+    state: &HttpState<Store>,
+    headers: &HeaderMap,
+    cmd: CommandRequest,
+) -> Response
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    let conn = state.service.new_connection();
+    if let Some(identity) = &state.identity {
+        conn.context().set_peer_identity(identity.clone());
+    }
+    let conn = match basic_auth(headers) {
+        Ok(Some((username, password))) => match authenticate(conn, username, password).await {
+            Ok(conn) => conn,
+            Err(e) => return reply(e.into()),
+        },
+        Ok(None) => conn,
+        Err(e) => return reply(e.into()),
+    };
+
+    let res = match state.layers.clone().oneshot((conn, cmd)).await {
+        Ok(responses) => responses.merge(),
+        Err(e) => {
+            let e = layer_error(e);
+            warn!("Failed to execute request: {}", e);
+            e.into()
+        }
+    };
+    reply(res)
+}
+
+/// 用 Authorization 头里的凭证认证这个连接，成功后返回它
+///
+/// 和其他 listener 上的 Auth 命令一样，argon2 在 blocking 线程池里执行，不会占住 async 的 worker 线程。
+pub(crate) async fn authenticate<Store>(
+    conn: Service<Store>,
+    username: String,
+    password: String,
+) -> Result<Service<Store>, KvError>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    let cmd = CommandRequest::new_auth(username, password);
+    let mut responses = conn.execute_async(cmd).await;
+    let res = responses
+        .next()
+        .unwrap_or_else(|| KvError::Internal("Got no response".into()).into());
+    res.into_result().map(|_| conn)
+}
+
+/// 解析 `Authorization: Basic base64(username:password)`
+fn basic_auth(headers: &HeaderMap) -> Result<Option<(String, String)>, KvError> {
+    let Some(value) = headers.get(AUTHORIZATION) else {
+        return Ok(None);
+    };
+    let value = value.to_str().map_err(|_| invalid_authorization())?;
+    parse_basic_auth(value).map(Some)
+}
+
+/// 解析 Basic 认证的值，gRPC 的 authorization metadata 也用同样的格式
+pub(crate) fn parse_basic_auth(value: &str) -> Result<(String, String), KvError> {
+    let encoded = value
+        .strip_prefix("Basic ")
+        .ok_or_else(invalid_authorization)?;
+    let decoded = STANDARD
+        .decode(encoded.trim())
+        .map_err(|_| invalid_authorization())?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid_authorization())?;
+    let (username, password) = decoded.split_once(':').ok_or_else(invalid_authorization)?;
+    Ok((username.into(), password.into()))
+}
+
+fn invalid_authorization() -> KvError {
+    KvError::Unauthenticated("Invalid Authorization header".into())
+}
+
+/// 用 CommandResponse.status 作为 HTTP 状态码
+fn reply(res: CommandResponse) -> Response {
+    let status = StatusCode::from_u16(res.status as u16).unwrap_or_else(|_| {
+        debug!("Invalid status code in response: {}", res.status);
+        StatusCode::INTERNAL_SERVER_ERROR
+    });
+    (status, Json(res)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::*;
+    use crate::{hash_password, Authenticator, MemTable, ServiceInner};
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    async fn call(router: &Router, req: Request<Body>) -> (StatusCode, CommandResponse) {
+        let res = router.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn request(method: &str, uri: &str, body: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rest_routes_should_work() {
+        let router = http_router(Service::new(MemTable::new()), &LimitsConfig::default());
+
+        let req = request(
+            "PUT",
+            "/tables/t1/keys/k1",
+            r#"{"value": {"string": "v1"}}"#,
+        );
+        let (status, _) = call(&router, req).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, res) = call(&router, request("GET", "/tables/t1/keys/k1", "")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(res.values, [Value::from("v1")]);
+
+        let (status, res) = call(&router, request("GET", "/tables/t1/keys", "")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(res.pairs.len(), 1);
+
+        let (status, _) = call(&router, request("DELETE", "/tables/t1/keys/k1", "")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, res) = call(&router, request("GET", "/tables/t1/keys/k1", "")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(res.status, 404);
+    }
+
+    #[tokio::test]
+    async fn command_route_should_accept_json_request() {
+        let router = http_router(Service::new(MemTable::new()), &LimitsConfig::default());
+
+        let body = r#"{"request_data": {"hset": {"table": "t1", "pair": {"key": "k1", "value": {"value": {"integer": 42}}}}}}"#;
+        let (status, _) = call(&router, request("POST", "/v1/command", body)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let body = r#"{"request_data": {"hget": {"table": "t1", "key": "k1"}}}"#;
+        let (status, res) = call(&router, request("POST", "/v1/command", body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(res.values, [Value::from(42)]);
+
+        let (status, res) = call(&router, request("POST", "/v1/command", "not json")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(res.message.starts_with("Command is invalid"));
+    }
+
+    #[tokio::test]
+    async fn chunked_hgetall_should_return_all_pairs() {
+        let service = Service::new(MemTable::new());
+        for i in 0..5 {
+            service.execute(CommandRequest::new_hset("t1", format!("k{}", i), i.into()));
+        }
+        let router = http_router(service, &LimitsConfig::default());
+
+        let body = r#"{"request_data": {"hgetall": {"table": "t1", "chunk_size": 2}}}"#;
+        let (status, res) = call(&router, request("POST", "/v1/command", body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(res.pairs.len(), 5);
+        assert!(!res.more);
+    }
+
+    #[tokio::test]
+    async fn serve_http_should_handle_requests_on_a_stream() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        let service = Service::new(MemTable::new());
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        let limits = LimitsConfig::default();
+        let token = CancellationToken::new();
+        let server =
+            tokio::spawn(async move { serve_http(server, service, &limits, None, token).await });
+
+        let req = "GET /tables/t1/keys/k1 HTTP/1.1\r\nHost: kv\r\nConnection: close\r\n\r\n";
+        client.write_all(req.as_bytes()).await.unwrap();
+        let mut res = String::new();
+        client.read_to_string(&mut res).await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{}", res);
+        assert!(res.contains("v1"));
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn basic_auth_should_be_checked() {
+        let users = HashMap::from([("alice".to_string(), hash_password("secret").unwrap())]);
+        let service: Service = ServiceInner::new(MemTable::new())
+            .authenticator(Arc::new(Authenticator::new(users)))
+            .into();
+        let router = http_router(service, &LimitsConfig::default());
+
+        let (status, _) = call(&router, request("GET", "/tables/t1/keys", "")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let mut req = request("GET", "/tables/t1/keys", "");
+        let token = STANDARD.encode("alice:wrong");
+        req.headers_mut()
+            .insert(AUTHORIZATION, format!("Basic {}", token).parse().unwrap());
+        let (status, _) = call(&router, req).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let mut req = request("GET", "/tables/t1/keys", "");
+        let token = STANDARD.encode("alice:secret");
+        req.headers_mut()
+            .insert(AUTHORIZATION, format!("Basic {}", token).parse().unwrap());
+        let (status, _) = call(&router, req).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
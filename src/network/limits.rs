@@ -0,0 +1,189 @@
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use dashmap::DashMap;
+
+use crate::KvError;
+
+/// 服务器连接相关的计数，可以定期打到日志里，或者接到监控系统
+#[derive(Debug, Default)]
+pub struct ConnectionMetrics {
+    active: AtomicU64,
+    accepted: AtomicU64,
+    rejected_global: AtomicU64,
+    rejected_per_ip: AtomicU64,
+    idle_closed: AtomicU64,
+    ping_timeouts: AtomicU64,
+}
+
+/// ConnectionMetrics 某一时刻的值
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// 当前的连接数
+    pub active: u64,
+    /// 接受的连接总数
+    pub accepted: u64,
+    /// 因为总连接数超过限制被拒绝的连接
+    pub rejected_global: u64,
+    /// 因为同一个 IP 的连接数超过限制被拒绝的连接
+    pub rejected_per_ip: u64,
+    /// 空闲太久被关闭的连接
+    pub idle_closed: u64,
+    /// 没有回复服务器的 ping 被关闭的连接
+    pub ping_timeouts: u64,
+}
+
+impl ConnectionMetrics {
+    pub fn snapshot(&self) -> ConnectionStats {
+        ConnectionStats {
+            active: self.active.load(Ordering::Relaxed),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected_global: self.rejected_global.load(Ordering::Relaxed),
+            rejected_per_ip: self.rejected_per_ip.load(Ordering::Relaxed),
+            idle_closed: self.idle_closed.load(Ordering::Relaxed),
+            ping_timeouts: self.ping_timeouts.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn record_idle_closed(&self) {
+        self.idle_closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_ping_timeout(&self) {
+        self.ping_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 限制服务器的总连接数和每个 IP 的连接数
+///
+/// clone 出来的 ConnectionLimiter 共享同一组计数，所有 listener 应该使用同一个。
+#[derive(Clone)]
+pub struct ConnectionLimiter {
+    inner: Arc<LimiterInner>,
+}
+
+struct LimiterInner {
+    max_connections: usize,
+    max_connections_per_ip: usize,
+    per_ip: DashMap<IpAddr, usize>,
+    metrics: Arc<ConnectionMetrics>,
+}
+
+/// 一个连接占用的名额，drop 时归还
+pub struct ConnectionPermit {
+    inner: Arc<LimiterInner>,
+    ip: Option<IpAddr>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_connections: usize, max_connections_per_ip: usize) -> Self {
+        Self {
+            inner: Arc::new(LimiterInner {
+                max_connections,
+                max_connections_per_ip,
+                per_ip: DashMap::new(),
+                metrics: Arc::new(ConnectionMetrics::default()),
+            }),
+        }
+    }
+
+    pub fn metrics(&self) -> &Arc<ConnectionMetrics> {
+        &self.inner.metrics
+    }
+
+    /// 为一个新连接申请名额，超过限制时返回 `KvError::TooManyConnections`
+    ///
+    /// ip 为 None（如 Unix domain socket）时只检查总连接数。
+    pub fn try_acquire(&self, ip: Option<IpAddr>) -> Result<ConnectionPermit, KvError> {
+        let inner = &self.inner;
+        let metrics = &inner.metrics;
+        let max = inner.max_connections as u64;
+        let acquired = metrics
+            .active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            });
+        if acquired.is_err() {
+            metrics.rejected_global.fetch_add(1, Ordering::Relaxed);
+            return Err(KvError::TooManyConnections(format!(
+                "server allows at most {} connections",
+                inner.max_connections
+            )));
+        }
+        // 从这里开始，出错时 permit 被 drop，会把上面占用的名额还回去
+        let mut permit = ConnectionPermit {
+            inner: inner.clone(),
+            ip: None,
+        };
+
+        if let Some(ip) = ip {
+            let mut count = inner.per_ip.entry(ip).or_insert(0);
+            if *count >= inner.max_connections_per_ip {
+                drop(count);
+                metrics.rejected_per_ip.fetch_add(1, Ordering::Relaxed);
+                return Err(KvError::TooManyConnections(format!(
+                    "{} already has {} connections",
+                    ip, inner.max_connections_per_ip
+                )));
+            }
+            *count += 1;
+            permit.ip = Some(ip);
+        }
+        metrics.accepted.fetch_add(1, Ordering::Relaxed);
+        Ok(permit)
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.inner.metrics.active.fetch_sub(1, Ordering::AcqRel);
+        if let Some(ip) = self.ip {
+            if let Some(mut count) = self.inner.per_ip.get_mut(&ip) {
+                *count -= 1;
+            }
+            // 不再有连接的 IP 从表里删掉，避免表无限增长
+            self.inner.per_ip.remove_if(&ip, |_, count| *count == 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_limiter_should_enforce_limits() {
+        let limiter = ConnectionLimiter::new(3, 2);
+        let ip1: IpAddr = "10.0.0.1".parse().unwrap();
+        let ip2: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let p1 = limiter.try_acquire(Some(ip1)).unwrap();
+        let _p2 = limiter.try_acquire(Some(ip1)).unwrap();
+        let res = limiter.try_acquire(Some(ip1));
+        assert!(matches!(res, Err(KvError::TooManyConnections(_))));
+
+        let _p3 = limiter.try_acquire(None).unwrap();
+        let res = limiter.try_acquire(Some(ip2));
+        assert!(matches!(res, Err(KvError::TooManyConnections(_))));
+
+        // 归还名额之后可以再连接
+        drop(p1);
+        let _p4 = limiter.try_acquire(Some(ip1)).unwrap();
+
+        assert_eq!(
+            limiter.metrics().snapshot(),
+            ConnectionStats {
+                active: 3,
+                accepted: 4,
+                rejected_global: 1,
+                rejected_per_ip: 1,
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -1,4 +1,31 @@
+mod client;
 mod frame;
-pub use frame::FrameCoder;
-
+mod grpc;
+mod http;
+mod limits;
+mod multiplex;
+mod pipeline;
+mod quic;
+mod resp;
+mod shutdown;
+mod stream;
 mod tls;
+#[cfg(unix)]
+mod unix;
+
+pub use client::{KvClient, KvClientBuilder, UNIX_SCHEME};
+pub use frame::{
+    read_frame, read_frame_with, FrameCoder, FrameConfig, COMPRESSION_LIMIT, MAX_FRAME_SIZE,
+};
+pub use grpc::{GrpcConnection, GrpcService};
+pub use http::{http_router, serve_http};
+pub use limits::{ConnectionLimiter, ConnectionMetrics, ConnectionPermit, ConnectionStats};
+pub use multiplex::{StreamKind, YamuxCtrl, YamuxServerConnection, YamuxStream, MAX_STREAMS};
+pub use pipeline::PipelinedClient;
+pub use quic::{quic_endpoint, QuicClient, QuicServerConnection};
+pub use resp::{parse_request, RespServerStream, RespValue, RespVersion};
+pub use shutdown::{DrainReport, GracefulShutdown, ServerRunner, DRAIN_TIMEOUT};
+pub use stream::{ProstClientStream, ProstServerStream, COMMANDS, MAX_IN_FLIGHT};
+pub use tls::{client_identity, load_certs, load_key, TlsClientConnector, TlsServerAcceptor};
+#[cfg(unix)]
+pub use unix::bind_unix;
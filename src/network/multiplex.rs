@@ -0,0 +1,560 @@
+use std::{
+    collections::VecDeque,
+    future::{poll_fn, Future},
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{stream, Stream};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    sync::{broadcast::error::RecvError, mpsc, oneshot},
+};
+use tokio_util::{
+    compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt},
+    sync::CancellationToken,
+    task::TaskTracker,
+};
+use tracing::{debug, warn};
+use yamux::{Config, Connection, ConnectionError, Mode};
+
+use super::stream::{recv_frame, send_frame};
+use crate::{
+    Change, CommandResponse, KvError, ProstClientStream, Service, Storage, Value, WatchRequest,
+};
+
+/// 一个连接上默认最多同时打开的逻辑 stream 数
+pub const MAX_STREAMS: usize = 256;
+
+type OpenResponder = oneshot::Sender<Result<yamux::Stream, ConnectionError>>;
+
+/// 逻辑 stream 的用途，客户端打开 stream 后先写一个字节告诉服务器
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamKind {
+    /// 普通的请求和响应，协议和 TCP 连接上的一样（`ProstServerStream`）
+    Command = 1,
+    /// 订阅一个 table 的变化：客户端发送 WatchRequest，服务器回复一个 CommandResponse，
+    /// 成功后持续发送 Change，直到任意一方关闭 stream
+    Watch = 2,
+}
+
+impl StreamKind {
+    fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            1 => Some(Self::Command),
+            2 => Some(Self::Watch),
+            _ => None,
+        }
+    }
+}
+
+/// 在一个 TCP / TLS 连接上复用多个逻辑 stream（yamux 协议）的客户端
+///
+/// 每个逻辑 stream 都是独立的 AsyncRead + AsyncWrite，有自己的流控窗口，
+/// 一个 stream 上的慢请求或长期存在的订阅不会阻塞其他 stream。
+/// 服务器端的所有逻辑 stream 共用这个连接的上下文，在任意一个 stream 上 Auth 之后，
+/// 其他 stream（包括 Watch）也是登录状态。
+#[derive(Clone)]
+pub struct YamuxCtrl {
+    tx: mpsc::Sender<OpenResponder>,
+}
+
+/// yamux 上的一个逻辑 stream
+///
+/// 客户端的 stream 持有 YamuxCtrl 的一份引用，只要还有 stream 在用，底层连接就不会关闭。
+pub struct YamuxStream {
+    inner: Compat<yamux::Stream>,
+    _ctrl: Option<YamuxCtrl>,
+}
+
+impl YamuxCtrl {
+    /// 在 stream 上建立客户端的 yamux 连接
+    ///
+    /// 所有 YamuxCtrl 和打开的 YamuxStream 都 drop 之后，连接会被关闭。
+    pub fn new_client<S>(stream: S, config: Option<Config>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let conn = Connection::new(stream.compat(), config.unwrap_or_default(), Mode::Client);
+        let (tx, rx) = mpsc::channel(32);
+        tokio::spawn(drive_client(conn, rx));
+        Self { tx }
+    }
+
+    /// 打开一个新的逻辑 stream，用法和 TCP 连接上的 ProstClientStream 一样
+    pub async fn open_client_stream(&self) -> Result<ProstClientStream<YamuxStream>, KvError> {
+        let stream = self.open_stream(StreamKind::Command).await?;
+        Ok(ProstClientStream::new(stream))
+    }
+
+    /// 打开一个 Watch stream，订阅 table 的变化
+    ///
+    /// 服务器拒绝订阅（如没有读权限）时返回错误。返回的 Stream 结束表示服务器关闭了订阅
+    /// （订阅者落后太多或者服务器退出），需要重新订阅。
+    pub async fn watch(
+        &self,
+        table: impl Into<String>,
+    ) -> Result<impl Stream<Item = Result<Change, KvError>> + Send + 'static, KvError> {
+        let mut stream = self.open_stream(StreamKind::Watch).await?;
+        let req = WatchRequest {
+            table: table.into(),
+        };
+        send_frame(&mut stream, &req).await?;
+        let res: CommandResponse = recv_frame(&mut stream).await?.ok_or_else(closed)?;
+        res.into_result()?;
+
+        Ok(stream::unfold(Some(stream), |stream| async move {
+            let mut stream = stream?;
+            match recv_frame::<_, Change>(&mut stream).await {
+                Ok(Some(change)) => Some((Ok(change), Some(stream))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        }))
+    }
+
+    /// 打开一个新的逻辑 stream，先写入它的用途
+    async fn open_stream(&self, kind: StreamKind) -> Result<YamuxStream, KvError> {
+        let (sender, receiver) = oneshot::channel();
+        let closed = || KvError::Internal("Yamux connection is closed".into());
+        self.tx.send(sender).await.map_err(|_| closed())?;
+        let stream = receiver.await.map_err(|_| closed())?.map_err(yamux_error)?;
+        let mut stream = YamuxStream {
+            inner: stream.compat(),
+            _ctrl: Some(self.clone()),
+        };
+        stream.write_u8(kind as u8).await?;
+        Ok(stream)
+    }
+}
+
+/// 驱动客户端的 yamux 连接：处理打开 stream 的请求，同时推动底层的读写
+async fn drive_client<T>(mut conn: Connection<T>, mut rx: mpsc::Receiver<OpenResponder>)
+where
+    T: futures::AsyncRead + futures::AsyncWrite + Unpin,
+{
+    let mut waiting: VecDeque<OpenResponder> = VecDeque::new();
+    let res = poll_fn(|cx| {
+        let mut handles_dropped = false;
+        loop {
+            match rx.poll_recv(cx) {
+                Poll::Ready(Some(responder)) => waiting.push_back(responder),
+                Poll::Ready(None) => {
+                    handles_dropped = true;
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        while !waiting.is_empty() {
+            match conn.poll_new_outbound(cx) {
+                Poll::Ready(res) => {
+                    let responder = waiting.pop_front().expect("waiting is not empty");
+                    let _ = responder.send(res);
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        // 客户端不接受服务器打开的 stream，但需要一直 poll 来推动连接的读写
+        loop {
+            match conn.poll_next_inbound(cx) {
+                Poll::Ready(Some(Ok(stream))) => drop(stream),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                Poll::Ready(None) => return Poll::Ready(Ok(false)),
+                Poll::Pending => break,
+            }
+        }
+
+        if handles_dropped {
+            Poll::Ready(Ok(true))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+
+    match res {
+        // 所有 YamuxCtrl 和 stream 都已经 drop，正常关闭连接
+        Ok(true) => {
+            if let Err(e) = poll_fn(|cx| conn.poll_close(cx)).await {
+                debug!("Failed to close yamux connection: {}", e);
+            }
+        }
+        Ok(false) => debug!("Yamux connection closed by server"),
+        Err(e) => warn!("Yamux connection error: {}", e),
+    }
+}
+
+/// 服务器端的一个 yamux 连接
+///
+/// 所有逻辑 stream 共用同一个连接上下文（mTLS 身份、Auth 登录的状态），和 QUIC 连接上的 stream 一样。
+/// Command stream 交给调用者处理（通常是 `ProstServerStream`），Watch stream 在这里处理。
+pub struct YamuxServerConnection<S> {
+    stream: S,
+    config: Config,
+    shutdown: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl<S> YamuxServerConnection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    pub fn new(stream: S) -> Self {
+        let mut config = Config::default();
+        config.set_max_num_streams(MAX_STREAMS);
+        Self {
+            stream,
+            config,
+            shutdown: CancellationToken::new(),
+            tracker: TaskTracker::new(),
+        }
+    }
+
+    /// 同时打开的逻辑 stream 数，超过时新的 stream 会被拒绝
+    ///
+    /// 每个 stream 有自己的流控窗口，从 256KB 开始按需增长，所有 stream 的窗口加起来不超过 1GB，
+    /// 所以 n 不能超过 4096。
+    pub fn max_streams(mut self, n: usize) -> Self {
+        self.config.set_max_num_streams(n);
+        self
+    }
+
+    /// token 被取消后不再接受新的 stream，已有的 stream 处理完后关闭连接
+    ///
+    /// Command stream 需要调用者自己处理 token（如 `ProstServerStream::shutdown`），Watch stream 会直接结束。
+    pub fn shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// 每个逻辑 stream 的处理同时也被 tracker 跟踪，和 `QuicServerConnection::tracker` 一样
+    pub fn tracker(mut self, tracker: TaskTracker) -> Self {
+        self.tracker = tracker;
+        self
+    }
+
+    /// 接受客户端打开的逻辑 stream 并处理，直到连接关闭
+    ///
+    /// 每个 Command stream 都会调用 f，传入的 Service 和 conn 共享连接上下文。
+    pub async fn process<Store, F, Fut>(self, conn: Service<Store>, f: F) -> Result<(), KvError>
+    where
+        Store: Storage + Send + Sync + 'static,
+        F: Fn(YamuxStream, Service<Store>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), KvError>> + Send + 'static,
+    {
+        let mut yamux = Connection::new(self.stream.compat(), self.config, Mode::Server);
+        let f = Arc::new(f);
+        let streams = TaskTracker::new();
+        loop {
+            let stream = tokio::select! {
+                stream = poll_fn(|cx| yamux.poll_next_inbound(cx)) => stream,
+                _ = self.shutdown.cancelled() => break,
+            };
+            let stream = match stream {
+                Some(Ok(stream)) => stream,
+                Some(Err(e)) => return Err(yamux_error(e)),
+                None => return Ok(()),
+            };
+            debug!("Accepted logical stream {}", stream.id());
+            let stream = YamuxStream {
+                inner: stream.compat(),
+                _ctrl: None,
+            };
+            let conn = conn.same_connection();
+            let f = f.clone();
+            let shutdown = self.shutdown.clone();
+            streams.spawn(self.tracker.track_future(async move {
+                if let Err(e) = serve_logical_stream(stream, conn, f.as_ref(), shutdown).await {
+                    warn!("Failed to serve logical stream: {}", e);
+                }
+            }));
+        }
+
+        // 不再接受新的 stream，但要继续驱动连接，已有的 stream 才能处理完
+        streams.close();
+        let done = streams.wait();
+        tokio::pin!(done);
+        loop {
+            tokio::select! {
+                stream = poll_fn(|cx| yamux.poll_next_inbound(cx)) => match stream {
+                    Some(Ok(stream)) => drop(stream),
+                    Some(Err(e)) => return Err(yamux_error(e)),
+                    None => return Ok(()),
+                },
+                _ = &mut done => break,
+            }
+        }
+        poll_fn(|cx| yamux.poll_close(cx))
+            .await
+            .map_err(yamux_error)
+    }
+}
+
+async fn serve_logical_stream<Store, F, Fut>(
+    mut stream: YamuxStream,
+    conn: Service<Store>,
+    f: &F,
+    shutdown: CancellationToken,
+) -> Result<(), KvError>
+where
+    Store: Storage + Send + Sync + 'static,
+    F: Fn(YamuxStream, Service<Store>) -> Fut,
+    Fut: Future<Output = Result<(), KvError>>,
+{
+    let kind = stream.read_u8().await?;
+    match StreamKind::from_u8(kind) {
+        Some(StreamKind::Command) => f(stream, conn).await,
+        Some(StreamKind::Watch) => serve_watch(stream, conn, shutdown).await,
+        None => Err(KvError::InvalidCommand(format!(
+            "Unknown stream kind: {}",
+            kind
+        ))),
+    }
+}
+
+/// 只转发订阅的 table 的变化；订阅者落后太多、服务器退出或者客户端关闭 stream 时结束
+async fn serve_watch<Store>(
+    mut stream: YamuxStream,
+    conn: Service<Store>,
+    shutdown: CancellationToken,
+) -> Result<(), KvError>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    let Some(req) = recv_frame::<_, WatchRequest>(&mut stream).await? else {
+        return Ok(());
+    };
+    let mut rx = match conn.watch(&req.table) {
+        Ok(rx) => rx,
+        Err(e) => {
+            send_frame(&mut stream, &CommandResponse::from(e)).await?;
+            stream.shutdown().await?;
+            return Ok(());
+        }
+    };
+    send_frame(&mut stream, &CommandResponse::from(Value::default())).await?;
+
+    let mut buf = [0u8; 1];
+    loop {
+        let change = tokio::select! {
+            change = rx.recv() => change,
+            // 客户端订阅之后不会再写数据，读到任何结果都表示它关闭了 stream
+            _ = stream.read(&mut buf) => break,
+            _ = shutdown.cancelled() => break,
+        };
+        match change {
+            Ok(change) if change.table == req.table => send_frame(&mut stream, &change).await?,
+            Ok(_) => continue,
+            Err(RecvError::Lagged(n)) => {
+                warn!(
+                    "Watcher of table {} lagged behind by {} changes",
+                    req.table, n
+                );
+                break;
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn yamux_error(e: ConnectionError) -> KvError {
+    match e {
+        ConnectionError::Io(e) => KvError::IoError(e),
+        e => KvError::Internal(e.to_string()),
+    }
+}
+
+fn closed() -> KvError {
+    KvError::IoError(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Server closed the stream",
+    ))
+}
+
+impl AsyncRead for YamuxStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for YamuxStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use bytes::Bytes;
+    use futures::{future::join_all, StreamExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{
+        service::assert_res_ok, Acl, AclRule, CommandRequest, MemTable, Permissions,
+        ProstServerStream, ServiceInner, TlsClientConnector, TlsServerAcceptor,
+    };
+
+    /// 在 stream 上处理一个 yamux 连接，conn 是这个连接的上下文
+    async fn serve<S>(stream: S, conn: Service) -> Result<(), KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        YamuxServerConnection::new(stream)
+            .process(conn, |stream, svc| {
+                ProstServerStream::new(stream).process(svc)
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn yamux_should_multiplex_many_streams_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = Service::new(MemTable::new());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve(stream, service.new_connection()).await
+        });
+
+        let ctrl = YamuxCtrl::new_client(TcpStream::connect(addr).await.unwrap(), None);
+
+        // 一个一直不用的 stream，模拟长期存在的订阅，不应该影响其他 stream
+        let _idle = ctrl.open_client_stream().await.unwrap();
+
+        let tasks = (0..20).map(|i| {
+            let ctrl = ctrl.clone();
+            async move {
+                let mut client = ctrl.open_client_stream().await.unwrap();
+                let key = format!("k{}", i);
+                let cmd = CommandRequest::new_hset("t1", &key, (i as i64).into());
+                let res = client.execute(&cmd).await.unwrap();
+                assert_res_ok(res, &[Value::default()], &[]);
+                let res = client
+                    .execute(&CommandRequest::new_hget("t1", &key))
+                    .await
+                    .unwrap();
+                assert_res_ok(res, &[(i as i64).into()], &[]);
+            }
+        });
+        join_all(tasks).await;
+    }
+
+    #[tokio::test]
+    async fn yamux_should_transfer_values_larger_than_window_over_tls() {
+        let acceptor =
+            TlsServerAcceptor::new("fixtures/server.cert", "fixtures/server.key", None).unwrap();
+        let connector =
+            TlsClientConnector::new("kvserver.acme.inc", None, Some("fixtures/ca.cert")).unwrap();
+        let (client, server) = tokio::io::duplex(4096);
+        let service = Service::new(MemTable::new());
+        tokio::spawn(async move {
+            let stream = acceptor.accept(server).await.unwrap();
+            serve(stream, service.new_connection()).await
+        });
+
+        let stream = connector.connect(client).await.unwrap();
+        let ctrl = YamuxCtrl::new_client(stream, None);
+        let mut big = ctrl.open_client_stream().await.unwrap();
+        let mut small = ctrl.open_client_stream().await.unwrap();
+
+        // 1MB 远大于 yamux 默认 256KB 的窗口，需要流控窗口更新才能发完
+        let value: Value = Bytes::from(vec![42u8; 1024 * 1024]).into();
+        let cmd = CommandRequest::new_hset("t1", "big", value.clone());
+        let small_cmd = CommandRequest::new_hset("t1", "small", "v".into());
+        let (r1, r2) = tokio::join!(big.execute(&cmd), small.execute(&small_cmd));
+        assert_res_ok(r1.unwrap(), &[Value::default()], &[]);
+        assert_res_ok(r2.unwrap(), &[Value::default()], &[]);
+
+        let res = small
+            .execute(&CommandRequest::new_hget("t1", "big"))
+            .await
+            .unwrap();
+        assert_res_ok(res, &[value], &[]);
+    }
+
+    #[tokio::test]
+    async fn logical_streams_should_share_identity_and_watch_changes() {
+        let rules = vec![AclRule::new("alice", "t1", Permissions::ALL)];
+        let service: Service = ServiceInner::new(MemTable::new())
+            .acl(Arc::new(Acl::new(rules)))
+            .into();
+        let (client, server) = tokio::io::duplex(4096);
+        let conn = service.new_connection();
+        conn.context().set_peer_identity("alice");
+        tokio::spawn(serve(server, conn));
+
+        let ctrl = YamuxCtrl::new_client(client, None);
+        let mut changes = Box::pin(ctrl.watch("t1").await.unwrap());
+        // 没有权限的 table 不能订阅
+        assert!(matches!(
+            ctrl.watch("t2").await.err(),
+            Some(KvError::PermissionDenied(..))
+        ));
+
+        let mut client = ctrl.open_client_stream().await.unwrap();
+        for cmd in [
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_hdel("t1", "k1"),
+        ] {
+            assert_eq!(client.execute(&cmd).await.unwrap().status, 200);
+        }
+
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!(
+            (change.key.as_str(), change.value, change.deleted),
+            ("k1", Some("v1".into()), false)
+        );
+        let change = changes.next().await.unwrap().unwrap();
+        assert!(change.deleted);
+    }
+
+    #[tokio::test]
+    async fn yamux_connection_should_close_after_shutdown() {
+        let service = Service::new(MemTable::new());
+        let (client, server) = tokio::io::duplex(4096);
+        let token = CancellationToken::new();
+        let server = tokio::spawn(
+            YamuxServerConnection::new(server)
+                .shutdown(token.clone())
+                .process(service.new_connection(), |stream, svc| {
+                    ProstServerStream::new(stream).process(svc)
+                }),
+        );
+
+        let ctrl = YamuxCtrl::new_client(client, None);
+        let mut changes = Box::pin(ctrl.watch("t1").await.unwrap());
+        token.cancel();
+        assert!(changes.next().await.is_none());
+        tokio::time::timeout(Duration::from_secs(1), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+}
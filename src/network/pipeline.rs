@@ -0,0 +1,382 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures::Stream;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+};
+use tracing::debug;
+
+use super::stream::{recv_frame, send_frame, MAX_IN_FLIGHT};
+use crate::{CommandRequest, CommandResponse, KvError};
+
+type ResponseSender = oneshot::Sender<Result<CommandResponse, KvError>>;
+type ChunkSender = mpsc::Sender<Result<CommandResponse, KvError>>;
+
+/// 分块响应的队列长度，stream 消费得慢时读任务会等待队列有空位
+const CHUNK_BUFFER: usize = 16;
+
+/// 支持 pipelining 的客户端连接
+///
+/// 每个请求都会分配一个 id，可以在同一个连接上同时发出多个请求，响应按 id 匹配，
+/// 不要求服务器按顺序返回。clone 出来的 PipelinedClient 共享同一个连接。
+#[derive(Clone)]
+pub struct PipelinedClient {
+    inner: Arc<PipelineInner>,
+}
+
+struct PipelineInner {
+    tx: mpsc::Sender<CommandRequest>,
+    pending: Arc<Mutex<Pending>>,
+    next_id: AtomicU64,
+}
+
+/// 等待响应的请求；连接断开后 closed 为 true，不再接受新请求
+#[derive(Default)]
+struct Pending {
+    closed: bool,
+    waiting: HashMap<u64, Waiter>,
+}
+
+/// 普通请求只等一个响应；分块的请求会收到多个响应，直到 more 为 false
+enum Waiter {
+    Once(ResponseSender),
+    Chunks(ChunkSender),
+}
+
+impl Pending {
+    /// 把响应交给对应 id 的请求
+    ///
+    /// 分块的响应不在这里发送：返回它的 sender，由调用者在锁外等待队列有空位。
+    fn deliver(&mut self, res: CommandResponse) -> Option<(ChunkSender, CommandResponse)> {
+        let id = res.id;
+        match self.waiting.remove(&id) {
+            Some(Waiter::Once(sender)) => {
+                let _ = sender.send(Ok(res));
+                None
+            }
+            Some(Waiter::Chunks(sender)) => {
+                if res.more {
+                    self.waiting.insert(id, Waiter::Chunks(sender.clone()));
+                }
+                Some((sender, res))
+            }
+            None => {
+                debug!("Dropping response for unknown request {}", id);
+                None
+            }
+        }
+    }
+
+    /// 连接断开，通知所有还在等待的请求
+    fn close(&mut self, reason: &str) {
+        self.closed = true;
+        for (_, waiter) in self.waiting.drain() {
+            let err = Err(connection_closed(reason));
+            match waiter {
+                Waiter::Once(sender) => {
+                    let _ = sender.send(err);
+                }
+                Waiter::Chunks(sender) => {
+                    // 队列可能已满，在后台等待空位，不阻塞关闭
+                    tokio::spawn(async move {
+                        let _ = sender.send(err).await;
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// 请求的 future 或者响应的 stream 被 drop（比如超时）时，把它从等待列表中移除
+struct PendingGuard {
+    pending: Arc<Mutex<Pending>>,
+    id: u64,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        lock(&self.pending).waiting.remove(&self.id);
+    }
+}
+
+impl PipelinedClient {
+    /// 接管 stream，启动读写两个后台任务
+    pub fn new<S>(stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let (tx, mut rx) = mpsc::channel::<CommandRequest>(MAX_IN_FLIGHT);
+        let pending = Arc::new(Mutex::new(Pending::default()));
+
+        let write_pending = pending.clone();
+        tokio::spawn(async move {
+            while let Some(cmd) = rx.recv().await {
+                if let Err(e) = send_frame(&mut writer, &cmd).await {
+                    lock(&write_pending).close(&e.to_string());
+                    return;
+                }
+            }
+            // 所有 PipelinedClient 都已经 drop，关闭写端让服务器知道不会再有新请求
+            let _ = writer.shutdown().await;
+        });
+
+        let read_pending = pending.clone();
+        // 只用弱引用回复 ping，所有 PipelinedClient 都 drop 之后写端仍然能关闭
+        let pong_tx = tx.downgrade();
+        tokio::spawn(async move {
+            let reason = loop {
+                match recv_frame::<_, CommandResponse>(&mut reader).await {
+                    Ok(Some(res)) if res.ping => {
+                        if let Some(tx) = pong_tx.upgrade() {
+                            let _ = tx.send(CommandRequest::new_pong()).await;
+                        }
+                    }
+                    Ok(Some(res)) => {
+                        let id = res.id;
+                        let chunk = lock(&read_pending).deliver(res);
+                        if let Some((sender, res)) = chunk {
+                            // stream 已经 drop，后续的响应都不需要了
+                            if sender.send(Ok(res)).await.is_err() {
+                                lock(&read_pending).waiting.remove(&id);
+                            }
+                        }
+                    }
+                    Ok(None) => break "Server closed the connection".to_string(),
+                    Err(e) => break e.to_string(),
+                }
+            };
+            lock(&read_pending).close(&reason);
+        });
+
+        Self {
+            inner: Arc::new(PipelineInner {
+                tx,
+                pending,
+                next_id: AtomicU64::new(1),
+            }),
+        }
+    }
+
+    /// 发送一个命令，等待对应 id 的响应
+    ///
+    /// 可以并发调用，请求会在同一个连接上同时发出。cmd 原有的 id 会被覆盖。
+    /// 分块返回的命令（带 chunk_size 的 Hgetall）只能拿到第一个响应，请用 `execute_stream`。
+    pub async fn execute(&self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        let (sender, receiver) = oneshot::channel();
+        let _guard = self.send(cmd, Waiter::Once(sender)).await?;
+        receiver
+            .await
+            .unwrap_or_else(|_| Err(connection_closed("Connection is closed")))
+    }
+
+    /// 发送一个命令，返回它的所有响应，最后一个响应的 more 为 false
+    ///
+    /// 响应放在一个有界队列里：stream 消费得慢时，读任务会停下来等待，
+    /// 服务器的发送也随之被 TCP 流控挡住，同一连接上其他请求的响应也要等待。
+    /// stream 被 drop 之后，后续到达的响应会被丢弃。
+    pub async fn execute_stream(
+        &self,
+        cmd: CommandRequest,
+    ) -> Result<impl Stream<Item = Result<CommandResponse, KvError>> + Send + 'static, KvError>
+    {
+        let (sender, receiver) = mpsc::channel(CHUNK_BUFFER);
+        let guard = self.send(cmd, Waiter::Chunks(sender)).await?;
+        Ok(futures::stream::unfold(
+            (receiver, guard),
+            |(mut receiver, guard)| async move {
+                let res = receiver.recv().await?;
+                Some((res, (receiver, guard)))
+            },
+        ))
+    }
+
+    /// 给 cmd 分配 id，登记等待响应的 waiter 后发出去
+    async fn send(&self, mut cmd: CommandRequest, waiter: Waiter) -> Result<PendingGuard, KvError> {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        cmd.id = id;
+
+        {
+            let mut pending = lock(&self.inner.pending);
+            if pending.closed {
+                return Err(connection_closed("Connection is closed"));
+            }
+            pending.waiting.insert(id, waiter);
+        }
+        let guard = PendingGuard {
+            pending: self.inner.pending.clone(),
+            id,
+        };
+
+        if self.inner.tx.send(cmd).await.is_err() {
+            return Err(connection_closed("Connection is closed"));
+        }
+        Ok(guard)
+    }
+
+    /// 连接是否已经断开
+    pub fn is_closed(&self) -> bool {
+        lock(&self.inner.pending).closed
+    }
+}
+
+fn lock(pending: &Mutex<Pending>) -> std::sync::MutexGuard<'_, Pending> {
+    pending.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn connection_closed(reason: &str) -> KvError {
+    KvError::IoError(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        reason.to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future::join_all, StreamExt};
+
+    use super::*;
+    use crate::{service::assert_res_ok, MemTable, ProstServerStream, Service, Value};
+
+    #[tokio::test]
+    async fn pipelined_client_should_match_out_of_order_responses() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let client = PipelinedClient::new(client);
+
+        // 先收两个请求，再倒序返回，value 里带上请求中的 key
+        tokio::spawn(async move {
+            let mut cmds = vec![];
+            for _ in 0..2 {
+                let cmd: CommandRequest = recv_frame(&mut server).await.unwrap().unwrap();
+                cmds.push(cmd);
+            }
+            for cmd in cmds.into_iter().rev() {
+                let key = match cmd.request_data {
+                    Some(crate::command_request::RequestData::Hget(p)) => p.key,
+                    _ => unreachable!(),
+                };
+                let mut res: CommandResponse = Value::from(key).into();
+                res.id = cmd.id;
+                send_frame(&mut server, &res).await.unwrap();
+            }
+        });
+
+        let (r1, r2) = tokio::join!(
+            client.execute(CommandRequest::new_hget("t1", "k1")),
+            client.execute(CommandRequest::new_hget("t1", "k2"))
+        );
+        assert_res_ok(r1.unwrap(), &["k1".into()], &[]);
+        assert_res_ok(r2.unwrap(), &["k2".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn pipelined_client_should_keep_many_requests_in_flight() {
+        let (client, server) = tokio::io::duplex(4096);
+        let service = Service::new(MemTable::new());
+        tokio::spawn(ProstServerStream::new(server).process(service));
+        let client = PipelinedClient::new(client);
+
+        let requests = (0..200).map(|i| {
+            let client = client.clone();
+            async move {
+                let key = format!("k{}", i);
+                let res = client
+                    .execute(CommandRequest::new_hset("t1", &key, (i as i64).into()))
+                    .await
+                    .unwrap();
+                assert_res_ok(res, &[Value::default()], &[]);
+                let res = client
+                    .execute(CommandRequest::new_hget("t1", &key))
+                    .await
+                    .unwrap();
+                assert_res_ok(res, &[(i as i64).into()], &[]);
+            }
+        });
+        join_all(requests).await;
+    }
+
+    #[tokio::test]
+    async fn pipelined_client_should_stream_chunked_responses() {
+        let (client, server) = tokio::io::duplex(4096);
+        let service = Service::new(MemTable::new());
+        for i in 0..10 {
+            service.execute(CommandRequest::new_hset("t1", format!("k{}", i), i.into()));
+        }
+        tokio::spawn(ProstServerStream::new(server).process(service));
+        let client = PipelinedClient::new(client);
+
+        let chunks = client
+            .execute_stream(CommandRequest::new_hgetall_chunked("t1", 3))
+            .await
+            .unwrap();
+        // 同一个连接上的其他请求不受影响
+        let (chunks, res) = tokio::join!(
+            chunks.collect::<Vec<_>>(),
+            client.execute(CommandRequest::new_hget("t1", "k1"))
+        );
+        assert_res_ok(res.unwrap(), &[1.into()], &[]);
+
+        let chunks: Vec<_> = chunks.into_iter().map(Result::unwrap).collect();
+        let sizes: Vec<_> = chunks.iter().map(|r| (r.pairs.len(), r.more)).collect();
+        assert_eq!(
+            sizes,
+            [(3, true), (3, true), (3, true), (1, true), (0, false)]
+        );
+        assert!(lock(&client.inner.pending).waiting.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pipelined_client_should_wait_for_slow_stream_consumers() {
+        let (client, server) = tokio::io::duplex(4096);
+        let service = Service::new(MemTable::new());
+        let total = CHUNK_BUFFER * 4;
+        for i in 0..total {
+            service.execute(CommandRequest::new_hset(
+                "t1",
+                format!("k{}", i),
+                (i as i64).into(),
+            ));
+        }
+        tokio::spawn(ProstServerStream::new(server).process(service));
+        let client = PipelinedClient::new(client);
+
+        let mut chunks = Box::pin(
+            client
+                .execute_stream(CommandRequest::new_hgetall_chunked("t1", 1))
+                .await
+                .unwrap(),
+        );
+        // 先不消费，让队列填满，读任务等待空位而不是丢弃响应
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let mut pairs = 0;
+        while let Some(res) = chunks.next().await {
+            pairs += res.unwrap().pairs.len();
+        }
+        assert_eq!(pairs, total);
+    }
+
+    #[tokio::test]
+    async fn pipelined_client_should_fail_pending_requests_when_closed() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let client = PipelinedClient::new(client);
+
+        tokio::spawn(async move {
+            let _: Option<CommandRequest> = recv_frame(&mut server).await.unwrap();
+            // 不响应，直接断开
+        });
+
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await;
+        assert!(matches!(res, Err(KvError::IoError(_))));
+        assert!(client.is_closed());
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await;
+        assert!(matches!(res, Err(KvError::IoError(_))));
+    }
+}
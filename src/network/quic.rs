@@ -0,0 +1,427 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use futures::{stream, Stream};
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    ClientConfig, Connection, ConnectionError, Endpoint, RecvStream, SendStream, TransportConfig,
+    VarInt,
+};
+use tokio_rustls::rustls::{self, pki_types::CertificateDer};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::BoxError;
+use tracing::{debug, warn};
+
+use super::{
+    stream::{check_server_hello, handshake, recv_frame_with, send_frame_with, SharedService},
+    tls::{cert_identity, tls_error},
+};
+use crate::{
+    command_request::RequestData, CommandRequest, CommandResponse, FrameConfig, KvError,
+    ResponseStream, ServerHello, TlsClientConnector, TlsServerAcceptor, MAX_IN_FLIGHT,
+};
+
+/// 在 addr 上监听 QUIC，复用 TLS 的证书和 kv ALPN
+///
+/// TlsServerAcceptor reload（或 watch 发现证书修改）之后，endpoint 的配置也会被替换，
+/// 新连接使用新的证书，已有的连接不受影响。
+pub fn quic_endpoint(addr: SocketAddr, acceptor: &TlsServerAcceptor) -> Result<Endpoint, KvError> {
+    let endpoint = Endpoint::server(server_config(acceptor.server_config())?, addr)?;
+
+    let mut configs = acceptor.subscribe();
+    let reloaded = endpoint.clone();
+    tokio::spawn(async move {
+        while configs.changed().await.is_ok() {
+            let config = configs.borrow_and_update().clone();
+            match server_config(config) {
+                Ok(config) => reloaded.set_server_config(Some(config)),
+                Err(e) => warn!("Failed to reload QUIC certificate: {}", e),
+            }
+        }
+    });
+    Ok(endpoint)
+}
+
+fn server_config(crypto: Arc<rustls::ServerConfig>) -> Result<quinn::ServerConfig, KvError> {
+    let crypto = QuicServerConfig::try_from(crypto).map_err(tls_error)?;
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(Arc::new(transport_config()));
+    Ok(config)
+}
+
+/// 一个 QUIC 连接上同时打开的 stream 数和 TCP 上同时处理的请求数一样
+fn transport_config() -> TransportConfig {
+    let mut transport = TransportConfig::default();
+    transport.max_concurrent_bidi_streams(VarInt::from_u32(MAX_IN_FLIGHT as u32));
+    transport.max_concurrent_uni_streams(VarInt::from_u32(0));
+    transport
+}
+
+/// 处理服务器端的一个 QUIC 连接
+///
+/// 每个请求使用一个双向 stream：客户端写入一个 CommandRequest frame 后关闭发送端，
+/// 服务器写回所有响应（分块的 Hgetall 有多个）后关闭发送端。
+/// stream 之间互不阻塞，也就没有 TCP 上的队头阻塞。Hello 和其他请求一样走一个 stream，
+/// 只用来检查协议版本和交换服务器的信息，frame 始终使用默认配置，不切换压缩算法。
+/// 协议版本不兼容时，写回 505 之后关闭整个连接。
+pub struct QuicServerConnection {
+    conn: Connection,
+    config: FrameConfig,
+    shutdown: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl QuicServerConnection {
+    pub fn new(conn: Connection) -> Self {
+        Self {
+            conn,
+            config: FrameConfig::default(),
+            shutdown: CancellationToken::new(),
+            tracker: TaskTracker::new(),
+        }
+    }
+
+    /// 每个 stream 的处理同时也被 tracker 跟踪，和 `ProstServerStream::tracker` 一样，
+    /// 传入 `GracefulShutdown::requests()` 后，连接被强制关闭时退出也会等这些请求结束
+    pub fn tracker(mut self, tracker: TaskTracker) -> Self {
+        self.tracker = tracker;
+        self
+    }
+
+    /// token 被取消后不再接受新的 stream，已有的 stream 处理完后关闭连接
+    pub fn shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// 单个 frame 的最大长度，以及压缩的 frame 解压后的最大长度
+    pub fn max_frame_size(mut self, max_frame_size: usize, max_decompressed_size: usize) -> Self {
+        self.config.max_frame_size = max_frame_size;
+        self.config.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    /// mTLS 时客户端证书中的身份，和 `client_identity` 的规则一样
+    pub fn peer_identity(&self) -> Option<String> {
+        let certs = self
+            .conn
+            .peer_identity()?
+            .downcast::<Vec<CertificateDer<'static>>>()
+            .ok()?;
+        cert_identity(certs.first()?)
+    }
+
+    /// 接受客户端打开的 stream 并处理，直到连接关闭
+    ///
+    /// 所有 stream 共用同一个 svc，也就共用同一个连接上下文（身份、Auth 登录的状态）。
+    pub async fn process<Svc>(self, svc: Svc) -> Result<(), KvError>
+    where
+        Svc: tower::Service<CommandRequest> + Send + 'static,
+        Svc::Response: Into<ResponseStream> + Send,
+        Svc::Future: Send + 'static,
+        Svc::Error: Into<BoxError> + Send,
+    {
+        let svc = SharedService::new(svc);
+        let streams = TaskTracker::new();
+        loop {
+            let res = tokio::select! {
+                res = self.conn.accept_bi() => res,
+                _ = self.shutdown.cancelled() => break,
+            };
+            let (send, recv) = match res {
+                Ok(streams) => streams,
+                Err(ConnectionError::ApplicationClosed(_) | ConnectionError::LocallyClosed) => {
+                    return Ok(())
+                }
+                Err(e) => return Err(KvError::IoError(e.into())),
+            };
+            let svc = svc.clone();
+            let conn = self.conn.clone();
+            let config = self.config;
+            streams.spawn(self.tracker.track_future(async move {
+                if let Err(e) = serve_stream(send, recv, conn, svc, config).await {
+                    warn!("Failed to serve QUIC stream: {}", e);
+                }
+            }));
+        }
+        streams.close();
+        streams.wait().await;
+        self.conn
+            .close(VarInt::from_u32(0), b"server shutting down");
+        Ok(())
+    }
+}
+
+async fn serve_stream<Svc>(
+    mut send: SendStream,
+    mut recv: RecvStream,
+    conn: Connection,
+    svc: SharedService<Svc>,
+    config: FrameConfig,
+) -> Result<(), KvError>
+where
+    Svc: tower::Service<CommandRequest>,
+    Svc::Response: Into<ResponseStream>,
+    Svc::Error: Into<BoxError>,
+{
+    let Some(cmd) = recv_frame_with::<_, CommandRequest>(&mut recv, &config).await? else {
+        return Ok(());
+    };
+    debug!("Got a new command over QUIC: {:?}", cmd.redacted());
+    let id = cmd.id;
+    let (responses, rejected) = match &cmd.request_data {
+        Some(RequestData::Hello(hello)) => {
+            let (res, compression) = handshake(hello, &[config.compression]);
+            (res.into(), compression.is_none())
+        }
+        _ => (svc.call(cmd).await, false),
+    };
+    for mut res in responses {
+        res.id = id;
+        send_frame_with(&mut send, &res, &config).await?;
+    }
+    send.finish().map_err(io::Error::from)?;
+    // 等客户端确认收到，之后关闭连接时才不会丢掉还没发出去的响应
+    let _ = send.stopped().await;
+    if rejected {
+        conn.close(VarInt::from_u32(1), b"unsupported protocol");
+    }
+    Ok(())
+}
+
+/// QUIC 客户端，每个请求打开一个新的 stream
+///
+/// 请求之间互不阻塞；网络切换（如 Wi-Fi 换到蜂窝网络）时 QUIC 会迁移连接，不需要重连。
+#[derive(Clone)]
+pub struct QuicClient {
+    // 保留 endpoint，它被 drop 之后连接也会关闭
+    _endpoint: Endpoint,
+    conn: Connection,
+    config: FrameConfig,
+    server: Option<ServerHello>,
+}
+
+impl QuicClient {
+    /// 用 connector 的证书配置和 kv ALPN 连接服务器，并用 Hello 检查协议版本
+    ///
+    /// 版本不兼容时返回 `KvError::UnsupportedProtocol`；不认识 Hello 的老服务器返回的错误会被忽略。
+    pub async fn connect(
+        addr: SocketAddr,
+        connector: &TlsClientConnector,
+    ) -> Result<Self, KvError> {
+        let crypto = QuicClientConfig::try_from(connector.config.clone()).map_err(tls_error)?;
+        let mut config = ClientConfig::new(Arc::new(crypto));
+        config.transport_config(Arc::new(transport_config()));
+
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let mut endpoint = Endpoint::client(local)?;
+        endpoint.set_default_client_config(config);
+        let conn = endpoint
+            .connect(addr, &connector.domain)
+            .map_err(|e| KvError::Internal(e.to_string()))?
+            .await
+            .map_err(|e| KvError::IoError(e.into()))?;
+        let mut client = Self {
+            _endpoint: endpoint,
+            conn,
+            config: FrameConfig::default(),
+            server: None,
+        };
+
+        let hello = CommandRequest::new_hello("kv-quic", &[client.config.compression]);
+        let res = client.execute(&hello).await?;
+        check_server_hello(&res)?;
+        client.server = res.hello;
+        Ok(client)
+    }
+
+    /// 握手时服务器返回的信息，老服务器没有
+    pub fn server_hello(&self) -> Option<&ServerHello> {
+        self.server.as_ref()
+    }
+
+    /// 发送一个命令，返回第一个响应
+    pub async fn execute(&self, cmd: &CommandRequest) -> Result<CommandResponse, KvError> {
+        let mut recv = self.send(cmd).await?;
+        recv_frame_with(&mut recv, &self.config)
+            .await?
+            .ok_or_else(closed)
+    }
+
+    /// 发送一个命令，返回它的所有响应（如分块的 Hgetall），最后一个响应的 more 为 false
+    pub async fn execute_stream(
+        &self,
+        cmd: &CommandRequest,
+    ) -> Result<impl Stream<Item = Result<CommandResponse, KvError>> + Send + 'static, KvError>
+    {
+        let recv = self.send(cmd).await?;
+        let config = self.config;
+        Ok(stream::unfold(Some(recv), move |recv| async move {
+            let mut recv = recv?;
+            match recv_frame_with::<_, CommandResponse>(&mut recv, &config).await {
+                Ok(Some(res)) if res.more => Some((Ok(res), Some(recv))),
+                Ok(Some(res)) => Some((Ok(res), None)),
+                Ok(None) => Some((Err(closed()), None)),
+                Err(e) => Some((Err(e), None)),
+            }
+        }))
+    }
+
+    /// 打开一个 stream 写入请求，返回读取响应的一端
+    async fn send(&self, cmd: &CommandRequest) -> Result<RecvStream, KvError> {
+        let (mut send, recv) = self
+            .conn
+            .open_bi()
+            .await
+            .map_err(|e| KvError::IoError(e.into()))?;
+        send_frame_with(&mut send, cmd, &self.config).await?;
+        send.finish().map_err(io::Error::from)?;
+        Ok(recv)
+    }
+}
+
+fn closed() -> KvError {
+    KvError::IoError(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Server closed the stream",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::{
+        service::assert_res_ok, MemTable, Service, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    };
+
+    async fn start_server(acceptor: TlsServerAcceptor) -> SocketAddr {
+        let endpoint = quic_endpoint("127.0.0.1:0".parse().unwrap(), &acceptor).unwrap();
+        let addr = endpoint.local_addr().unwrap();
+        let service = Service::new(MemTable::new());
+        tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                let conn = incoming.await.unwrap();
+                let svc = service.new_connection();
+                let conn = QuicServerConnection::new(conn);
+                if let Some(identity) = conn.peer_identity() {
+                    svc.context().set_peer_identity(identity);
+                }
+                tokio::spawn(conn.process(svc));
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn quic_should_map_requests_to_streams() {
+        let acceptor =
+            TlsServerAcceptor::new("fixtures/server.cert", "fixtures/server.key", None).unwrap();
+        let addr = start_server(acceptor).await;
+        let connector =
+            TlsClientConnector::new("kvserver.acme.inc", None, Some("fixtures/ca.cert")).unwrap();
+        let client = QuicClient::connect(addr, &connector).await.unwrap();
+
+        // 同时发出多个请求，每个请求走自己的 stream
+        let requests = (0..10).map(|i| {
+            let client = client.clone();
+            async move {
+                let cmd = CommandRequest::new_hset("t1", format!("k{}", i), i.into());
+                client.execute(&cmd).await.unwrap()
+            }
+        });
+        for res in futures::future::join_all(requests).await {
+            assert_res_ok(res, &[Default::default()], &[]);
+        }
+
+        let res = client
+            .execute(&CommandRequest::new_hget("t1", "k3"))
+            .await
+            .unwrap();
+        assert_res_ok(res, &[3.into()], &[]);
+
+        let cmd = CommandRequest::new_hgetall_chunked("t1", 4);
+        let responses: Vec<_> = client.execute_stream(&cmd).await.unwrap().collect().await;
+        let sizes: Vec<_> = responses
+            .into_iter()
+            .map(|res| res.unwrap().pairs.len())
+            .collect();
+        assert_eq!(sizes, [4, 4, 2, 0]);
+    }
+
+    #[tokio::test]
+    async fn quic_client_should_say_hello() {
+        let acceptor =
+            TlsServerAcceptor::new("fixtures/server.cert", "fixtures/server.key", None).unwrap();
+        let addr = start_server(acceptor).await;
+        let connector =
+            TlsClientConnector::new("kvserver.acme.inc", None, Some("fixtures/ca.cert")).unwrap();
+        let client = QuicClient::connect(addr, &connector).await.unwrap();
+
+        let hello = client.server_hello().unwrap();
+        assert_eq!(hello.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(hello.min_protocol_version, MIN_PROTOCOL_VERSION);
+        assert!(hello.commands.iter().any(|c| c == "hgetall"));
+    }
+
+    #[tokio::test]
+    async fn quic_endpoint_should_use_reloaded_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = dir.path().join("server.cert");
+        let key = dir.path().join("server.key");
+        std::fs::copy("fixtures/server.cert", &cert).unwrap();
+        std::fs::copy("fixtures/server.key", &key).unwrap();
+        let acceptor =
+            TlsServerAcceptor::new(cert.to_str().unwrap(), key.to_str().unwrap(), None).unwrap();
+        let addr = start_server(acceptor.clone()).await;
+        let connector =
+            TlsClientConnector::new("kvserver.acme.inc", None, Some("fixtures/ca.cert")).unwrap();
+
+        let server_identity = |client: QuicClient| {
+            let certs = client.conn.peer_identity().unwrap();
+            let certs = certs.downcast::<Vec<CertificateDer<'static>>>().unwrap();
+            cert_identity(&certs[0])
+        };
+        let client = QuicClient::connect(addr, &connector).await.unwrap();
+        assert_eq!(server_identity(client).as_deref(), Some("Acme KV server"));
+
+        std::fs::copy("fixtures/rotated.cert", &cert).unwrap();
+        std::fs::copy("fixtures/rotated.key", &key).unwrap();
+        acceptor.reload().unwrap();
+        for _ in 0..100 {
+            let client = QuicClient::connect(addr, &connector).await.unwrap();
+            if server_identity(client).as_deref() == Some("Acme KV server rotated") {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("QUIC endpoint did not use the reloaded certificate");
+    }
+
+    #[tokio::test]
+    async fn quic_should_close_connection_after_rejected_hello() {
+        let acceptor =
+            TlsServerAcceptor::new("fixtures/server.cert", "fixtures/server.key", None).unwrap();
+        let addr = start_server(acceptor).await;
+        let connector =
+            TlsClientConnector::new("kvserver.acme.inc", None, Some("fixtures/ca.cert")).unwrap();
+        let client = QuicClient::connect(addr, &connector).await.unwrap();
+
+        let mut hello = CommandRequest::new_hello("kv-test", &[]);
+        if let Some(RequestData::Hello(hello)) = hello.request_data.as_mut() {
+            hello.protocol_version = PROTOCOL_VERSION + 1;
+        }
+        let res = client.execute(&hello).await.unwrap();
+        assert_eq!(res.status, 505);
+
+        let e = client.conn.closed().await;
+        assert!(matches!(e, ConnectionError::ApplicationClosed(_)), "{}", e);
+        assert!(client
+            .execute(&CommandRequest::new_hget("t1", "k1"))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,923 @@
+use std::{sync::Arc, time::Duration};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time,
+};
+use tokio_util::sync::CancellationToken;
+use tower::BoxError;
+use tracing::debug;
+
+use super::stream::SharedService;
+use crate::{
+    command_request::RequestData, value, Auth, CommandRequest, CommandResponse, ConnectionMetrics,
+    Hexist, Hget, Hgetall, Hmdel, Hmget, Hmset, KvError, Kvpair, ResponseStream, Value,
+    MAX_FRAME_SIZE,
+};
+
+/// 数组最多嵌套的层数，防止恶意请求把栈打爆
+const MAX_DEPTH: usize = 32;
+
+/// RESP 协议的一个值，包括 RESP3 新增的类型
+///
+/// 用 RESP2 编码时，RESP3 的类型会降级：Null 编码成 `$-1`，Boolean 编码成整数，
+/// Double 编码成 bulk string，Map 编码成 key value 交替的数组。
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Array(Vec<RespValue>),
+    Null,
+    Boolean(bool),
+    Double(f64),
+    Map(Vec<(RespValue, RespValue)>),
+}
+
+/// 连接使用的 RESP 版本，客户端可以用 `HELLO 3` 切换到 RESP3
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RespVersion {
+    Resp2,
+    Resp3,
+}
+
+impl RespValue {
+    /// 从 buf 开头解析一个完整的值，返回值和它占用的字节数；数据不完整时返回 None
+    pub fn parse(buf: &[u8]) -> Result<Option<(Self, usize)>, KvError> {
+        Self::parse_with_limit(buf, MAX_FRAME_SIZE)
+    }
+
+    /// 和 `parse` 一样，但 bulk string 的长度不能超过 max_bulk_size
+    pub fn parse_with_limit(
+        buf: &[u8],
+        max_bulk_size: usize,
+    ) -> Result<Option<(Self, usize)>, KvError> {
+        parse_at(buf, 0, 0, max_bulk_size)
+    }
+
+    /// 按 version 把值编码进 buf
+    pub fn encode(&self, buf: &mut BytesMut, version: RespVersion) {
+        match self {
+            RespValue::Simple(s) => put_line(buf, b'+', s.as_bytes()),
+            RespValue::Error(s) => put_line(buf, b'-', s.as_bytes()),
+            RespValue::Integer(i) => put_line(buf, b':', i.to_string().as_bytes()),
+            RespValue::Bulk(b) => {
+                put_line(buf, b'$', b.len().to_string().as_bytes());
+                buf.put_slice(b);
+                buf.put_slice(b"\r\n");
+            }
+            RespValue::Array(items) => {
+                put_line(buf, b'*', items.len().to_string().as_bytes());
+                for item in items {
+                    item.encode(buf, version);
+                }
+            }
+            RespValue::Null => match version {
+                RespVersion::Resp2 => buf.put_slice(b"$-1\r\n"),
+                RespVersion::Resp3 => buf.put_slice(b"_\r\n"),
+            },
+            RespValue::Boolean(b) => match version {
+                RespVersion::Resp2 => RespValue::Integer(*b as i64).encode(buf, version),
+                RespVersion::Resp3 => put_line(buf, b'#', if *b { b"t" } else { b"f" }),
+            },
+            RespValue::Double(f) => match version {
+                RespVersion::Resp2 => put_bulk(buf, f.to_string().as_bytes()),
+                RespVersion::Resp3 => put_line(buf, b',', f.to_string().as_bytes()),
+            },
+            RespValue::Map(pairs) => {
+                match version {
+                    RespVersion::Resp2 => {
+                        put_line(buf, b'*', (pairs.len() * 2).to_string().as_bytes())
+                    }
+                    RespVersion::Resp3 => put_line(buf, b'%', pairs.len().to_string().as_bytes()),
+                }
+                for (k, v) in pairs {
+                    k.encode(buf, version);
+                    v.encode(buf, version);
+                }
+            }
+        }
+    }
+
+    fn bulk(data: impl Into<Bytes>) -> Self {
+        RespValue::Bulk(data.into())
+    }
+
+    fn ok() -> Self {
+        RespValue::Simple("OK".into())
+    }
+
+    fn err(msg: impl std::fmt::Display) -> Self {
+        RespValue::Error(format!("ERR {}", msg))
+    }
+}
+
+/// kv 的 Value 在 RESP 里都是 bulk string，空值是 Null
+impl From<Value> for RespValue {
+    fn from(v: Value) -> Self {
+        match v.value {
+            None => RespValue::Null,
+            Some(value::Value::String(s)) => RespValue::bulk(s),
+            Some(value::Value::Binary(b)) => RespValue::Bulk(b),
+            Some(value::Value::Integer(i)) => RespValue::bulk(i.to_string()),
+            Some(value::Value::Float(f)) => RespValue::bulk(f.to_string()),
+            Some(value::Value::Bool(b)) => RespValue::bulk(b.to_string()),
+        }
+    }
+}
+
+/// RESP 里的参数都是字节串，合法的 UTF-8 存成字符串，否则存成二进制
+fn to_value(arg: Bytes) -> Value {
+    match String::from_utf8(arg.to_vec()) {
+        Ok(s) => s.into(),
+        Err(_) => Value {
+            value: Some(value::Value::Binary(arg)),
+        },
+    }
+}
+
+/// 表名和 key 在 kv 里是字符串，不是合法 UTF-8 的直接拒绝，不能像 value 那样存成二进制
+fn text(arg: &Bytes) -> Result<String, RespValue> {
+    String::from_utf8(arg.to_vec()).map_err(|_| RespValue::err("table and key must be valid UTF-8"))
+}
+
+fn put_line(buf: &mut BytesMut, prefix: u8, data: &[u8]) {
+    buf.put_u8(prefix);
+    buf.put_slice(data);
+    buf.put_slice(b"\r\n");
+}
+
+fn put_bulk(buf: &mut BytesMut, data: &[u8]) {
+    RespValue::Bulk(Bytes::copy_from_slice(data)).encode(buf, RespVersion::Resp2);
+}
+
+fn protocol_error(msg: &str) -> KvError {
+    KvError::InvalidCommand(format!("Protocol error: {}", msg))
+}
+
+fn parse_at(
+    buf: &[u8],
+    pos: usize,
+    depth: usize,
+    max_bulk_size: usize,
+) -> Result<Option<(RespValue, usize)>, KvError> {
+    if depth > MAX_DEPTH {
+        return Err(protocol_error("too many nested arrays"));
+    }
+    let Some(&prefix) = buf.get(pos) else {
+        return Ok(None);
+    };
+    let Some(end) = find_crlf(buf, pos + 1) else {
+        return Ok(None);
+    };
+    let line = &buf[pos + 1..end];
+    let next = end + 2;
+
+    let value = match prefix {
+        b'+' => RespValue::Simple(String::from_utf8_lossy(line).into()),
+        b'-' => RespValue::Error(String::from_utf8_lossy(line).into()),
+        b':' => RespValue::Integer(parse_int(line)?),
+        b'_' => RespValue::Null,
+        b'#' => match line {
+            b"t" => RespValue::Boolean(true),
+            b"f" => RespValue::Boolean(false),
+            _ => return Err(protocol_error("invalid boolean")),
+        },
+        b',' => RespValue::Double(
+            std::str::from_utf8(line)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| protocol_error("invalid double"))?,
+        ),
+        b'$' => {
+            let len = parse_int(line)?;
+            if len == -1 {
+                return Ok(Some((RespValue::Null, next)));
+            }
+            let len = usize::try_from(len).map_err(|_| protocol_error("invalid bulk length"))?;
+            if len > max_bulk_size {
+                return Err(protocol_error("bulk string is too large"));
+            }
+            if buf.len() < next + len + 2 {
+                return Ok(None);
+            }
+            if &buf[next + len..next + len + 2] != b"\r\n" {
+                return Err(protocol_error("bulk string is not terminated by CRLF"));
+            }
+            let data = Bytes::copy_from_slice(&buf[next..next + len]);
+            return Ok(Some((RespValue::Bulk(data), next + len + 2)));
+        }
+        b'*' | b'%' => {
+            let n = parse_int(line)?;
+            if n == -1 {
+                return Ok(Some((RespValue::Null, next)));
+            }
+            let n = usize::try_from(n).map_err(|_| protocol_error("invalid multibulk length"))?;
+            let count = match prefix {
+                b'%' => n
+                    .checked_mul(2)
+                    .ok_or_else(|| protocol_error("invalid map length"))?,
+                _ => n,
+            };
+            // 长度来自客户端，不能直接按它预留内存
+            let mut items = Vec::with_capacity(count.min(1024));
+            let mut pos = next;
+            for _ in 0..count {
+                match parse_at(buf, pos, depth + 1, max_bulk_size)? {
+                    Some((item, p)) => {
+                        items.push(item);
+                        pos = p;
+                    }
+                    None => return Ok(None),
+                }
+            }
+            let value = if prefix == b'%' {
+                let mut pairs = Vec::with_capacity(n);
+                let mut iter = items.into_iter();
+                while let (Some(k), Some(v)) = (iter.next(), iter.next()) {
+                    pairs.push((k, v));
+                }
+                RespValue::Map(pairs)
+            } else {
+                RespValue::Array(items)
+            };
+            return Ok(Some((value, pos)));
+        }
+        _ => return Err(protocol_error("unknown type")),
+    };
+    Ok(Some((value, next)))
+}
+
+fn find_crlf(buf: &[u8], from: usize) -> Option<usize> {
+    buf.get(from..)?
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .map(|i| from + i)
+}
+
+fn parse_int(line: &[u8]) -> Result<i64, KvError> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| protocol_error("invalid integer"))
+}
+
+/// 解析客户端发来的一个命令：RESP 数组，或者 telnet 里直接输入的 inline 命令
+///
+/// 返回命令的参数和占用的字节数，数据不完整时返回 None。参数的长度不能超过 max_size。
+pub fn parse_request(buf: &[u8], max_size: usize) -> Result<Option<(Vec<Bytes>, usize)>, KvError> {
+    match buf.first() {
+        None => Ok(None),
+        Some(b'*') => match RespValue::parse_with_limit(buf, max_size)? {
+            Some((RespValue::Array(items), used)) => {
+                let args = items
+                    .into_iter()
+                    .map(|item| match item {
+                        RespValue::Bulk(b) => Ok(b),
+                        _ => Err(protocol_error("expected bulk string")),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Some((args, used)))
+            }
+            Some((_, used)) => Ok(Some((vec![], used))),
+            None => Ok(None),
+        },
+        Some(_) => {
+            let Some(pos) = buf.iter().position(|b| *b == b'\n') else {
+                return Ok(None);
+            };
+            let line = String::from_utf8_lossy(&buf[..pos]);
+            let args = line
+                .split_whitespace()
+                .map(|s| Bytes::copy_from_slice(s.as_bytes()))
+                .collect();
+            Ok(Some((args, pos + 1)))
+        }
+    }
+}
+
+/// kv 的响应如何转换成 RESP 的回复
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reply {
+    /// HGET：单个值，404 返回 Null
+    Value,
+    /// HMGET：值的数组
+    Values,
+    /// HGETALL：RESP3 用 Map，RESP2 用 key value 交替的数组
+    Pairs,
+    Keys,
+    Vals,
+    Len,
+    /// HSET：新增的 key 的数量，即旧值为空的个数
+    Added,
+    /// HDEL：删掉的 key 的数量，即旧值不为空的个数
+    Removed,
+    /// HEXISTS
+    Exists,
+    Ok,
+}
+
+/// 会翻译成 CommandRequest 的命令，第一个参数都是 table
+const HASH_COMMANDS: &[&str] = &[
+    "HGET", "HSET", "HMSET", "HGETALL", "HKEYS", "HVALS", "HLEN", "HMGET", "HDEL", "HEXISTS",
+];
+
+/// 把 RESP 命令翻译成 CommandRequest，同时记下响应该怎么转换
+fn translate(name: &str, mut args: Vec<Bytes>) -> Result<(CommandRequest, Reply), RespValue> {
+    if !HASH_COMMANDS.contains(&name) {
+        return Err(RespValue::err(format!(
+            "unknown command '{}'",
+            name.to_ascii_lowercase()
+        )));
+    }
+    let wrong_args = || {
+        RespValue::err(format!(
+            "wrong number of arguments for '{}' command",
+            name.to_ascii_lowercase()
+        ))
+    };
+    let table = if args.is_empty() {
+        return Err(wrong_args());
+    } else {
+        text(&args.remove(0))?
+    };
+
+    let (data, reply) = match name {
+        "HGET" => match args.as_slice() {
+            [key] => (
+                RequestData::Hget(Hget {
+                    table,
+                    key: text(key)?,
+                }),
+                Reply::Value,
+            ),
+            _ => return Err(wrong_args()),
+        },
+        "HSET" | "HMSET" => {
+            if args.is_empty() || !args.len().is_multiple_of(2) {
+                return Err(wrong_args());
+            }
+            let mut pairs = Vec::with_capacity(args.len() / 2);
+            let mut iter = args.into_iter();
+            while let (Some(k), Some(v)) = (iter.next(), iter.next()) {
+                pairs.push(Kvpair::new(text(&k)?, to_value(v)));
+            }
+            let reply = if name == "HSET" {
+                Reply::Added
+            } else {
+                Reply::Ok
+            };
+            (RequestData::Hmset(Hmset { table, pairs }), reply)
+        }
+        "HGETALL" | "HKEYS" | "HVALS" | "HLEN" => {
+            if !args.is_empty() {
+                return Err(wrong_args());
+            }
+            let reply = match name {
+                "HGETALL" => Reply::Pairs,
+                "HKEYS" => Reply::Keys,
+                "HVALS" => Reply::Vals,
+                _ => Reply::Len,
+            };
+            let data = RequestData::Hgetall(Hgetall {
+                table,
+                chunk_size: 0,
+            });
+            (data, reply)
+        }
+        "HMGET" | "HDEL" => {
+            if args.is_empty() {
+                return Err(wrong_args());
+            }
+            let keys = args.iter().map(text).collect::<Result<_, _>>()?;
+            match name {
+                "HMGET" => (RequestData::Hmget(Hmget { table, keys }), Reply::Values),
+                _ => (RequestData::Hmdel(Hmdel { table, keys }), Reply::Removed),
+            }
+        }
+        "HEXISTS" => match args.as_slice() {
+            [key] => (
+                RequestData::Hexist(Hexist {
+                    table,
+                    key: text(key)?,
+                }),
+                Reply::Exists,
+            ),
+            _ => return Err(wrong_args()),
+        },
+        _ => unreachable!("checked against HASH_COMMANDS"),
+    };
+    let cmd = CommandRequest {
+        request_data: Some(data),
+        ..Default::default()
+    };
+    Ok((cmd, reply))
+}
+
+/// 把 CommandResponse 转换成 RESP 回复
+fn to_reply(res: CommandResponse, reply: Reply) -> RespValue {
+    if !(200..300).contains(&res.status) {
+        return match res.status {
+            404 if reply == Reply::Value => RespValue::Null,
+            401 => RespValue::Error(format!("NOAUTH {}", res.message)),
+            403 => RespValue::Error(format!("NOPERM {}", res.message)),
+            _ => RespValue::err(res.message),
+        };
+    }
+
+    let is_empty = |v: &Value| v.value.is_none();
+    match reply {
+        Reply::Value => res.values.into_iter().next().unwrap_or_default().into(),
+        Reply::Values => RespValue::Array(res.values.into_iter().map(Into::into).collect()),
+        Reply::Pairs => RespValue::Map(
+            res.pairs
+                .into_iter()
+                .map(|p| (RespValue::bulk(p.key), p.value.unwrap_or_default().into()))
+                .collect(),
+        ),
+        Reply::Keys => RespValue::Array(
+            res.pairs
+                .into_iter()
+                .map(|p| RespValue::bulk(p.key))
+                .collect(),
+        ),
+        Reply::Vals => RespValue::Array(
+            res.pairs
+                .into_iter()
+                .map(|p| p.value.unwrap_or_default().into())
+                .collect(),
+        ),
+        Reply::Len => RespValue::Integer(res.pairs.len() as i64),
+        Reply::Added => RespValue::Integer(res.values.iter().filter(|v| is_empty(v)).count() as _),
+        Reply::Removed => {
+            RespValue::Integer(res.values.iter().filter(|v| !is_empty(v)).count() as _)
+        }
+        Reply::Exists => {
+            let exists = res
+                .values
+                .into_iter()
+                .next()
+                .and_then(|v| v.try_into().ok());
+            RespValue::Integer(exists.unwrap_or(false) as i64)
+        }
+        Reply::Ok => RespValue::ok(),
+    }
+}
+
+/// 处理一个 RESP 连接，让 redis-cli、redis-benchmark 以及各种 Redis 客户端可以访问 kv
+///
+/// 支持 RESP2 和 RESP3（通过 `HELLO 3` 切换），Hash 相关的命令（HGET、HSET、HMSET、HGETALL、
+/// HMGET、HDEL、HEXISTS、HKEYS、HVALS、HLEN）会翻译成 CommandRequest 交给 svc 执行，
+/// 另外支持 AUTH、HELLO、PING、ECHO、SELECT、QUIT 这些客户端常用的连接命令。
+pub struct RespServerStream<S> {
+    inner: S,
+    version: RespVersion,
+    max_request_size: usize,
+    shutdown: CancellationToken,
+    idle_timeout: Option<Duration>,
+    metrics: Option<Arc<ConnectionMetrics>>,
+}
+
+impl<S> RespServerStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: S) -> Self {
+        Self {
+            inner: stream,
+            version: RespVersion::Resp2,
+            max_request_size: MAX_FRAME_SIZE,
+            shutdown: CancellationToken::new(),
+            idle_timeout: None,
+            metrics: None,
+        }
+    }
+
+    /// 单个请求的最大字节数，超过时返回错误并断开连接
+    pub fn max_request_size(mut self, size: usize) -> Self {
+        self.max_request_size = size;
+        self
+    }
+
+    /// token 被取消后，回复完已经收到的命令就关闭连接
+    pub fn shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// 超过 timeout 没有收到命令时关闭连接，和 Redis 的 timeout 配置一样
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// 因为空闲关闭连接时，记录到 metrics 里
+    pub fn metrics(mut self, metrics: Arc<ConnectionMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// 处理这个连接上的所有命令，直到客户端断开或者发送 QUIT
+    ///
+    /// 命令按顺序执行、按顺序回复，客户端可以 pipeline 多个命令。
+    /// svc 通常是 `Service::layered` 返回的带超时、限流的 service，和 ProstServerStream 一样。
+    pub async fn process<Svc>(mut self, svc: Svc) -> Result<(), KvError>
+    where
+        Svc: tower::Service<CommandRequest> + Send + 'static,
+        Svc::Response: Into<ResponseStream> + Send,
+        Svc::Future: Send + 'static,
+        Svc::Error: Into<BoxError> + Send,
+    {
+        let svc = SharedService::new(svc);
+        let mut buf = BytesMut::new();
+        let mut out = BytesMut::new();
+        loop {
+            loop {
+                let (args, used) = match parse_request(&buf, self.max_request_size) {
+                    Ok(Some(req)) => req,
+                    Ok(None) => break,
+                    Err(e) => {
+                        // 和 Redis 一样，协议错误时回复错误后断开连接
+                        RespValue::err(&e).encode(&mut out, self.version);
+                        self.inner.write_all(&out).await?;
+                        return Err(e);
+                    }
+                };
+                buf.advance(used);
+                if args.is_empty() {
+                    continue;
+                }
+                let (reply, quit) = self.handle(&svc, args).await;
+                reply.encode(&mut out, self.version);
+                if quit {
+                    self.inner.write_all(&out).await?;
+                    return Ok(());
+                }
+            }
+
+            if !out.is_empty() {
+                self.inner.write_all(&out).await?;
+                self.inner.flush().await?;
+                out.clear();
+            }
+            if buf.len() > self.max_request_size {
+                let e = KvError::FrameError;
+                RespValue::err(&e).encode(&mut out, self.version);
+                self.inner.write_all(&out).await?;
+                return Err(e);
+            }
+            let idle = self.idle_timeout.unwrap_or_default();
+            let n = tokio::select! {
+                res = self.inner.read_buf(&mut buf) => res?,
+                _ = self.shutdown.cancelled() => return Ok(()),
+                _ = time::sleep(idle), if self.idle_timeout.is_some() => {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.record_idle_closed();
+                    }
+                    return Err(KvError::Timeout(format!("Client has been idle for {:?}", idle)));
+                }
+            };
+            if n == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// 执行一个命令，返回回复以及是否要关闭连接
+    async fn handle<Svc>(&mut self, svc: &SharedService<Svc>, args: Vec<Bytes>) -> (RespValue, bool)
+    where
+        Svc: tower::Service<CommandRequest>,
+        Svc::Response: Into<ResponseStream>,
+        Svc::Error: Into<BoxError>,
+    {
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        let mut args = args;
+        args.remove(0);
+        debug!("Got RESP command {} with {} args", name, args.len());
+
+        let reply = match name.as_str() {
+            "PING" => match args.as_slice() {
+                [] => RespValue::Simple("PONG".into()),
+                [msg] => RespValue::Bulk(msg.clone()),
+                _ => RespValue::err("wrong number of arguments for 'ping' command"),
+            },
+            "ECHO" => match args.as_slice() {
+                [msg] => RespValue::Bulk(msg.clone()),
+                _ => RespValue::err("wrong number of arguments for 'echo' command"),
+            },
+            "QUIT" => return (RespValue::ok(), true),
+            "SELECT" => match args.as_slice() {
+                [db] if db.as_ref() == b"0" => RespValue::ok(),
+                _ => RespValue::err("DB index is out of range"),
+            },
+            // redis-cli 启动时会发 COMMAND DOCS，客户端库可能会发 CLIENT SETNAME
+            "COMMAND" => RespValue::Array(vec![]),
+            "CLIENT" => RespValue::ok(),
+            "AUTH" => match args.as_slice() {
+                [password] => self.auth(svc, b"default", password).await,
+                [username, password] => self.auth(svc, username, password).await,
+                _ => RespValue::err("wrong number of arguments for 'auth' command"),
+            },
+            "HELLO" => self.hello(svc, args).await,
+            _ => match translate(&name, args) {
+                Ok((cmd, reply)) => to_reply(execute(svc, cmd).await, reply),
+                Err(e) => e,
+            },
+        };
+        (reply, false)
+    }
+
+    async fn auth<Svc>(
+        &self,
+        svc: &SharedService<Svc>,
+        username: &[u8],
+        password: &[u8],
+    ) -> RespValue
+    where
+        Svc: tower::Service<CommandRequest>,
+        Svc::Response: Into<ResponseStream>,
+        Svc::Error: Into<BoxError>,
+    {
+        let cmd = CommandRequest {
+            request_data: Some(RequestData::Auth(Auth {
+                username: String::from_utf8_lossy(username).into(),
+                password: String::from_utf8_lossy(password).into(),
+            })),
+            ..Default::default()
+        };
+        let res = execute(svc, cmd).await;
+        match res.status {
+            200..=299 => RespValue::ok(),
+            401 => RespValue::Error(
+                "WRONGPASS invalid username-password pair or user is disabled.".into(),
+            ),
+            _ => RespValue::err(res.message),
+        }
+    }
+
+    /// `HELLO [protover [AUTH username password] [SETNAME clientname]]`
+    async fn hello<Svc>(&mut self, svc: &SharedService<Svc>, args: Vec<Bytes>) -> RespValue
+    where
+        Svc: tower::Service<CommandRequest>,
+        Svc::Response: Into<ResponseStream>,
+        Svc::Error: Into<BoxError>,
+    {
+        let mut args = args.into_iter();
+        let version = match args.next() {
+            None => self.version,
+            Some(v) => match v.as_ref() {
+                b"2" => RespVersion::Resp2,
+                b"3" => RespVersion::Resp3,
+                _ => return RespValue::Error("NOPROTO unsupported protocol version".into()),
+            },
+        };
+        while let Some(opt) = args.next() {
+            match opt.to_ascii_uppercase().as_slice() {
+                b"AUTH" => {
+                    let (Some(username), Some(password)) = (args.next(), args.next()) else {
+                        return RespValue::err("Syntax error in HELLO option 'auth'");
+                    };
+                    let res = self.auth(svc, &username, &password).await;
+                    if matches!(res, RespValue::Error(_)) {
+                        return res;
+                    }
+                }
+                b"SETNAME" => {
+                    if args.next().is_none() {
+                        return RespValue::err("Syntax error in HELLO option 'setname'");
+                    }
+                }
+                _ => {
+                    return RespValue::err(format!(
+                        "Syntax error in HELLO option '{}'",
+                        String::from_utf8_lossy(&opt)
+                    ))
+                }
+            }
+        }
+
+        self.version = version;
+        let proto = match version {
+            RespVersion::Resp2 => 2,
+            RespVersion::Resp3 => 3,
+        };
+        let field = |k: &'static str, v: RespValue| (RespValue::bulk(k), v);
+        RespValue::Map(vec![
+            field("server", RespValue::bulk("kv")),
+            field("version", RespValue::bulk(env!("CARGO_PKG_VERSION"))),
+            field("proto", RespValue::Integer(proto)),
+            field("mode", RespValue::bulk("standalone")),
+            field("role", RespValue::bulk("master")),
+            field("modules", RespValue::Array(vec![])),
+        ])
+    }
+}
+
+/// 执行一个命令，RESP 的命令都只有一个响应
+async fn execute<Svc>(svc: &SharedService<Svc>, cmd: CommandRequest) -> CommandResponse
+where
+    Svc: tower::Service<CommandRequest>,
+    Svc::Response: Into<ResponseStream>,
+    Svc::Error: Into<BoxError>,
+{
+    svc.call(cmd)
+        .await
+        .next()
+        .unwrap_or_else(|| KvError::Internal("Got no response".into()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::*;
+    use crate::{LimitsConfig, MemTable, Service};
+
+    fn encode(v: &RespValue, version: RespVersion) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        v.encode(&mut buf, version);
+        buf.to_vec()
+    }
+
+    #[test]
+    fn resp_value_should_round_trip() {
+        let value = RespValue::Array(vec![
+            RespValue::Simple("OK".into()),
+            RespValue::Error("ERR oops".into()),
+            RespValue::Integer(-42),
+            RespValue::bulk("hello\r\nworld"),
+            RespValue::Null,
+            RespValue::Boolean(true),
+            RespValue::Double(1.5),
+            RespValue::Map(vec![(RespValue::bulk("k"), RespValue::bulk("v"))]),
+        ]);
+        let data = encode(&value, RespVersion::Resp3);
+        assert_eq!(RespValue::parse(&data).unwrap(), Some((value, data.len())));
+
+        // 数据不完整时等待更多数据
+        for i in 0..data.len() {
+            assert_eq!(RespValue::parse(&data[..i]).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn resp2_should_downgrade_resp3_types() {
+        let map = RespValue::Map(vec![(RespValue::bulk("k"), RespValue::Null)]);
+        assert_eq!(
+            encode(&map, RespVersion::Resp2),
+            b"*2\r\n$1\r\nk\r\n$-1\r\n"
+        );
+        assert_eq!(encode(&map, RespVersion::Resp3), b"%1\r\n$1\r\nk\r\n_\r\n");
+        assert_eq!(
+            encode(&RespValue::Boolean(true), RespVersion::Resp2),
+            b":1\r\n"
+        );
+    }
+
+    #[test]
+    fn parse_request_should_support_inline_and_reject_garbage() {
+        let parse = |buf: &[u8]| parse_request(buf, MAX_FRAME_SIZE);
+        let (args, used) = parse(b"HGET t1 k1\r\nPING").unwrap().unwrap();
+        assert_eq!(args, vec![Bytes::from("HGET"), "t1".into(), "k1".into()]);
+        assert_eq!(used, 12);
+
+        assert!(parse(b"*1\r\n:1\r\n").is_err());
+        assert!(parse(b"*1\r\n$-5\r\n").is_err());
+        assert!(parse(b"*1\r\n$3\r\nabcde\r\n").is_err());
+    }
+
+    #[test]
+    fn parse_request_should_use_the_configured_limit() {
+        let header = format!("*1\r\n${}\r\n", MAX_FRAME_SIZE + 1);
+        // 声明的长度超过限制时，不等数据到齐就拒绝
+        assert!(parse_request(header.as_bytes(), 16).is_err());
+        assert_eq!(
+            parse_request(header.as_bytes(), MAX_FRAME_SIZE * 2).unwrap(),
+            None
+        );
+    }
+
+    /// 发送 requests，读取 n 个回复
+    async fn run(requests: &[u8], n: usize) -> Vec<RespValue> {
+        let (mut client, server) = duplex(4096);
+        // 和服务器一样，命令经过超时、限流等 layer
+        let service = Service::new(MemTable::new()).layered(&LimitsConfig::default());
+        tokio::spawn(RespServerStream::new(server).process(service));
+        client.write_all(requests).await.unwrap();
+
+        let mut buf = BytesMut::new();
+        let mut replies = vec![];
+        while replies.len() < n {
+            match RespValue::parse(&buf).unwrap() {
+                Some((v, used)) => {
+                    buf.advance(used);
+                    replies.push(v);
+                }
+                None => {
+                    assert!(client.read_buf(&mut buf).await.unwrap() > 0);
+                }
+            }
+        }
+        replies
+    }
+
+    fn array(args: &[&str]) -> Vec<u8> {
+        let v = RespValue::Array(
+            args.iter()
+                .map(|a| RespValue::bulk(a.to_string()))
+                .collect(),
+        );
+        encode(&v, RespVersion::Resp2)
+    }
+
+    #[tokio::test]
+    async fn resp_server_should_handle_hash_commands() {
+        let mut requests = vec![];
+        for cmd in [
+            &["HSET", "t1", "k1", "v1", "k2", "v2"][..],
+            &["HSET", "t1", "k1", "v3"],
+            &["HGET", "t1", "k1"],
+            &["HGET", "t1", "nope"],
+            &["HMGET", "t1", "k1", "nope"],
+            &["HEXISTS", "t1", "k2"],
+            &["HLEN", "t1"],
+            &["HDEL", "t1", "k2", "nope"],
+            &["HGETALL", "t1"],
+            &["HGET", "t1"],
+            &["FLUSHALL"],
+        ] {
+            requests.extend(array(cmd));
+        }
+        requests.extend(b"PING\r\n");
+
+        let replies = run(&requests, 12).await;
+        assert_eq!(
+            replies,
+            vec![
+                RespValue::Integer(2),
+                RespValue::Integer(0),
+                RespValue::bulk("v3"),
+                RespValue::Null,
+                RespValue::Array(vec![RespValue::bulk("v3"), RespValue::Null]),
+                RespValue::Integer(1),
+                RespValue::Integer(2),
+                RespValue::Integer(1),
+                RespValue::Array(vec![RespValue::bulk("k1"), RespValue::bulk("v3")]),
+                RespValue::err("wrong number of arguments for 'hget' command"),
+                RespValue::err("unknown command 'flushall'"),
+                RespValue::Simple("PONG".into()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn resp_server_should_keep_binary_values_and_reject_binary_keys() {
+        let bulk = |b: &'static [u8]| RespValue::Bulk(Bytes::from_static(b));
+        let mut requests = vec![];
+        for cmd in [
+            vec![bulk(b"HSET"), bulk(b"t1"), bulk(b"k1"), bulk(b"\xff\x00v")],
+            vec![bulk(b"HGET"), bulk(b"t1"), bulk(b"k1")],
+            vec![bulk(b"HGET"), bulk(b"t1"), bulk(b"k\xff")],
+            vec![bulk(b"HSET"), bulk(b"\xfe"), bulk(b"k1"), bulk(b"v1")],
+        ] {
+            requests.extend(encode(&RespValue::Array(cmd), RespVersion::Resp2));
+        }
+
+        let replies = run(&requests, 4).await;
+        let invalid = RespValue::err("table and key must be valid UTF-8");
+        assert_eq!(
+            replies,
+            vec![
+                RespValue::Integer(1),
+                bulk(b"\xff\x00v"),
+                invalid.clone(),
+                invalid,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn resp_server_should_switch_to_resp3_with_hello() {
+        let mut requests = array(&["HELLO", "3"]);
+        requests.extend(array(&["HSET", "t1", "k1", "v1"]));
+        requests.extend(array(&["HGETALL", "t1"]));
+        requests.extend(array(&["HGET", "t1", "nope"]));
+        requests.extend(array(&["HELLO", "4"]));
+
+        let replies = run(&requests, 5).await;
+        match &replies[0] {
+            RespValue::Map(fields) => {
+                assert!(fields.contains(&(RespValue::bulk("proto"), RespValue::Integer(3))))
+            }
+            v => panic!("unexpected reply {:?}", v),
+        }
+        assert_eq!(
+            replies[2],
+            RespValue::Map(vec![(RespValue::bulk("k1"), RespValue::bulk("v1"))])
+        );
+        assert_eq!(replies[3], RespValue::Null);
+        assert!(matches!(&replies[4], RespValue::Error(e) if e.starts_with("NOPROTO")));
+    }
+}
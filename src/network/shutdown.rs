@@ -0,0 +1,359 @@
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+    time,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{info, warn};
+
+use crate::{KvError, Service, Storage};
+
+/// 默认等待连接处理完的时间
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 跟踪服务器上的连接，退出时先让连接把已经收到的请求处理完，超时后再强制关闭
+///
+/// 连接的 task 通过 `spawn` 启动。`shutdown` 之后 `token()` 会被取消，连接应该停止读取新的请求，
+/// 写完已有请求的响应后退出（`ProstServerStream::shutdown` 就是这样做的）。
+/// 自己管理连接的 server（如 tonic）通过 `spawn_server` 启动，连接里单独 spawn 的请求使用 `requests()`，
+/// `shutdown` 返回时它们都已经结束，之后不会再有写入。
+#[derive(Clone, Default)]
+pub struct GracefulShutdown {
+    /// 通知连接不再读取新的请求
+    token: CancellationToken,
+    /// 等待超时后取消，还没退出的连接会被直接关闭
+    force: CancellationToken,
+    tracker: TaskTracker,
+    servers: TaskTracker,
+    requests: TaskTracker,
+    drained: Arc<AtomicUsize>,
+    force_closed: Arc<AtomicUsize>,
+}
+
+/// 退出时连接的处理情况
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrainReport {
+    /// 在期限内处理完请求、正常关闭的连接数
+    pub drained: usize,
+    /// 超过期限被强制关闭的连接数
+    pub force_closed: usize,
+}
+
+impl GracefulShutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 开始退出时被取消，连接和 listener 用它来停止接受新的请求
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// 还没有退出的连接数
+    pub fn connections(&self) -> usize {
+        self.tracker.len()
+    }
+
+    /// 在一个被跟踪的 task 里处理连接，超过退出的期限时 fut 会被直接 drop
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let token = self.token.clone();
+        let force = self.force.clone();
+        let drained = self.drained.clone();
+        let force_closed = self.force_closed.clone();
+        self.tracker.spawn(async move {
+            tokio::select! {
+                _ = fut => {
+                    if token.is_cancelled() {
+                        drained.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                _ = force.cancelled() => {
+                    force_closed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        })
+    }
+
+    /// 在一个被跟踪的 task 里运行自己管理连接的 server（如 tonic），它应该在 `token()` 被取消后退出
+    ///
+    /// 和连接一样，超过退出的期限时 fut 会被直接 drop，但 server 不计入 DrainReport。
+    pub fn spawn_server<F>(&self, fut: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let force = self.force.clone();
+        self.servers.spawn(async move {
+            tokio::select! {
+                _ = fut => {}
+                _ = force.cancelled() => {}
+            }
+        })
+    }
+
+    /// 连接里单独 spawn 请求时使用的 tracker
+    ///
+    /// 连接被强制关闭时，已经开始的请求不会被取消，`shutdown` 会等它们结束后才返回。
+    pub fn requests(&self) -> TaskTracker {
+        self.requests.clone()
+    }
+
+    /// 通知所有连接退出，最多等待 timeout，之后强制关闭剩下的连接，最后等待还在运行的请求
+    pub async fn shutdown(&self, timeout: Duration) -> DrainReport {
+        self.token.cancel();
+        self.tracker.close();
+        self.servers.close();
+        let wait = async { tokio::join!(self.tracker.wait(), self.servers.wait()) };
+        if time::timeout(timeout, wait).await.is_err() {
+            warn!(
+                "{} connections are still active after {:?}, closing them",
+                self.tracker.len(),
+                timeout
+            );
+            self.force.cancel();
+            tokio::join!(self.tracker.wait(), self.servers.wait());
+        }
+        // 连接都已经退出，不会再有新的请求
+        self.requests.close();
+        if !self.requests.is_empty() {
+            info!(
+                "Waiting for {} requests of closed connections",
+                self.requests.len()
+            );
+        }
+        self.requests.wait().await;
+        DrainReport {
+            drained: self.drained.load(Ordering::Relaxed),
+            force_closed: self.force_closed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 运行服务器直到收到退出信号，然后等待连接处理完、flush 存储
+///
+/// ```no_run
+/// # async fn run() -> Result<(), kv::KvError> {
+/// use kv::{MemTable, ProstServerStream, ServerRunner, Service};
+///
+/// let listener = tokio::net::TcpListener::bind("127.0.0.1:9527").await?;
+/// let runner = ServerRunner::new(Service::new(MemTable::new()));
+/// let requests = runner.graceful_shutdown().requests();
+/// let report = runner
+///     .run(listener, tokio::signal::ctrl_c(), move |stream, svc, token| {
+///         ProstServerStream::new(stream)
+///             .shutdown(token)
+///             .tracker(requests.clone())
+///             .process(svc)
+///     })
+///     .await?;
+/// println!("{:?}", report);
+/// # Ok(())
+/// # }
+/// ```
+pub struct ServerRunner<Store> {
+    service: Service<Store>,
+    shutdown: GracefulShutdown,
+    drain_timeout: Duration,
+}
+
+impl<Store> ServerRunner<Store>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    pub fn new(service: Service<Store>) -> Self {
+        Self {
+            service,
+            shutdown: GracefulShutdown::new(),
+            drain_timeout: DRAIN_TIMEOUT,
+        }
+    }
+
+    /// 收到退出信号后最多等待多久，默认是 DRAIN_TIMEOUT
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// 其他 listener（RESP、Unix socket 等）上的连接也可以交给它跟踪，一起等待退出
+    pub fn graceful_shutdown(&self) -> &GracefulShutdown {
+        &self.shutdown
+    }
+
+    /// 在 listener 上接受连接，每个连接用一个新的 Service 交给 handler 处理，直到 signal 完成
+    ///
+    /// accept 出错（如文件描述符用完）时只打日志，等一会儿再继续。
+    /// 退出时不再接受新连接，等待已有的连接退出，最后 flush 存储。
+    pub async fn run<Sig, H, Fut>(
+        self,
+        listener: TcpListener,
+        signal: Sig,
+        mut handler: H,
+    ) -> Result<DrainReport, KvError>
+    where
+        Sig: Future,
+        H: FnMut(TcpStream, Service<Store>, CancellationToken) -> Fut,
+        Fut: Future<Output = Result<(), KvError>> + Send + 'static,
+    {
+        tokio::pin!(signal);
+        loop {
+            let (stream, addr) = tokio::select! {
+                res = listener.accept() => match res {
+                    Ok(res) => res,
+                    Err(e) => {
+                        accept_error(e).await;
+                        continue;
+                    }
+                },
+                _ = &mut signal => break,
+            };
+            info!("Client {:?} connected", addr);
+            let fut = handler(stream, self.service.new_connection(), self.shutdown.token());
+            self.shutdown.spawn(serve(fut, addr));
+        }
+        drop(listener);
+        self.stop().await
+    }
+
+    /// 等待所有连接、server 和请求退出，然后 flush 存储
+    pub async fn stop(self) -> Result<DrainReport, KvError> {
+        info!(
+            "Shutting down, waiting for {} connections to finish",
+            self.shutdown.connections()
+        );
+        let report = self.shutdown.shutdown(self.drain_timeout).await;
+        info!(
+            "{} connections drained, {} force closed",
+            report.drained, report.force_closed
+        );
+        self.service.flush()?;
+        Ok(report)
+    }
+}
+
+async fn serve<F>(fut: F, addr: SocketAddr)
+where
+    F: Future<Output = Result<(), KvError>>,
+{
+    if let Err(e) = fut.await {
+        warn!("Failed to serve client {:?}: {}", addr, e);
+    }
+    info!("Client {:?} disconnected", addr);
+}
+
+/// accept 失败通常是暂时的（如文件描述符用完），等一会儿再继续，避免空转
+async fn accept_error(e: io::Error) {
+    warn!("Failed to accept connection: {}", e);
+    time::sleep(Duration::from_millis(100)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommandRequest, MemTable, ProstClientStream, ProstServerStream, Value};
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn graceful_shutdown_should_force_close_after_timeout() {
+        let shutdown = GracefulShutdown::new();
+        // 一个会响应退出信号的连接，一个不会
+        let token = shutdown.token();
+        shutdown.spawn(async move { token.cancelled().await });
+        shutdown.spawn(std::future::pending());
+        assert_eq!(shutdown.connections(), 2);
+
+        let report = shutdown.shutdown(Duration::from_millis(50)).await;
+        assert_eq!(
+            report,
+            DrainReport {
+                drained: 1,
+                force_closed: 1
+            }
+        );
+        assert_eq!(shutdown.connections(), 0);
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown_should_wait_for_servers_and_requests() {
+        let shutdown = GracefulShutdown::new();
+        let server_done = Arc::new(AtomicUsize::new(0));
+        let request_done = Arc::new(AtomicUsize::new(0));
+
+        // server 收到退出信号后还要一会儿才退出
+        let token = shutdown.token();
+        let done = server_done.clone();
+        shutdown.spawn_server(async move {
+            token.cancelled().await;
+            time::sleep(Duration::from_millis(20)).await;
+            done.fetch_add(1, Ordering::Relaxed);
+        });
+        // 连接被强制关闭，但它 spawn 的请求还在运行
+        let requests = shutdown.requests();
+        let done = request_done.clone();
+        shutdown.spawn(async move {
+            requests.spawn(async move {
+                time::sleep(Duration::from_millis(100)).await;
+                done.fetch_add(1, Ordering::Relaxed);
+            });
+            std::future::pending::<()>().await
+        });
+        tokio::task::yield_now().await;
+
+        let report = shutdown.shutdown(Duration::from_millis(50)).await;
+        assert_eq!(
+            report,
+            DrainReport {
+                drained: 0,
+                force_closed: 1
+            }
+        );
+        assert_eq!(server_done.load(Ordering::Relaxed), 1);
+        assert_eq!(request_done.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn server_runner_should_drain_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, signal) = oneshot::channel::<()>();
+        let runner = ServerRunner::new(Service::new(MemTable::new()));
+        let server = tokio::spawn(runner.run(listener, signal, |stream, svc, token| {
+            ProstServerStream::new(stream).shutdown(token).process(svc)
+        }));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut client = ProstClientStream::new(stream);
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let res = client.execute(&cmd).await.unwrap();
+        assert_eq!(res.values, [Value::default()]);
+
+        stop.send(()).unwrap();
+        let report = server.await.unwrap().unwrap();
+        assert_eq!(
+            report,
+            DrainReport {
+                drained: 1,
+                force_closed: 0
+            }
+        );
+        // 服务器已经关闭了连接
+        assert!(client.execute(&cmd).await.is_err());
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+}
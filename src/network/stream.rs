@@ -0,0 +1,1002 @@
+use std::{sync::Arc, time::Duration};
+
+use bytes::BytesMut;
+use futures::{stream, StreamExt};
+use http::StatusCode;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, Mutex, Semaphore},
+    time::{self, Instant},
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::{BoxError, ServiceExt};
+use tracing::{debug, info, warn};
+
+use crate::{
+    command_request::RequestData, read_frame_with, service::layer_error, value, CommandRequest,
+    CommandResponse, Compression, ConnectionMetrics, FrameCoder, FrameConfig, Hello, KvError,
+    ResponseStream, ServerHello, Value, CAPABILITIES, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+
+/// 一个连接上同时处理的请求数上限
+pub const MAX_IN_FLIGHT: usize = 128;
+
+/// 握手时告诉客户端服务器支持的命令
+pub const COMMANDS: &[&str] = &[
+    "hget", "hgetall", "hmget", "hset", "hmset", "hdel", "hmdel", "hexist", "hmexist", "custom",
+    "eval", "auth", "hello", "ping",
+];
+
+/// 处理服务器端的某个 stream 上的 CommandRequest / CommandResponse
+///
+/// S 可以是 TcpStream、TLS stream 或者内存里的 DuplexStream，只要实现了 AsyncRead + AsyncWrite。
+pub struct ProstServerStream<S> {
+    inner: S,
+    config: FrameConfig,
+    compressions: Vec<Compression>,
+    shutdown: CancellationToken,
+    keepalive: Keepalive,
+    metrics: Option<Arc<ConnectionMetrics>>,
+    tracker: TaskTracker,
+}
+
+/// 处理客户端的某个 stream 上的 CommandRequest / CommandResponse
+pub struct ProstClientStream<S> {
+    inner: S,
+    config: FrameConfig,
+    client_name: String,
+    /// 握手时是否声明会回复服务器的 ping
+    keepalive: bool,
+    /// 握手之后服务器返回的信息，老服务器没有
+    server: Option<ServerHello>,
+}
+
+/// 写回客户端的内容：响应，或者握手之后切换压缩算法
+enum Outgoing {
+    Response(Box<CommandResponse>),
+    Compression(Compression),
+}
+
+impl<S> ProstServerStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: S) -> Self {
+        Self {
+            inner: stream,
+            config: FrameConfig::default(),
+            compressions: Compression::SUPPORTED.to_vec(),
+            shutdown: CancellationToken::new(),
+            keepalive: Keepalive::default(),
+            metrics: None,
+            tracker: TaskTracker::new(),
+        }
+    }
+
+    /// 握手时允许使用的压缩算法，默认是所有支持的算法
+    pub fn compressions(mut self, compressions: Vec<Compression>) -> Self {
+        self.compressions = compressions;
+        self
+    }
+
+    /// payload 超过多少字节才压缩
+    pub fn compression_limit(mut self, limit: usize) -> Self {
+        self.config.compression_limit = limit;
+        self
+    }
+
+    /// 单个 frame 的最大长度，以及压缩的 frame 解压后的最大长度，超过时断开连接
+    pub fn max_frame_size(mut self, max_frame_size: usize, max_decompressed_size: usize) -> Self {
+        self.config.max_frame_size = max_frame_size;
+        self.config.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    /// token 被取消后不再读取新的请求，已经收到的请求处理完、响应写回后 process 返回
+    pub fn shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// 在 tracker 里 spawn 请求
+    ///
+    /// process 的 future 被 drop（如退出时被强制关闭）后，已经开始的请求还会继续运行。
+    /// 传入 `GracefulShutdown::requests()`，退出时会等这些请求结束后再 flush 存储。
+    pub fn tracker(mut self, tracker: TaskTracker) -> Self {
+        self.tracker = tracker;
+        self
+    }
+
+    /// 超过 timeout 没有收到请求（Pong 不算）、也没有正在处理的请求时关闭连接
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.keepalive.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// 超过 interval 没有收到任何 frame 时发送 ping，再过 interval 还没有收到 Pong 就关闭连接
+    ///
+    /// 只对在 Hello 中声明了 "ping" 的客户端生效，老客户端不认识服务器发起的 ping。
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.keepalive.ping_interval = Some(interval);
+        self
+    }
+
+    /// 因为空闲或者没有回复 ping 关闭连接时，记录到 metrics 里
+    pub fn metrics(mut self, metrics: Arc<ConnectionMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// 处理这个连接上的所有请求，直到客户端断开
+    ///
+    /// svc 可以是 `kv::Service` 本身，也可以是用 `tower::ServiceBuilder` 包了任意 layer 的 Service，
+    /// 外层 layer 返回的错误（如超时、过载）会被转换成 500 的 CommandResponse 返回给客户端。
+    ///
+    /// 同一个连接上的请求会并发处理（最多 MAX_IN_FLIGHT 个），响应按完成的顺序写回，
+    /// 并带上请求的 id，客户端需要用 id 来匹配响应。需要保证顺序的请求（如 Auth 之后的命令），
+    /// 客户端应该等上一个响应回来后再发送。
+    ///
+    /// svc 返回 ResponseStream 时（如分块的 Hgetall），会边迭代边写回，每个响应都带上请求的 id。
+    ///
+    /// Hello 握手由这里直接处理，不会交给 svc。没有握手的客户端使用 gzip 压缩；
+    /// 客户端的协议版本不兼容时返回 505，然后不再处理这个连接上的请求。
+    pub async fn process<Svc>(self, svc: Svc) -> Result<(), KvError>
+    where
+        Svc: tower::Service<CommandRequest> + Send + 'static,
+        Svc::Response: Into<ResponseStream> + Send,
+        Svc::Future: Send + 'static,
+        Svc::Error: Into<BoxError> + Send,
+    {
+        let svc = SharedService::new(svc);
+        let (reader, mut writer) = tokio::io::split(self.inner);
+        let (tx, mut rx) = mpsc::channel::<Outgoing>(MAX_IN_FLIGHT);
+        let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+        let compressions = self.compressions;
+        let limits = self.config;
+        let shutdown = self.shutdown;
+        let tracker = self.tracker;
+        let mut liveness = Liveness::new(self.keepalive, self.metrics);
+
+        // 读到一半的 frame 保存在 stream 里，select 中的定时器触发时不会丢掉已经读到的数据
+        let frames = stream::unfold(reader, move |mut reader| async move {
+            let frame = recv_frame_with::<_, CommandRequest>(&mut reader, &limits)
+                .await
+                .transpose()?;
+            Some((frame, reader))
+        });
+
+        let read_loop = async move {
+            tokio::pin!(frames);
+            loop {
+                let deadline = liveness.deadline();
+                let cmd = tokio::select! {
+                    cmd = frames.next() => match cmd {
+                        Some(cmd) => cmd?,
+                        None => break,
+                    },
+                    _ = shutdown.cancelled() => {
+                        debug!("Server is shutting down, stop reading requests");
+                        break;
+                    }
+                    _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                        let busy = in_flight.available_permits() < MAX_IN_FLIGHT;
+                        if liveness.check(busy)? {
+                            let _ = tx.send(Outgoing::Response(Box::new(CommandResponse::new_ping()))).await;
+                        }
+                        continue;
+                    }
+                };
+                liveness.received(&cmd);
+                if let Some(RequestData::Pong(_)) = &cmd.request_data {
+                    continue;
+                }
+                debug!("Got a new command: {:?}", cmd.redacted());
+                if let Some(RequestData::Hello(hello)) = &cmd.request_data {
+                    let (mut res, compression) = handshake(hello, &compressions);
+                    res.id = cmd.id;
+                    let _ = tx.send(Outgoing::Response(Box::new(res))).await;
+                    match compression {
+                        // 先用原来的算法返回握手结果，之后的响应再切换
+                        Some(compression) => {
+                            let _ = tx.send(Outgoing::Compression(compression)).await;
+                            continue;
+                        }
+                        None => break,
+                    }
+                }
+                let permit = in_flight
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .map_err(|e| KvError::Internal(e.to_string()))?;
+                let svc = svc.clone();
+                let tx = tx.clone();
+                tracker.spawn(async move {
+                    let id = cmd.id;
+                    for mut res in svc.call(cmd).await {
+                        res.id = id;
+                        // 写端已经关闭时，连接已经断了，剩下的响应直接丢弃
+                        if tx.send(Outgoing::Response(Box::new(res))).await.is_err() {
+                            break;
+                        }
+                    }
+                    drop(permit);
+                });
+            }
+            // tx 在这里被 drop，所有请求处理完后 write_loop 会退出
+            Ok::<_, KvError>(())
+        };
+
+        let mut config = self.config;
+        let write_loop = async move {
+            while let Some(out) = rx.recv().await {
+                match out {
+                    Outgoing::Response(res) => send_frame_with(&mut writer, &*res, &config).await?,
+                    Outgoing::Compression(compression) => config.compression = compression,
+                }
+            }
+            Ok::<_, KvError>(())
+        };
+
+        tokio::try_join!(read_loop, write_loop)?;
+        Ok(())
+    }
+}
+
+impl<S> ProstClientStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: S) -> Self {
+        Self {
+            inner: stream,
+            config: FrameConfig::default(),
+            client_name: "kv-client".into(),
+            keepalive: false,
+            server: None,
+        }
+    }
+
+    /// 握手时是否告诉服务器会回复它发起的 ping，默认不会
+    ///
+    /// ProstClientStream 只在等待响应时读取连接，空闲时收不到 ping，服务器会因为等不到 Pong 而断开连接。
+    /// 只有会持续发送请求的客户端才应该打开。
+    pub fn keepalive(mut self, enabled: bool) -> Self {
+        self.keepalive = enabled;
+        self
+    }
+
+    /// 握手时发给服务器的客户端名字
+    pub fn client_name(mut self, name: impl Into<String>) -> Self {
+        self.client_name = name.into();
+        self
+    }
+
+    /// 握手时服务器返回的版本、命令和功能，没有握手或者服务器太老时为 None
+    pub fn server_hello(&self) -> Option<&ServerHello> {
+        self.server.as_ref()
+    }
+
+    /// payload 超过多少字节才压缩
+    pub fn compression_limit(mut self, limit: usize) -> Self {
+        self.config.compression_limit = limit;
+        self
+    }
+
+    /// 单个 frame 的最大长度，以及压缩的 frame 解压后的最大长度，超过时断开连接
+    pub fn max_frame_size(mut self, max_frame_size: usize, max_decompressed_size: usize) -> Self {
+        self.config.max_frame_size = max_frame_size;
+        self.config.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    /// 和服务器握手，检查协议版本并协商压缩算法，compressions 按优先级排列
+    ///
+    /// 不认识 Hello 的老服务器会返回错误，这时继续使用 gzip。
+    /// 双方的协议版本不兼容时返回 `KvError::UnsupportedProtocol`。
+    pub async fn negotiate(
+        &mut self,
+        compressions: &[Compression],
+    ) -> Result<Compression, KvError> {
+        let mut cmd = CommandRequest::new_hello(self.client_name.clone(), compressions);
+        if self.keepalive {
+            cmd = cmd.with_ping();
+        }
+        let res = self.execute(&cmd).await?;
+        check_server_hello(&res)?;
+        let negotiated = res.values.first().and_then(|v| match v.value {
+            Some(value::Value::Integer(i)) => Compression::try_from(i as i32).ok(),
+            _ => None,
+        });
+        let compression = match negotiated {
+            Some(c) if (200..300).contains(&res.status) => c,
+            _ => Compression::Gzip,
+        };
+        self.config.compression = compression;
+        self.server = res.hello;
+        Ok(compression)
+    }
+
+    /// 发送一个命令，等待服务器的响应
+    ///
+    /// 等待期间收到服务器发起的 ping 时，回复 Pong 后继续等待。
+    pub async fn execute(&mut self, cmd: &CommandRequest) -> Result<CommandResponse, KvError> {
+        send_frame_with(&mut self.inner, cmd, &self.config).await?;
+        loop {
+            let res: CommandResponse = recv_frame_with(&mut self.inner, &self.config)
+                .await?
+                .ok_or_else(|| {
+                    KvError::IoError(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Server closed the connection",
+                    ))
+                })?;
+            if !res.ping {
+                return Ok(res);
+            }
+            send_frame_with(&mut self.inner, &CommandRequest::new_pong(), &self.config).await?;
+        }
+    }
+}
+
+/// 一个连接上的所有请求共用同一个 svc
+///
+/// 一般的 tower 用法是每个请求 clone 一次 svc，但 `Service` clone 出来的是一个新的连接，
+/// 看不到这个连接上 Auth 登录的状态，所以这里把 svc 放在锁里共用。锁只在等 svc ready 和调用 call 时持有，
+/// `Service` 的 call 只是创建 future，命令在 future 里执行，所以请求本身仍然是并发执行的。
+pub(crate) struct SharedService<Svc>(Arc<Mutex<Svc>>);
+
+impl<Svc> Clone for SharedService<Svc> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<Svc> SharedService<Svc>
+where
+    Svc: tower::Service<CommandRequest>,
+    Svc::Response: Into<ResponseStream>,
+    Svc::Error: Into<BoxError>,
+{
+    pub(crate) fn new(svc: Svc) -> Self {
+        Self(Arc::new(Mutex::new(svc)))
+    }
+
+    /// 执行一个请求，外层 layer 返回的错误转换成对应的响应（超时 504、过载 503）
+    pub(crate) async fn call(&self, cmd: CommandRequest) -> ResponseStream {
+        let fut = {
+            let mut svc = self.0.lock().await;
+            match svc.ready().await {
+                Ok(svc) => Ok(svc.call(cmd)),
+                Err(e) => Err(e),
+            }
+        };
+        let res = match fut {
+            Ok(fut) => fut.await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(responses) => responses.into(),
+            Err(e) => {
+                let e = layer_error(e.into());
+                warn!("Failed to execute request: {}", e);
+                CommandResponse::from(e).into()
+            }
+        }
+    }
+}
+
+/// 连接的存活检查参数，都不设置时不检查
+#[derive(Clone, Copy, Debug, Default)]
+struct Keepalive {
+    idle_timeout: Option<Duration>,
+    ping_interval: Option<Duration>,
+}
+
+/// 记录一个连接最近收到请求的时间，判断是否该发 ping 或者关闭连接
+struct Liveness {
+    keepalive: Keepalive,
+    metrics: Option<Arc<ConnectionMetrics>>,
+    /// 客户端在 Hello 中声明支持 ping 之后才会发送
+    ping_enabled: bool,
+    last_request: Instant,
+    last_frame: Instant,
+    ping_sent: Option<Instant>,
+}
+
+impl Liveness {
+    fn new(keepalive: Keepalive, metrics: Option<Arc<ConnectionMetrics>>) -> Self {
+        let now = Instant::now();
+        Self {
+            keepalive,
+            metrics,
+            ping_enabled: false,
+            last_request: now,
+            last_frame: now,
+            ping_sent: None,
+        }
+    }
+
+    /// 下一次需要检查的时间，不需要检查时为 None
+    fn deadline(&self) -> Option<Instant> {
+        let idle = self.keepalive.idle_timeout.map(|t| self.last_request + t);
+        let ping = self
+            .keepalive
+            .ping_interval
+            .filter(|_| self.ping_enabled)
+            .map(|i| self.ping_sent.unwrap_or(self.last_frame) + i);
+        idle.into_iter().chain(ping).min()
+    }
+
+    /// 收到一个 frame，任何 frame 都说明客户端还活着
+    fn received(&mut self, cmd: &CommandRequest) {
+        let now = Instant::now();
+        self.last_frame = now;
+        self.ping_sent = None;
+        match &cmd.request_data {
+            Some(RequestData::Pong(_)) => {}
+            Some(RequestData::Hello(hello)) => {
+                self.ping_enabled = hello.capabilities.iter().any(|c| c == "ping");
+                self.last_request = now;
+            }
+            _ => self.last_request = now,
+        }
+    }
+
+    /// 到了 deadline 时检查连接，返回是否需要发送 ping；空闲太久或者没有回复 ping 时返回错误
+    fn check(&mut self, busy: bool) -> Result<bool, KvError> {
+        let now = Instant::now();
+        // 还有请求在处理时不算空闲
+        if busy {
+            self.last_request = now;
+        }
+        if let Some(timeout) = self.keepalive.idle_timeout {
+            if now >= self.last_request + timeout {
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_idle_closed();
+                }
+                return Err(KvError::Timeout(format!(
+                    "Client has been idle for {:?}",
+                    timeout
+                )));
+            }
+        }
+        let Some(interval) = self.keepalive.ping_interval.filter(|_| self.ping_enabled) else {
+            return Ok(false);
+        };
+        match self.ping_sent {
+            Some(sent) if now >= sent + interval => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_ping_timeout();
+                }
+                Err(KvError::Timeout(format!(
+                    "Client did not answer ping in {:?}",
+                    interval
+                )))
+            }
+            None if now >= self.last_frame + interval => {
+                self.ping_sent = Some(now);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// 检查客户端的协议版本并选出压缩算法，生成握手的响应
+///
+/// 响应的 values 里只有选出的算法，和没有版本号的老客户端兼容；服务器的信息放在 hello 里。
+/// 版本不兼容时返回 505 的响应和 None，响应里同样带上 hello，客户端从中得到服务器支持的版本范围。
+pub(crate) fn handshake(
+    hello: &Hello,
+    supported: &[Compression],
+) -> (CommandResponse, Option<Compression>) {
+    let server = ServerHello {
+        protocol_version: PROTOCOL_VERSION,
+        server_version: env!("CARGO_PKG_VERSION").into(),
+        commands: COMMANDS.iter().map(|s| s.to_string()).collect(),
+        compressions: supported.iter().map(|c| *c as i32).collect(),
+        capabilities: CAPABILITIES.iter().map(|s| s.to_string()).collect(),
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+    };
+    let version = hello.protocol_version.max(MIN_PROTOCOL_VERSION);
+    if version > PROTOCOL_VERSION {
+        let e = KvError::UnsupportedProtocol(
+            hello.protocol_version,
+            MIN_PROTOCOL_VERSION,
+            PROTOCOL_VERSION,
+        );
+        warn!("Rejected client {:?}: {}", hello.client_name, e);
+        let mut res = CommandResponse::from(e);
+        res.hello = Some(server);
+        return (res, None);
+    }
+    info!(
+        "Client {:?} uses protocol version {}, capabilities: {:?}",
+        hello.client_name, version, hello.capabilities
+    );
+
+    let compression = negotiate(hello, supported);
+    let mut res = CommandResponse::from(Value::from(compression as i64));
+    res.hello = Some(server);
+    (res, Some(compression))
+}
+
+/// 检查服务器对 Hello 的响应，双方的协议版本不兼容时返回 `KvError::UnsupportedProtocol`
+///
+/// 服务器支持的版本范围来自 ServerHello；不认识 Hello 的老服务器返回的其他错误不算不兼容。
+pub(crate) fn check_server_hello(res: &CommandResponse) -> Result<(), KvError> {
+    let unsupported = res.status == StatusCode::HTTP_VERSION_NOT_SUPPORTED.as_u16() as u32;
+    match &res.hello {
+        Some(hello) if unsupported => Err(KvError::UnsupportedProtocol(
+            PROTOCOL_VERSION,
+            hello.min_protocol_version,
+            hello.protocol_version,
+        )),
+        Some(hello) if hello.protocol_version < MIN_PROTOCOL_VERSION => {
+            Err(KvError::UnsupportedProtocol(
+                hello.protocol_version,
+                MIN_PROTOCOL_VERSION,
+                PROTOCOL_VERSION,
+            ))
+        }
+        None if unsupported => res.clone().into_result().map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// 选出客户端和服务器都支持的第一个算法，都不支持时不压缩
+fn negotiate(hello: &Hello, supported: &[Compression]) -> Compression {
+    hello
+        .compressions
+        .iter()
+        .filter_map(|c| Compression::try_from(*c).ok())
+        .find(|c| supported.contains(c))
+        .unwrap_or(Compression::None)
+}
+
+/// 把 msg 用默认配置编码成一个 frame 写入 stream
+pub(crate) async fn send_frame<S, M>(stream: &mut S, msg: &M) -> Result<(), KvError>
+where
+    S: AsyncWrite + Unpin + Send,
+    M: FrameCoder,
+{
+    send_frame_with(stream, msg, &FrameConfig::default()).await
+}
+
+/// 把 msg 按 config 编码成一个 frame 写入 stream
+pub(crate) async fn send_frame_with<S, M>(
+    stream: &mut S,
+    msg: &M,
+    config: &FrameConfig,
+) -> Result<(), KvError>
+where
+    S: AsyncWrite + Unpin + Send,
+    M: FrameCoder,
+{
+    let mut buf = BytesMut::new();
+    msg.encode_frame_with(&mut buf, config)?;
+    stream.write_all(&buf).await?;
+    // TLS 之类的 stream 会缓存数据，需要 flush 才能真正发出去
+    stream.flush().await?;
+    Ok(())
+}
+
+/// 从 stream 中用默认的大小限制读取一个 frame 并解码，对方正常关闭连接时返回 None
+pub(crate) async fn recv_frame<S, M>(stream: &mut S) -> Result<Option<M>, KvError>
+where
+    S: AsyncRead + Unpin + Send,
+    M: FrameCoder,
+{
+    recv_frame_with(stream, &FrameConfig::default()).await
+}
+
+/// 从 stream 中按 config 的大小限制读取一个 frame 并解码，对方正常关闭连接时返回 None
+pub(crate) async fn recv_frame_with<S, M>(
+    stream: &mut S,
+    config: &FrameConfig,
+) -> Result<Option<M>, KvError>
+where
+    S: AsyncRead + Unpin + Send,
+    M: FrameCoder,
+{
+    let mut buf = BytesMut::new();
+    match read_frame_with(stream, &mut buf, config).await {
+        Ok(()) => Ok(Some(M::decode_frame_with(&mut buf, config)?)),
+        Err(KvError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::net::{TcpListener, TcpStream};
+    use tower::ServiceBuilder;
+
+    use super::*;
+    use crate::{
+        hash_password, service::assert_res_ok, Authenticator, MemTable, PipelinedClient, Service,
+        ServiceInner, TlsClientConnector, TlsServerAcceptor,
+    };
+
+    /// 在一个 client stream 上跑一组 hset / hget，包括超过压缩阈值的大 value
+    async fn run_commands<S>(client: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let mut client = ProstClientStream::new(client);
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let res = client.execute(&cmd).await.unwrap();
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let res = client.execute(&cmd).await.unwrap();
+        assert_res_ok(res, &["v1".into()], &[]);
+
+        let big: Value = Bytes::from(vec![7u8; 16384]).into();
+        let cmd = CommandRequest::new_hset("t1", "big", big.clone());
+        let res = client.execute(&cmd).await.unwrap();
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hget("t1", "big");
+        let res = client.execute(&cmd).await.unwrap();
+        assert_res_ok(res, &[big], &[]);
+    }
+
+    #[tokio::test]
+    async fn prost_stream_should_work_over_duplex() {
+        let (client, server) = tokio::io::duplex(4096);
+        let service = Service::new(MemTable::new());
+        let server = tokio::spawn(ProstServerStream::new(server).process(service));
+
+        run_commands(client).await;
+        // client 关闭后 server 正常退出
+        assert!(server.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn prost_stream_should_work_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = Service::new(MemTable::new());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            ProstServerStream::new(stream).process(service).await
+        });
+
+        let client = TcpStream::connect(addr).await.unwrap();
+        run_commands(client).await;
+    }
+
+    #[tokio::test]
+    async fn prost_stream_should_work_over_tls() {
+        let acceptor =
+            TlsServerAcceptor::new("fixtures/server.cert", "fixtures/server.key", None).unwrap();
+        let connector =
+            TlsClientConnector::new("kvserver.acme.inc", None, Some("fixtures/ca.cert")).unwrap();
+        let (client, server) = tokio::io::duplex(4096);
+        let service = Service::new(MemTable::new());
+        tokio::spawn(async move {
+            let stream = acceptor.accept(server).await.unwrap();
+            ProstServerStream::new(stream).process(service).await
+        });
+
+        let client = connector.connect(client).await.unwrap();
+        run_commands(client).await;
+    }
+
+    #[tokio::test]
+    async fn prost_server_stream_should_process_requests_concurrently() {
+        let (mut client, server) = tokio::io::duplex(4096);
+        // table 为 slow 的请求要等一会儿才返回
+        let svc = tower::service_fn(|cmd: CommandRequest| async move {
+            if let Some(RequestData::Hget(p)) = &cmd.request_data {
+                if p.table == "slow" {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+            }
+            Ok::<_, KvError>(CommandResponse::from(Value::from("done")))
+        });
+        tokio::spawn(ProstServerStream::new(server).process(svc));
+
+        let slow = CommandRequest::new_hget("slow", "k1").with_id(1);
+        let fast = CommandRequest::new_hget("fast", "k1").with_id(2);
+        send_frame(&mut client, &slow).await.unwrap();
+        send_frame(&mut client, &fast).await.unwrap();
+
+        let res: CommandResponse = recv_frame(&mut client).await.unwrap().unwrap();
+        assert_eq!(res.id, 2);
+        let res: CommandResponse = recv_frame(&mut client).await.unwrap().unwrap();
+        assert_eq!(res.id, 1);
+    }
+
+    #[tokio::test]
+    async fn prost_stream_should_negotiate_compression() {
+        for (client_prefers, server_allows, expected) in [
+            (
+                vec![Compression::Zstd, Compression::Gzip],
+                None,
+                Compression::Zstd,
+            ),
+            (vec![Compression::Lz4], None, Compression::Lz4),
+            (
+                vec![Compression::Zstd, Compression::Lz4],
+                Some(vec![Compression::Lz4, Compression::Gzip]),
+                Compression::Lz4,
+            ),
+            (
+                vec![Compression::Zstd],
+                Some(vec![Compression::Gzip]),
+                Compression::None,
+            ),
+        ] {
+            let (client, server) = tokio::io::duplex(4096);
+            let mut server = ProstServerStream::new(server).compression_limit(64);
+            if let Some(allows) = server_allows {
+                server = server.compressions(allows);
+            }
+            tokio::spawn(server.process(Service::new(MemTable::new())));
+
+            let mut client = ProstClientStream::new(client).compression_limit(64);
+            let compression = client.negotiate(&client_prefers).await.unwrap();
+            assert_eq!(compression, expected);
+            run_commands(client.inner).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn prost_stream_should_exchange_server_hello() {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(ProstServerStream::new(server).process(Service::new(MemTable::new())));
+
+        let mut client = ProstClientStream::new(client).client_name("test");
+        assert!(client.server_hello().is_none());
+        client.negotiate(&[Compression::Lz4]).await.unwrap();
+        let hello = client.server_hello().unwrap();
+        assert_eq!(hello.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(hello.min_protocol_version, MIN_PROTOCOL_VERSION);
+        assert_eq!(hello.server_version, env!("CARGO_PKG_VERSION"));
+        assert!(hello.commands.iter().any(|c| c == "hgetall"));
+        assert!(hello.compressions.contains(&(Compression::Lz4 as i32)));
+        assert_eq!(hello.capabilities, CAPABILITIES);
+    }
+
+    #[tokio::test]
+    async fn prost_server_stream_should_reject_newer_protocol() {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let handle =
+            tokio::spawn(ProstServerStream::new(server).process(Service::new(MemTable::new())));
+
+        let mut cmd = CommandRequest::new_hello("future", &[Compression::Gzip]);
+        if let Some(RequestData::Hello(hello)) = &mut cmd.request_data {
+            hello.protocol_version = PROTOCOL_VERSION + 1;
+        }
+        send_frame(&mut client, &cmd).await.unwrap();
+        let res: CommandResponse = recv_frame(&mut client).await.unwrap().unwrap();
+        assert_eq!(res.status, 505);
+        // 拒绝的响应里也有服务器支持的版本范围，客户端不需要解析错误信息
+        let hello = res.hello.as_ref().unwrap();
+        assert_eq!(hello.min_protocol_version, MIN_PROTOCOL_VERSION);
+        assert_eq!(hello.protocol_version, PROTOCOL_VERSION);
+        assert!(matches!(
+            res.into_result(),
+            Err(KvError::UnsupportedProtocol(v, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION))
+                if v == PROTOCOL_VERSION + 1
+        ));
+        // 拒绝之后服务器关闭连接
+        assert!(handle.await.unwrap().is_ok());
+        let res: Option<CommandResponse> = recv_frame(&mut client).await.unwrap();
+        assert!(res.is_none());
+    }
+
+    #[tokio::test]
+    async fn prost_server_stream_should_accept_hello_without_version() {
+        let (mut client, server) = tokio::io::duplex(4096);
+        tokio::spawn(ProstServerStream::new(server).process(Service::new(MemTable::new())));
+
+        // 老客户端的 Hello 里只有压缩算法
+        let cmd = CommandRequest {
+            request_data: Some(RequestData::Hello(Hello {
+                compressions: vec![Compression::Gzip as i32],
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        send_frame(&mut client, &cmd).await.unwrap();
+        let res: CommandResponse = recv_frame(&mut client).await.unwrap().unwrap();
+        assert_res_ok(res, &[(Compression::Gzip as i64).into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn prost_server_stream_should_close_on_oversized_frame() {
+        let (client, server) = tokio::io::duplex(4096);
+        let server = ProstServerStream::new(server).max_frame_size(1024, 1024);
+        let handle = tokio::spawn(server.process(Service::new(MemTable::new())));
+
+        let mut client = ProstClientStream::new(client);
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        assert_res_ok(
+            client.execute(&cmd).await.unwrap(),
+            &[Value::default()],
+            &[],
+        );
+
+        // 压缩后很小，但解压后超过 1024 字节，同样会被拒绝
+        let value: Value = Bytes::from(vec![0u8; 4096]).into();
+        let res = client
+            .execute(&CommandRequest::new_hset("t1", "k1", value))
+            .await;
+        assert!(res.is_err());
+        let res = handle.await.unwrap();
+        assert!(matches!(res, Err(KvError::FrameError)));
+    }
+
+    #[tokio::test]
+    async fn prost_client_stream_should_fall_back_to_gzip_for_old_servers() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        // 老服务器不认识 Hello，解出来的请求没有 request_data
+        tokio::spawn(async move {
+            let _: Option<CommandRequest> = recv_frame(&mut server).await.unwrap();
+            let res: CommandResponse = KvError::InvalidCommand("Request has no data".into()).into();
+            send_frame(&mut server, &res).await.unwrap();
+        });
+
+        let mut client = ProstClientStream::new(client);
+        let compression = client.negotiate(&[Compression::Zstd]).await.unwrap();
+        assert_eq!(compression, Compression::Gzip);
+    }
+
+    #[tokio::test]
+    async fn prost_server_stream_should_close_idle_connections() {
+        let (client, server) = tokio::io::duplex(4096);
+        let metrics = Arc::new(ConnectionMetrics::default());
+        let server = ProstServerStream::new(server)
+            .idle_timeout(Duration::from_millis(50))
+            .metrics(metrics.clone());
+        let handle = tokio::spawn(server.process(Service::new(MemTable::new())));
+
+        let mut client = ProstClientStream::new(client);
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        assert_res_ok(
+            client.execute(&cmd).await.unwrap(),
+            &[Value::default()],
+            &[],
+        );
+
+        let res = handle.await.unwrap();
+        assert!(matches!(res, Err(KvError::Timeout(_))));
+        assert_eq!(metrics.snapshot().idle_closed, 1);
+        assert!(client.execute(&cmd).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn prost_server_stream_should_close_when_ping_is_not_answered() {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let metrics = Arc::new(ConnectionMetrics::default());
+        let server = ProstServerStream::new(server)
+            .ping_interval(Duration::from_millis(50))
+            .metrics(metrics.clone());
+        let handle = tokio::spawn(server.process(Service::new(MemTable::new())));
+
+        let hello = CommandRequest::new_hello("test", &[]).with_ping();
+        send_frame(&mut client, &hello).await.unwrap();
+        let res: CommandResponse = recv_frame(&mut client).await.unwrap().unwrap();
+        assert!(!res.ping);
+
+        // 一段时间没有请求后，服务器发起 ping
+        let res: CommandResponse = recv_frame(&mut client).await.unwrap().unwrap();
+        assert!(res.ping);
+        assert_eq!(res.id, 0);
+
+        // 不回复 Pong，服务器关闭连接
+        let res = handle.await.unwrap();
+        assert!(matches!(res, Err(KvError::Timeout(_))));
+        assert_eq!(metrics.snapshot().ping_timeouts, 1);
+    }
+
+    #[tokio::test]
+    async fn prost_client_stream_should_answer_ping() {
+        let (client, server) = tokio::io::duplex(4096);
+        // 请求处理的时间比 ping 的间隔长，等待响应时会收到 ping
+        let svc = tower::service_fn(|_cmd: CommandRequest| async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            Ok::<_, KvError>(CommandResponse::from(Value::from("done")))
+        });
+        let server = ProstServerStream::new(server).ping_interval(Duration::from_millis(50));
+        tokio::spawn(server.process(svc));
+
+        let mut client = ProstClientStream::new(client).keepalive(true);
+        client.negotiate(&[Compression::Gzip]).await.unwrap();
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        for _ in 0..2 {
+            let res = client.execute(&cmd).await.unwrap();
+            assert_res_ok(res, &["done".into()], &[]);
+        }
+    }
+
+    #[tokio::test]
+    async fn prost_client_stream_should_not_be_pinged_by_default() {
+        let (client, server) = tokio::io::duplex(4096);
+        let server = ProstServerStream::new(server).ping_interval(Duration::from_millis(50));
+        tokio::spawn(server.process(Service::new(MemTable::new())));
+
+        // 没有声明 ping 的客户端空闲很久之后，连接仍然可用
+        let mut client = ProstClientStream::new(client);
+        client.negotiate(&[Compression::Gzip]).await.unwrap();
+        time::sleep(Duration::from_millis(200)).await;
+        let res = client
+            .execute(&CommandRequest::new_hget("t1", "k1"))
+            .await
+            .unwrap();
+        assert_eq!(res.status, 404);
+    }
+
+    #[tokio::test]
+    async fn slow_request_should_not_block_others_on_the_same_connection() {
+        let (client, server) = tokio::io::duplex(4096);
+        let service: Service = ServiceInner::new(MemTable::new())
+            .script_max_operations(u64::MAX)
+            .into();
+        tokio::spawn(ProstServerStream::new(server).process(service));
+
+        let client = PipelinedClient::new(client);
+        let slow = {
+            let client = client.clone();
+            let script = "let n = 0; for i in 0..5000000 { n += i; } n";
+            tokio::spawn(async move {
+                client
+                    .execute(CommandRequest::new_eval(script, vec![], vec![], vec![]))
+                    .await
+            })
+        };
+        time::sleep(Duration::from_millis(10)).await;
+
+        // Eval 还在执行时，同一个连接上的 Hget 已经返回了
+        let res = client
+            .execute(CommandRequest::new_hget("t1", "k1"))
+            .await
+            .unwrap();
+        assert_eq!(res.status, 404);
+        assert!(!slow.is_finished());
+        assert_eq!(slow.await.unwrap().unwrap().status, 200);
+    }
+
+    #[tokio::test]
+    async fn prost_stream_should_work_with_layers() {
+        let (client, server) = tokio::io::duplex(4096);
+        let svc = ServiceBuilder::new()
+            .timeout(Duration::from_secs(1))
+            .concurrency_limit(1)
+            .service(Service::new(MemTable::new()));
+        tokio::spawn(ProstServerStream::new(server).process(svc));
+
+        run_commands(client).await;
+    }
+
+    #[tokio::test]
+    async fn prost_server_stream_should_keep_auth_state_with_layers() {
+        let auth = Authenticator::default();
+        auth.set_user("alice", hash_password("secret").unwrap());
+        let service: Service = ServiceInner::new(MemTable::new())
+            .authenticator(Arc::new(auth))
+            .into();
+        let (client, server) = tokio::io::duplex(4096);
+        let svc = ServiceBuilder::new()
+            .concurrency_limit(4)
+            .service(service.new_connection());
+        tokio::spawn(ProstServerStream::new(server).process(svc));
+
+        // 同一个连接上 Auth 之后的请求都能看到登录的状态
+        let mut client = ProstClientStream::new(client);
+        let res = client
+            .execute(&CommandRequest::new_auth("alice", "secret"))
+            .await
+            .unwrap();
+        assert_res_ok(res, &[true.into()], &[]);
+        run_commands(client.inner).await;
+    }
+}
@@ -1,24 +1,79 @@
-use std::io::Read;
-use std::os::unix::fs::FileExt;
-use std::{io::Cursor, sync::Arc};
-
 use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
 use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
-use tokio_rustls::rustls::{ClientConfig, ServerConfig};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
 use tokio_rustls::TlsConnector;
 use tokio_rustls::{
     client::TlsStream as ClientTlsStream, server::TlsStream as ServerTlsStream, TlsAcceptor,
 };
+use tracing::{info, warn};
+use x509_parser::{extensions::GeneralName, parse_x509_certificate};
 
 use crate::KvError;
 
 /// KV Server 自己的 ALPN (Application-Layer Protocol Negotiation)
 const ALPN_KV: &str = "kv";
 
+/// TLS 服务端
+///
+/// ServerConfig 可以在运行时整体替换（reload / watch），新连接使用新的证书，已有的连接不受影响。
 #[derive(Clone)]
 pub struct TlsServerAcceptor {
-    inner: Arc<ServerConfig>,
+    inner: Arc<watch::Sender<Arc<ServerConfig>>>,
+    files: Arc<CertFiles>,
+    alpn: Option<Arc<Vec<Vec<u8>>>>,
+}
+
+/// 生成 ServerConfig 用到的文件
+#[derive(Debug)]
+struct CertFiles {
+    cert: String,
+    key: String,
+    client_ca: Option<String>,
+}
+
+impl CertFiles {
+    fn server_config(&self) -> Result<ServerConfig, KvError> {
+        let certs = load_certs(&self.cert)?;
+        let key = load_key(&self.key)?;
+
+        let builder = ServerConfig::builder();
+        let builder = match &self.client_ca {
+            Some(ca) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(ca)? {
+                    roots.add(cert).map_err(tls_error)?;
+                }
+                let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                    .build()
+                    .map_err(tls_error)?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_single_cert(certs, key).map_err(tls_error)?;
+        config.alpn_protocols = vec![ALPN_KV.as_bytes().to_vec()];
+        Ok(config)
+    }
+
+    /// 所有文件的修改时间，用来判断证书是否被更新了
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|f| {
+                std::fs::metadata(Path::new(f))
+                    .and_then(|m| m.modified())
+                    .ok()
+            })
+            .collect()
+    }
 }
 
 #[derive(Clone)]
@@ -28,29 +83,33 @@ pub struct TlsClientConnector {
 }
 
 impl TlsClientConnector {
-    /// load cert & key
+    /// 加载 client cert / key 以及签署服务器证书的 CA cert，生成 ClientConfig
     pub fn new(
         domain: impl Into<String>,
         identity: Option<(&str, &str)>,
         server_ca: Option<&str>,
     ) -> Result<Self, KvError> {
-        let mut config = ClientConfig::builder();
+        // 加载本地信任的根证书链，个别证书加载失败不影响使用
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
 
-        if let Some((cert, key)) = identity {
-            let certs = load_certs(cert)?;
-            let key = load_key(key)?;
-            config
+        // 如果有签署服务器的 CA 证书，则加载它，这样服务器证书不在根证书链
+        // 但是这个 CA 证书能验证它，也可以
+        if let Some(ca) = server_ca {
+            for cert in load_certs(ca)? {
+                roots.add(cert).map_err(tls_error)?;
+            }
         }
-        // 加载本地信任的根证书链
-        config.root_store = match rustls_native_certs::load_native_certs() {
-            Ok(store) | Err((Some(store), _)) => store,
-            Err((None, error)) => return Err(error.into()),
+
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let mut config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(tls_error)?,
+            None => builder.with_no_client_auth(),
         };
-        // 如果有签署服务器的 CA 证书，则加载它，这样服务器证书不在根证书链 // 但是这个 CA 证书能验证它，也可以
-        if let Some(cert) = server_ca {
-            let mut buf = Cursor::new(cert);
-            config.root_store.add_pem_file(&mut buf).unwrap();
-        }
+        config.alpn_protocols = vec![ALPN_KV.as_bytes().to_vec()];
+
         Ok(Self {
             config: Arc::new(config),
             domain: Arc::new(domain.into()),
@@ -61,7 +120,6 @@ impl TlsClientConnector {
     where
         S: AsyncRead + AsyncWrite + Unpin + Send,
     {
-        // let dns = DNSNameRef::
         let dns = ServerName::try_from(self.domain.as_str())
             .map_err(|_| KvError::Internal("Invalid DNS name".into()))?
             .to_owned();
@@ -74,55 +132,362 @@ impl TlsClientConnector {
 
 impl TlsServerAcceptor {
     /// 加载 server cert / CA cert，生成 ServerConfig
+    ///
+    /// 提供了 client_ca 时开启 mTLS，客户端必须出示由它签署的证书。
     pub fn new(cert: &str, key: &str, client_ca: Option<&str>) -> Result<Self, KvError> {
-        todo!()
+        let files = CertFiles {
+            cert: cert.into(),
+            key: key.into(),
+            client_ca: client_ca.map(Into::into),
+        };
+        let config = files.server_config()?;
+
+        Ok(Self {
+            inner: Arc::new(watch::Sender::new(Arc::new(config))),
+            files: Arc::new(files),
+            alpn: None,
+        })
+    }
+
+    /// 使用 protocols 代替 kv 自己的 ALPN，HTTP 等标准协议的 listener 需要设置
+    ///
+    /// 返回的 acceptor 和原来的共享证书，reload 对两者都生效。
+    pub fn alpn(&self, protocols: &[&str]) -> Self {
+        let protocols = protocols.iter().map(|p| p.as_bytes().to_vec()).collect();
+        Self {
+            alpn: Some(Arc::new(protocols)),
+            ..self.clone()
+        }
     }
 
     pub async fn accept<S>(&self, stream: S) -> Result<ServerTlsStream<S>, KvError>
     where
         S: AsyncRead + AsyncWrite + Unpin + Send,
     {
-        let acceptor = TlsAcceptor::from(self.inner.clone());
-        Ok(acceptor.accept(stream).await?)
+        let config = match &self.alpn {
+            Some(alpn) => {
+                let mut config = self.server_config().as_ref().clone();
+                config.alpn_protocols = alpn.to_vec();
+                Arc::new(config)
+            }
+            None => self.server_config(),
+        };
+        Ok(TlsAcceptor::from(config).accept(stream).await?)
+    }
+
+    /// 当前使用的 ServerConfig，QUIC 用它建立 endpoint
+    pub(crate) fn server_config(&self) -> Arc<ServerConfig> {
+        self.inner.borrow().clone()
+    }
+
+    /// 每次 reload 成功后收到新的 ServerConfig，QUIC 用它替换 endpoint 的配置
+    ///
+    /// 所有 clone 出来的 acceptor 都被 drop 之后，`changed()` 返回错误。
+    pub(crate) fn subscribe(&self) -> watch::Receiver<Arc<ServerConfig>> {
+        self.inner.subscribe()
+    }
+
+    /// 重新加载 cert / key / CA 文件并原子地替换 ServerConfig
+    ///
+    /// 加载失败时继续使用原来的配置。
+    pub fn reload(&self) -> Result<(), KvError> {
+        let config = self.files.server_config()?;
+        self.inner.send_replace(Arc::new(config));
+        info!("Reloaded TLS certificate from {}", self.files.cert);
+        Ok(())
+    }
+
+    /// 每隔 interval 检查一次证书文件，有修改就 reload
+    ///
+    /// 所有 clone 出来的 acceptor 都被 drop 之后，后台任务自动退出。
+    pub fn watch(&self, interval: Duration) {
+        let inner = Arc::downgrade(&self.inner);
+        let files = self.files.clone();
+        tokio::spawn(async move {
+            let mut last = files.modified();
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(inner) = inner.upgrade() else {
+                    break;
+                };
+                let current = files.modified();
+                if current == last {
+                    continue;
+                }
+                // 证书和私钥可能不是同时写完的，加载失败时下次再试
+                let acceptor = TlsServerAcceptor {
+                    inner,
+                    files: files.clone(),
+                    alpn: None,
+                };
+                match acceptor.reload() {
+                    Ok(()) => last = current,
+                    Err(e) => warn!("Failed to reload TLS certificate: {}", e),
+                }
+            }
+        });
     }
 }
 
-fn load_certs(cert: &str) -> Result<Vec<CertificateDer>, KvError> {
+/// 从 mTLS 的客户端证书中取出身份：优先使用 subject 的 CN，没有 CN 时使用第一个 SAN
+pub fn client_identity<S>(stream: &ServerTlsStream<S>) -> Option<String> {
+    let cert = stream.get_ref().1.peer_certificates()?.first()?;
+    cert_identity(cert)
+}
+
+pub(crate) fn cert_identity(cert: &CertificateDer) -> Option<String> {
+    let (_, cert) = parse_x509_certificate(cert.as_ref()).ok()?;
+    let cn = cert
+        .subject()
+        .iter_common_name()
+        .find_map(|cn| cn.as_str().ok().map(ToOwned::to_owned));
+    if cn.is_some() {
+        return cn;
+    }
+    cert.subject_alternative_name()
+        .ok()
+        .flatten()?
+        .value
+        .general_names
+        .iter()
+        .find_map(|name| match name {
+            GeneralName::DNSName(s) | GeneralName::RFC822Name(s) | GeneralName::URI(s) => {
+                Some(s.to_string())
+            }
+            _ => None,
+        })
+}
+
+/// 从 PEM 文件中加载证书链
+pub fn load_certs(cert: &str) -> Result<Vec<CertificateDer<'static>>, KvError> {
     let mut reader = BufReader::new(std::fs::File::open(cert)?);
-    rustls_pemfile::certs(&mut reader)
+    let certs = rustls_pemfile::certs(&mut reader)
         .collect::<Result<Vec<_>, _>>()
-        .map_err(|_| KvError::CertifcateParseError("server".to_owned(), "cert".to_owned()))
+        .map_err(|_| KvError::CertifcateParseError(cert.to_owned(), "cert".to_owned()))?;
+    if certs.is_empty() {
+        return Err(KvError::CertifcateParseError(
+            cert.to_owned(),
+            "cert".to_owned(),
+        ));
+    }
+    Ok(certs)
 }
 
-fn load_key(key: &str) -> Result<PrivateKeyDer, KvError> {
+/// 从 PEM 文件中加载私钥（PKCS#1 / PKCS#8 / SEC1）
+pub fn load_key(key: &str) -> Result<PrivateKeyDer<'static>, KvError> {
     let mut reader = BufReader::new(std::fs::File::open(key)?);
-    if let Ok(keys) = rustls_pemfile::private_key(&mut reader) {
-        if keys.is_some() {
-            return Ok(keys.unwrap().into());
-        }
+    match rustls_pemfile::private_key(&mut reader) {
+        Ok(Some(key)) => Ok(key),
+        _ => Err(KvError::CertifcateParseError(
+            "private".to_owned(),
+            "key".to_owned(),
+        )),
     }
+}
 
-    Err(KvError::CertifcateParseError(
-        "private".to_owned(),
-        "key".to_owned(),
-    ))
+pub(crate) fn tls_error(e: impl std::fmt::Display) -> KvError {
+    KvError::TlsError(e.to_string())
 }
 
 #[cfg(test)]
 mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
     use super::*;
-    use crate::Value;
-    use bytes::Bytes;
 
-    #[test]
-    fn load_key_with() {
-        let key = include_str!("/home/aji/kv/fixtures/server.key");
-        println!("{}", key);
-        let mut key = BufReader::new(Cursor::new(key));
-        let keys = rustls_pemfile::pkcs8_private_keys(&mut key)
-            .next()
+    const CA_CERT: &str = "fixtures/ca.cert";
+    const CLIENT_CERT: &str = "fixtures/client.cert";
+    const CLIENT_KEY: &str = "fixtures/client.key";
+    const SERVER_CERT: &str = "fixtures/server.cert";
+    const SERVER_KEY: &str = "fixtures/server.key";
+    const ROTATED_CERT: &str = "fixtures/rotated.cert";
+    const ROTATED_KEY: &str = "fixtures/rotated.key";
+
+    /// 在内存里的 duplex stream 上完成握手，并互相发一段数据
+    async fn handshake(
+        acceptor: TlsServerAcceptor,
+        connector: TlsClientConnector,
+    ) -> Result<(), KvError> {
+        let (client, server) = tokio::io::duplex(4096);
+
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server).await?;
+            assert_eq!(stream.get_ref().1.alpn_protocol(), Some(ALPN_KV.as_bytes()));
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await?;
+            stream.write_all(&buf).await?;
+            stream.flush().await?;
+            Ok::<_, KvError>(())
+        });
+
+        let mut stream = connector.connect(client).await?;
+        stream.write_all(b"hello").await?;
+        stream.flush().await?;
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello");
+
+        server.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn acceptor_should_negotiate_configured_alpn() {
+        let acceptor = TlsServerAcceptor::new(SERVER_CERT, SERVER_KEY, None)
             .unwrap()
-            .unwrap();
-        println!("{:?}", keys.secret_pkcs8_der());
+            .alpn(&["h2", "http/1.1"]);
+        let mut connector =
+            TlsClientConnector::new("kvserver.acme.inc", None, Some(CA_CERT)).unwrap();
+        let mut config = connector.config.as_ref().clone();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        connector.config = Arc::new(config);
+
+        let (client, server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move { acceptor.accept(server).await.map(|_| ()) });
+        let stream = connector.connect(client).await.unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_should_rotate_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = dir.path().join("server.cert");
+        let key = dir.path().join("server.key");
+        std::fs::copy(SERVER_CERT, &cert).unwrap();
+        std::fs::copy(SERVER_KEY, &key).unwrap();
+        let (cert, key) = (cert.to_str().unwrap(), key.to_str().unwrap());
+
+        let acceptor = TlsServerAcceptor::new(cert, key, None).unwrap();
+        let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(CA_CERT)).unwrap();
+        assert_eq!(
+            server_identity(&acceptor, &connector).await.as_deref(),
+            Some("Acme KV server")
+        );
+
+        // 坏掉的证书不会替换正在使用的配置
+        std::fs::write(cert, "broken").unwrap();
+        assert!(acceptor.reload().is_err());
+        assert_eq!(
+            server_identity(&acceptor, &connector).await.as_deref(),
+            Some("Acme KV server")
+        );
+
+        std::fs::copy(ROTATED_CERT, cert).unwrap();
+        std::fs::copy(ROTATED_KEY, key).unwrap();
+        acceptor.reload().unwrap();
+        assert_eq!(
+            server_identity(&acceptor, &connector).await.as_deref(),
+            Some("Acme KV server rotated")
+        );
+    }
+
+    #[tokio::test]
+    async fn watch_should_reload_changed_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = dir.path().join("server.cert");
+        let key = dir.path().join("server.key");
+        std::fs::copy(SERVER_CERT, &cert).unwrap();
+        std::fs::copy(SERVER_KEY, &key).unwrap();
+
+        let acceptor =
+            TlsServerAcceptor::new(cert.to_str().unwrap(), key.to_str().unwrap(), None).unwrap();
+        acceptor.watch(Duration::from_millis(10));
+
+        // 等待 mtime 一定发生变化
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        std::fs::copy(ROTATED_CERT, &cert).unwrap();
+        std::fs::copy(ROTATED_KEY, &key).unwrap();
+
+        let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(CA_CERT)).unwrap();
+        for _ in 0..100 {
+            let identity = server_identity(&acceptor, &connector).await;
+            if identity.as_deref() == Some("Acme KV server rotated") {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("certificate is not reloaded");
+    }
+
+    /// 握手并返回客户端看到的服务器证书的 CN
+    async fn server_identity(
+        acceptor: &TlsServerAcceptor,
+        connector: &TlsClientConnector,
+    ) -> Option<String> {
+        let (client, server) = tokio::io::duplex(4096);
+        let acceptor = acceptor.clone();
+        let server = tokio::spawn(async move { acceptor.accept(server).await.map(|_| ()) });
+        let stream = connector.connect(client).await.unwrap();
+        server.await.unwrap().unwrap();
+        let cert = stream.get_ref().1.peer_certificates()?.first()?.clone();
+        cert_identity(&cert)
+    }
+
+    #[test]
+    fn cert_identity_should_use_cn() {
+        let cert = &load_certs(CLIENT_CERT).unwrap()[0];
+        assert_eq!(cert_identity(cert).as_deref(), Some("awesome-device-id"));
+        let cert = &load_certs(SERVER_CERT).unwrap()[0];
+        assert_eq!(cert_identity(cert).as_deref(), Some("Acme KV server"));
+    }
+
+    #[tokio::test]
+    async fn client_identity_should_work_with_mtls() {
+        let acceptor = TlsServerAcceptor::new(SERVER_CERT, SERVER_KEY, Some(CA_CERT)).unwrap();
+        let connector = TlsClientConnector::new(
+            "kvserver.acme.inc",
+            Some((CLIENT_CERT, CLIENT_KEY)),
+            Some(CA_CERT),
+        )
+        .unwrap();
+        let (client, server) = tokio::io::duplex(4096);
+
+        let server = tokio::spawn(async move {
+            let stream = acceptor.accept(server).await.unwrap();
+            client_identity(&stream)
+        });
+        let _stream = connector.connect(client).await.unwrap();
+        assert_eq!(server.await.unwrap().as_deref(), Some("awesome-device-id"));
+    }
+
+    #[test]
+    fn load_certs_and_key_should_work() {
+        assert_eq!(load_certs(SERVER_CERT).unwrap().len(), 1);
+        assert!(load_key(SERVER_KEY).is_ok());
+        assert!(load_key(SERVER_CERT).is_err());
+        assert!(load_certs(SERVER_KEY).is_err());
+    }
+
+    #[tokio::test]
+    async fn tls_should_work() {
+        let acceptor = TlsServerAcceptor::new(SERVER_CERT, SERVER_KEY, None).unwrap();
+        let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(CA_CERT)).unwrap();
+        handshake(acceptor, connector).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tls_with_client_cert_should_work() {
+        let acceptor = TlsServerAcceptor::new(SERVER_CERT, SERVER_KEY, Some(CA_CERT)).unwrap();
+        let connector = TlsClientConnector::new(
+            "kvserver.acme.inc",
+            Some((CLIENT_CERT, CLIENT_KEY)),
+            Some(CA_CERT),
+        )
+        .unwrap();
+        handshake(acceptor, connector).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tls_without_client_cert_should_fail_when_mtls_required() {
+        let acceptor = TlsServerAcceptor::new(SERVER_CERT, SERVER_KEY, Some(CA_CERT)).unwrap();
+        let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(CA_CERT)).unwrap();
+        assert!(handshake(acceptor, connector).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn tls_with_bad_domain_should_fail() {
+        let acceptor = TlsServerAcceptor::new(SERVER_CERT, SERVER_KEY, None).unwrap();
+        let connector = TlsClientConnector::new("kvserver1.acme.inc", None, Some(CA_CERT)).unwrap();
+        assert!(handshake(acceptor, connector).await.is_err());
     }
 }
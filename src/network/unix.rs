@@ -0,0 +1,93 @@
+use std::{
+    fs, io,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::Path,
+};
+
+use tokio::net::UnixListener;
+
+/// 在 path 上监听 Unix domain socket，并把 socket 文件的权限设为 mode
+///
+/// 同一台机器上的进程能不能连接由文件权限决定。上次退出时留下的 socket 文件会被替换，
+/// 但 path 是其他类型的文件时返回错误，避免误删。
+///
+/// socket 先在一个只有自己能访问（0700）的临时目录里创建并设置好权限，再 rename 到 path，
+/// path 上不会出现按 umask 创建、权限更宽的 socket。
+pub fn bind_unix(path: impl AsRef<Path>, mode: u32) -> io::Result<UnixListener> {
+    let path = path.as_ref();
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {}
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    // 临时目录和 path 在同一个目录下，rename 不会跨文件系统；名字尽量短，socket 路径有长度限制
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let dir = parent.join(format!(".kv-{}", std::process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let res = bind_private(&dir.join("s"), path, mode);
+    let _ = fs::remove_dir_all(&dir);
+    res
+}
+
+fn bind_private(tmp: &Path, path: &Path, mode: u32) -> io::Result<UnixListener> {
+    let listener = UnixListener::bind(tmp)?;
+    fs::set_permissions(tmp, fs::Permissions::from_mode(mode))?;
+    fs::rename(tmp, path)?;
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KvClient, MemTable, ProstServerStream, Service, TlsClientConnector, UNIX_SCHEME};
+
+    #[tokio::test]
+    async fn client_should_connect_via_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kv.sock");
+        // 留下一个旧的 socket 文件，bind_unix 应该能覆盖它
+        drop(bind_unix(&path, 0o600).unwrap());
+        let listener = bind_unix(&path, 0o600).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // 创建 socket 用的临时目录已经删掉了
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let service = Service::new(MemTable::new());
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(ProstServerStream::new(stream).process(service.new_connection()));
+            }
+        });
+
+        // 配置了 TLS 也不在 Unix domain socket 上使用
+        let connector =
+            TlsClientConnector::new("kvserver.acme.inc", None, Some("fixtures/ca.cert")).unwrap();
+        let client = KvClient::builder(format!("{}{}", UNIX_SCHEME, path.display()))
+            .tls(connector)
+            .build();
+        client.hset("t1", "k1", "v1").await.unwrap();
+        assert_eq!(client.hget("t1", "k1").await.unwrap(), Some("v1".into()));
+    }
+
+    #[test]
+    fn bind_unix_should_not_remove_regular_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kv.sock");
+        fs::write(&path, "data").unwrap();
+        let err = bind_unix(&path, 0o600).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(path.is_file());
+    }
+}
@@ -1,17 +1,25 @@
 /// 来自客户端的命令请求
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CommandRequest {
     #[prost(
         oneof = "command_request::RequestData",
-        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9"
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15"
     )]
     pub request_data: ::core::option::Option<command_request::RequestData>,
+    /// 请求 id，服务器在响应中原样返回，用于在一个连接上同时发出多个请求时匹配响应
+    /// 使用较大的 tag，给 request_data 留出空间
+    #[prost(uint64, tag = "100")]
+    pub id: u64,
 }
 /// Nested message and enum types in `CommandRequest`.
 pub mod command_request {
     #[derive(PartialOrd)]
+    #[derive(::serde::Serialize, ::serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
     #[allow(clippy::derive_partial_eq_without_eq)]
     #[derive(Clone, PartialEq, ::prost::Oneof)]
     pub enum RequestData {
@@ -33,10 +41,24 @@ pub mod command_request {
         Hexist(super::Hexist),
         #[prost(message, tag = "9")]
         Hmexist(super::Hmexist),
+        #[prost(message, tag = "10")]
+        Custom(super::Custom),
+        #[prost(message, tag = "11")]
+        Eval(super::Eval),
+        #[prost(message, tag = "12")]
+        Auth(super::Auth),
+        #[prost(message, tag = "13")]
+        Hello(super::Hello),
+        #[prost(message, tag = "14")]
+        Ping(super::Ping),
+        #[prost(message, tag = "15")]
+        Pong(super::Pong),
     }
 }
 /// 服务器的响应
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CommandResponse {
@@ -52,9 +74,39 @@ pub struct CommandResponse {
     /// 成功返回的 kv pairs
     #[prost(message, repeated, tag = "4")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// 对应请求的 id
+    #[prost(uint64, tag = "5")]
+    pub id: u64,
+    /// 分块返回时，后面还有同一个请求的响应；最后一个响应的 more 为 false，表示结束
+    #[prost(bool, tag = "6")]
+    pub more: bool,
+    /// Hello 的响应里带上服务器的信息
+    #[prost(message, optional, tag = "7")]
+    pub hello: ::core::option::Option<ServerHello>,
+    /// 服务器发起的存活检查，id 为 0；客户端收到后要回复 Pong
+    #[prost(bool, tag = "8")]
+    pub ping: bool,
+    /// 如果不是 2xx，结构化的错误信息，客户端据此还原出具体的错误，不需要解析 message
+    #[prost(message, optional, tag = "9")]
+    pub error: ::core::option::Option<ErrorInfo>,
+}
+/// 结构化的错误信息
+#[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ErrorInfo {
+    #[prost(enumeration = "ErrorCode", tag = "1")]
+    pub code: i32,
+    /// 错误的参数，如 NOT_FOUND 的 table 和 key，顺序和 KvError 中的字段一样
+    #[prost(string, repeated, tag = "2")]
+    pub args: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 /// 从 table 中获取一个 key，返回 value
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hget {
@@ -65,14 +117,21 @@ pub struct Hget {
 }
 /// 从 table 中获取所有的 Kvpair
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hgetall {
     #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
+    /// 大于 0 时分块返回，每个响应最多 chunk_size 个 kv pair；0 表示一次全部返回
+    #[prost(uint32, tag = "2")]
+    pub chunk_size: u32,
 }
 /// 从 table 中获取一组 key，返回它们的 value
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmget {
@@ -83,6 +142,8 @@ pub struct Hmget {
 }
 /// 返回的值
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Value {
@@ -92,6 +153,8 @@ pub struct Value {
 /// Nested message and enum types in `Value`.
 pub mod value {
     #[derive(PartialOrd)]
+    #[derive(::serde::Serialize, ::serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
     #[allow(clippy::derive_partial_eq_without_eq)]
     #[derive(Clone, PartialEq, ::prost::Oneof)]
     pub enum Value {
@@ -109,6 +172,8 @@ pub mod value {
 }
 /// 返回的 kvpair
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Kvpair {
@@ -120,6 +185,8 @@ pub struct Kvpair {
 /// 往 table 里存一个 kvpair，
 /// 如果 table 不存在就创建这个 table
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hset {
@@ -131,6 +198,8 @@ pub struct Hset {
 /// 往 table 中存一组 kvpair，
 /// 如果 table 不存在就创建这个 table
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmset {
@@ -141,6 +210,8 @@ pub struct Hmset {
 }
 /// 从 table 中删除一个 key，返回它之前的值
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hdel {
@@ -151,6 +222,8 @@ pub struct Hdel {
 }
 /// 从 table 中删除一组 key，返回它们之前的值
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmdel {
@@ -161,6 +234,8 @@ pub struct Hmdel {
 }
 /// 查看 key 是否存在
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hexist {
@@ -171,6 +246,8 @@ pub struct Hexist {
 }
 /// 查看一组 key 是否存在
 #[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmexist {
@@ -179,3 +256,598 @@ pub struct Hmexist {
     #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
+/// 自定义命令，由服务端注册的 handler 处理
+#[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Custom {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub args: ::prost::alloc::vec::Vec<Value>,
+}
+/// 在服务端原子地执行一段脚本
+/// 脚本只能通过 get/set/del 访问 tables x keys 中声明过的 key
+#[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Eval {
+    #[prost(string, tag = "1")]
+    pub script: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub tables: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, repeated, tag = "3")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, repeated, tag = "4")]
+    pub args: ::prost::alloc::vec::Vec<Value>,
+}
+/// 连接建立后的认证，成功后这个连接上的其他命令才能执行
+#[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Auth {
+    #[prost(string, tag = "1")]
+    pub username: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub password: ::prost::alloc::string::String,
+}
+/// 连接建立后的握手，协商协议版本和帧的压缩算法
+/// compressions 为客户端能解压的算法，按优先级排列；服务器选出双方都支持的第一个，
+/// 在响应的 values 里返回，之后双方都使用这个算法压缩
+/// 服务器不支持 protocol_version 时返回 505 并断开连接
+#[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hello {
+    #[prost(enumeration = "Compression", repeated, tag = "1")]
+    pub compressions: ::prost::alloc::vec::Vec<i32>,
+    /// 客户端使用的协议版本；0 表示没有发送版本号的老客户端，按版本 1 处理
+    #[prost(uint32, tag = "2")]
+    pub protocol_version: u32,
+    /// 客户端的名字，用于日志
+    #[prost(string, tag = "3")]
+    pub client_name: ::prost::alloc::string::String,
+    /// 客户端支持的功能
+    #[prost(string, repeated, tag = "4")]
+    pub capabilities: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 服务器对 Hello 的回应
+#[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ServerHello {
+    #[prost(uint32, tag = "1")]
+    pub protocol_version: u32,
+    /// 服务器的版本，如 "0.1.0"
+    #[prost(string, tag = "2")]
+    pub server_version: ::prost::alloc::string::String,
+    /// 服务器支持的命令
+    #[prost(string, repeated, tag = "3")]
+    pub commands: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 服务器允许使用的压缩算法
+    #[prost(enumeration = "Compression", repeated, tag = "4")]
+    pub compressions: ::prost::alloc::vec::Vec<i32>,
+    /// 服务器支持的功能
+    #[prost(string, repeated, tag = "5")]
+    pub capabilities: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 服务器能兼容的最老的协议版本，和 protocol_version 一起是服务器支持的版本范围
+    #[prost(uint32, tag = "6")]
+    pub min_protocol_version: u32,
+}
+/// 存活检查，服务器回复 "PONG"；不需要认证
+#[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Ping {}
+/// 对服务器发起的存活检查的回复，服务器不会再回复
+#[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Pong {}
+/// 订阅一个 table 的数据变化
+#[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WatchRequest {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 一次数据变化；deleted 为 true 时表示 key 被删除，value 为空
+#[derive(PartialOrd)]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(default)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Change {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub value: ::core::option::Option<Value>,
+    #[prost(bool, tag = "4")]
+    pub deleted: bool,
+}
+/// 帧的压缩算法
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Compression {
+    None = 0,
+    Gzip = 1,
+    Lz4 = 2,
+    Zstd = 3,
+}
+impl Compression {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Compression::None => "NONE",
+            Compression::Gzip => "GZIP",
+            Compression::Lz4 => "LZ4",
+            Compression::Zstd => "ZSTD",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "NONE" => Some(Self::None),
+            "GZIP" => Some(Self::Gzip),
+            "LZ4" => Some(Self::Lz4),
+            "ZSTD" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+}
+/// 错误的类型，没有对应类型的错误为 UNKNOWN，客户端只能拿到 status 和 message
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum ErrorCode {
+    Unknown = 0,
+    NotFound = 1,
+    InvalidCommand = 2,
+    Unauthenticated = 3,
+    PermissionDenied = 4,
+    UnsupportedProtocol = 5,
+    TooManyConnections = 6,
+    Timeout = 7,
+    ScriptError = 8,
+    Overloaded = 9,
+}
+impl ErrorCode {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            ErrorCode::Unknown => "UNKNOWN",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::InvalidCommand => "INVALID_COMMAND",
+            ErrorCode::Unauthenticated => "UNAUTHENTICATED",
+            ErrorCode::PermissionDenied => "PERMISSION_DENIED",
+            ErrorCode::UnsupportedProtocol => "UNSUPPORTED_PROTOCOL",
+            ErrorCode::TooManyConnections => "TOO_MANY_CONNECTIONS",
+            ErrorCode::Timeout => "TIMEOUT",
+            ErrorCode::ScriptError => "SCRIPT_ERROR",
+            ErrorCode::Overloaded => "OVERLOADED",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "UNKNOWN" => Some(Self::Unknown),
+            "NOT_FOUND" => Some(Self::NotFound),
+            "INVALID_COMMAND" => Some(Self::InvalidCommand),
+            "UNAUTHENTICATED" => Some(Self::Unauthenticated),
+            "PERMISSION_DENIED" => Some(Self::PermissionDenied),
+            "UNSUPPORTED_PROTOCOL" => Some(Self::UnsupportedProtocol),
+            "TOO_MANY_CONNECTIONS" => Some(Self::TooManyConnections),
+            "TIMEOUT" => Some(Self::Timeout),
+            "SCRIPT_ERROR" => Some(Self::ScriptError),
+            "OVERLOADED" => Some(Self::Overloaded),
+            _ => None,
+        }
+    }
+}
+/// Generated client implementations.
+pub mod kv_client {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    use tonic::codegen::http::Uri;
+    #[derive(Debug, Clone)]
+    pub struct KvClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl KvClient<tonic::transport::Channel> {
+        /// Attempt to create a new client by connecting to a given endpoint.
+        pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+        where
+            D: TryInto<tonic::transport::Endpoint>,
+            D::Error: Into<StdError>,
+        {
+            let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+            Ok(Self::new(conn))
+        }
+    }
+    impl<T> KvClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_origin(inner: T, origin: Uri) -> Self {
+            let inner = tonic::client::Grpc::with_origin(inner, origin);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> KvClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T::ResponseBody: Default,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+            >>::Error: Into<StdError> + Send + Sync,
+        {
+            KvClient::new(InterceptedService::new(inner, interceptor))
+        }
+        /// Compress requests with the given encoding.
+        ///
+        /// This requires the server to support it otherwise it might respond with an
+        /// error.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.send_compressed(encoding);
+            self
+        }
+        /// Enable decompressing responses.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.accept_compressed(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_decoding_message_size(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_encoding_message_size(limit);
+            self
+        }
+        /// 执行一个命令
+        pub async fn execute(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CommandRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CommandResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/abi.Kv/Execute",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("abi.Kv", "Execute"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// 订阅一个 table 的数据变化
+        pub async fn watch(
+            &mut self,
+            request: impl tonic::IntoRequest<super::WatchRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::Change>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/abi.Kv/Watch",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("abi.Kv", "Watch"));
+            self.inner.server_streaming(req, path, codec).await
+        }
+    }
+}
+/// Generated server implementations.
+pub mod kv_server {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    /// Generated trait containing gRPC methods that should be implemented for use with KvServer.
+    #[async_trait]
+    pub trait Kv: Send + Sync + 'static {
+        /// 执行一个命令
+        async fn execute(
+            &self,
+            request: tonic::Request<super::CommandRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CommandResponse>,
+            tonic::Status,
+        >;
+        /// Server streaming response type for the Watch method.
+        type WatchStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::Change, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        /// 订阅一个 table 的数据变化
+        async fn watch(
+            &self,
+            request: tonic::Request<super::WatchRequest>,
+        ) -> std::result::Result<tonic::Response<Self::WatchStream>, tonic::Status>;
+    }
+    #[derive(Debug)]
+    pub struct KvServer<T: Kv> {
+        inner: _Inner<T>,
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+        max_decoding_message_size: Option<usize>,
+        max_encoding_message_size: Option<usize>,
+    }
+    struct _Inner<T>(Arc<T>);
+    impl<T: Kv> KvServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            let inner = _Inner(inner);
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+                max_decoding_message_size: None,
+                max_encoding_message_size: None,
+            }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.enable(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.enable(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.max_decoding_message_size = Some(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.max_encoding_message_size = Some(limit);
+            self
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for KvServer<T>
+    where
+        T: Kv,
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            let inner = self.inner.clone();
+            match req.uri().path() {
+                "/abi.Kv/Execute" => {
+                    #[allow(non_camel_case_types)]
+                    struct ExecuteSvc<T: Kv>(pub Arc<T>);
+                    impl<
+                        T: Kv,
+                    > tonic::server::UnaryService<super::CommandRequest>
+                    for ExecuteSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CommandRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Kv>::execute(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ExecuteSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.Kv/Watch" => {
+                    #[allow(non_camel_case_types)]
+                    struct WatchSvc<T: Kv>(pub Arc<T>);
+                    impl<
+                        T: Kv,
+                    > tonic::server::ServerStreamingService<super::WatchRequest>
+                    for WatchSvc<T> {
+                        type Response = super::Change;
+                        type ResponseStream = T::WatchStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::WatchRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Kv>::watch(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = WatchSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => {
+                    Box::pin(async move {
+                        Ok(
+                            http::Response::builder()
+                                .status(200)
+                                .header("grpc-status", "12")
+                                .header("content-type", "application/grpc")
+                                .body(empty_body())
+                                .unwrap(),
+                        )
+                    })
+                }
+            }
+        }
+    }
+    impl<T: Kv> Clone for KvServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+                max_decoding_message_size: self.max_decoding_message_size,
+                max_encoding_message_size: self.max_encoding_message_size,
+            }
+        }
+    }
+    impl<T: Kv> Clone for _Inner<T> {
+        fn clone(&self) -> Self {
+            Self(Arc::clone(&self.0))
+        }
+    }
+    impl<T: std::fmt::Debug> std::fmt::Debug for _Inner<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:?}", self.0)
+        }
+    }
+    impl<T: Kv> tonic::server::NamedService for KvServer<T> {
+        const NAME: &'static str = "abi.Kv";
+    }
+}
@@ -1,5 +1,7 @@
 pub mod abi;
 
+use std::borrow::Cow;
+
 use abi::{command_request::RequestData, *};
 use bytes::Bytes;
 use http::StatusCode;
@@ -7,6 +9,21 @@ use prost::Message;
 
 use crate::KvError;
 
+/// 当前的协议版本
+///
+/// 版本 1 是 Hello 中还没有版本号时的协议，版本 2 增加了 ServerHello，版本 3 增加了 Ping/Pong。
+/// 只增加命令或字段、老客户端仍然可以使用时，只增加 PROTOCOL_VERSION；
+/// 不再兼容老客户端时才提高 MIN_PROTOCOL_VERSION。
+pub const PROTOCOL_VERSION: u32 = 3;
+/// 能兼容的最老的协议版本，服务器在 ServerHello 里告诉客户端
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// 握手时互相告诉对方支持的功能
+///
+/// 客户端在 Hello 中带上 "ping" 时，服务器会在连接空闲时发送 `ping` 为 true 的响应检查客户端是否还活着。
+/// 客户端空闲时也必须读取连接才能回复 Pong，所以 `new_hello` 默认不带它，需要时用 `with_ping` 加上。
+pub const CAPABILITIES: &[&str] = &["pipelining", "chunked_hgetall", "compression", "ping"];
+
 impl Kvpair {
     /// 创建一个新的 kv pair
     pub fn new(key: impl Into<String>, value: Value) -> Self {
@@ -63,6 +80,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -71,7 +89,20 @@ impl CommandRequest {
         Self {
             request_data: Some(RequestData::Hgetall(Hgetall {
                 table: table.into(),
+                chunk_size: 0,
             })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建分块返回的 HGETALL 命令，每个响应最多 chunk_size 个 kv pair
+    pub fn new_hgetall_chunked(table: impl Into<String>, chunk_size: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Hgetall(Hgetall {
+                table: table.into(),
+                chunk_size,
+            })),
+            ..Default::default()
         }
     }
 
@@ -82,6 +113,88 @@ impl CommandRequest {
                 table: table.into(),
                 pair: Some(Kvpair::new(key, value)),
             })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建自定义命令
+    pub fn new_custom(name: impl Into<String>, args: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Custom(Custom {
+                name: name.into(),
+                args,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 EVAL 命令，脚本只能访问 tables x keys 中的 key
+    pub fn new_eval(
+        script: impl Into<String>,
+        tables: Vec<String>,
+        keys: Vec<String>,
+        args: Vec<Value>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Eval(Eval {
+                script: script.into(),
+                tables,
+                keys,
+                args,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 AUTH 命令
+    pub fn new_auth(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Auth(Auth {
+                username: username.into(),
+                password: password.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HELLO 握手，带上当前的协议版本
+    pub fn new_hello(client_name: impl Into<String>, compressions: &[Compression]) -> Self {
+        Self {
+            request_data: Some(RequestData::Hello(Hello {
+                compressions: compressions.iter().map(|c| *c as i32).collect(),
+                protocol_version: PROTOCOL_VERSION,
+                client_name: client_name.into(),
+                capabilities: CAPABILITIES
+                    .iter()
+                    .filter(|c| **c != "ping")
+                    .map(|s| s.to_string())
+                    .collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 在 HELLO 中声明客户端会回复服务器发起的 ping，对其他命令没有作用
+    pub fn with_ping(mut self) -> Self {
+        if let Some(RequestData::Hello(hello)) = &mut self.request_data {
+            hello.capabilities.push("ping".into());
+        }
+        self
+    }
+
+    /// 创建 PING，服务器回复 "PONG"
+    pub fn new_ping() -> Self {
+        Self {
+            request_data: Some(RequestData::Ping(Ping {})),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 PONG，回复服务器发起的存活检查
+    pub fn new_pong() -> Self {
+        Self {
+            request_data: Some(RequestData::Pong(Pong {})),
+            ..Default::default()
         }
     }
 
@@ -91,6 +204,27 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
+        }
+    }
+
+    /// 设置请求 id，用于在同一个连接上同时发出多个请求时匹配响应
+    pub fn with_id(mut self, id: u64) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// 去掉 Auth 密码之后的请求，用于打日志和通知 hook
+    pub fn redacted(&self) -> Cow<'_, CommandRequest> {
+        match &self.request_data {
+            Some(RequestData::Auth(auth)) => Cow::Owned(Self {
+                request_data: Some(RequestData::Auth(Auth {
+                    username: auth.username.clone(),
+                    password: "******".into(),
+                })),
+                ..*self
+            }),
+            _ => Cow::Borrowed(self),
         }
     }
 }
@@ -140,13 +274,26 @@ impl From<KvError> for CommandResponse {
         let mut result = Self {
             status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as _,
             message: e.to_string(),
-            values: vec![],
-            pairs: vec![],
+            error: ErrorInfo::new(&e),
+            ..Default::default()
         };
 
         match e {
             KvError::NotFound(_, _) => result.status = StatusCode::NOT_FOUND.as_u16() as _,
             KvError::InvalidCommand(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
+            KvError::Unauthenticated(_) => result.status = StatusCode::UNAUTHORIZED.as_u16() as _,
+            KvError::PermissionDenied(..) => result.status = StatusCode::FORBIDDEN.as_u16() as _,
+            KvError::UnsupportedProtocol(..) => {
+                result.status = StatusCode::HTTP_VERSION_NOT_SUPPORTED.as_u16() as _
+            }
+            KvError::TooManyConnections(_) | KvError::Overloaded(_) => {
+                result.status = StatusCode::SERVICE_UNAVAILABLE.as_u16() as _
+            }
+            KvError::Timeout(_) => result.status = StatusCode::GATEWAY_TIMEOUT.as_u16() as _,
+            KvError::ServerError(status, message) => {
+                result.status = status;
+                result.message = message;
+            }
             _ => {}
         }
         result
@@ -163,6 +310,92 @@ impl From<Vec<Value>> for CommandResponse {
     }
 }
 
+impl CommandResponse {
+    /// 服务器发起的存活检查
+    pub fn new_ping() -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            ping: true,
+            ..Default::default()
+        }
+    }
+
+    /// 把非 2xx 的响应还原成对应的 KvError，2xx 的响应原样返回
+    pub fn into_result(self) -> Result<Self, KvError> {
+        if (200..300).contains(&self.status) {
+            return Ok(self);
+        }
+
+        let err = self.error.as_ref().and_then(ErrorInfo::to_error);
+        Err(err.unwrap_or(KvError::ServerError(self.status, self.message)))
+    }
+}
+
+impl ErrorInfo {
+    /// 客户端可以还原的错误带上类型和参数，其他错误只有 status 和 message
+    pub fn new(e: &KvError) -> Option<Self> {
+        let (code, args) = match e {
+            KvError::NotFound(table, key) => {
+                (ErrorCode::NotFound, vec![table.clone(), key.clone()])
+            }
+            KvError::InvalidCommand(msg) => (ErrorCode::InvalidCommand, vec![msg.clone()]),
+            KvError::Unauthenticated(msg) => (ErrorCode::Unauthenticated, vec![msg.clone()]),
+            KvError::PermissionDenied(user, table, perms) => (
+                ErrorCode::PermissionDenied,
+                vec![user.clone(), table.clone(), perms.clone()],
+            ),
+            KvError::UnsupportedProtocol(version, min, max) => (
+                ErrorCode::UnsupportedProtocol,
+                vec![version.to_string(), min.to_string(), max.to_string()],
+            ),
+            KvError::TooManyConnections(msg) => (ErrorCode::TooManyConnections, vec![msg.clone()]),
+            KvError::Timeout(msg) => (ErrorCode::Timeout, vec![msg.clone()]),
+            KvError::ScriptError(msg) => (ErrorCode::ScriptError, vec![msg.clone()]),
+            KvError::Overloaded(msg) => (ErrorCode::Overloaded, vec![msg.clone()]),
+            _ => return None,
+        };
+        Some(Self {
+            code: code as i32,
+            args,
+        })
+    }
+
+    /// 还原成 KvError，不认识的错误类型或者参数对不上时返回 None
+    pub fn to_error(&self) -> Option<KvError> {
+        let code = ErrorCode::try_from(self.code).ok()?;
+        let err = match (code, self.args.as_slice()) {
+            (ErrorCode::NotFound, [table, key]) => KvError::NotFound(table.clone(), key.clone()),
+            (ErrorCode::InvalidCommand, [msg]) => KvError::InvalidCommand(msg.clone()),
+            (ErrorCode::Unauthenticated, [msg]) => KvError::Unauthenticated(msg.clone()),
+            (ErrorCode::PermissionDenied, [user, table, perms]) => {
+                KvError::PermissionDenied(user.clone(), table.clone(), perms.clone())
+            }
+            (ErrorCode::UnsupportedProtocol, [version, min, max]) => KvError::UnsupportedProtocol(
+                version.parse().ok()?,
+                min.parse().ok()?,
+                max.parse().ok()?,
+            ),
+            (ErrorCode::TooManyConnections, [msg]) => KvError::TooManyConnections(msg.clone()),
+            (ErrorCode::Timeout, [msg]) => KvError::Timeout(msg.clone()),
+            (ErrorCode::ScriptError, [msg]) => KvError::ScriptError(msg.clone()),
+            (ErrorCode::Overloaded, [msg]) => KvError::Overloaded(msg.clone()),
+            _ => return None,
+        };
+        Some(err)
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Bool(b)) => Ok(b),
+            _ => Err(KvError::ConvertError(v, "bool")),
+        }
+    }
+}
+
 impl TryFrom<&[u8]> for Value {
     type Error = KvError;
 
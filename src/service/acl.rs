@@ -0,0 +1,283 @@
+use std::{fmt, ops::BitOr, str::FromStr, sync::RwLock};
+
+use crate::{command_request::RequestData, CommandRequest, KvError};
+
+/// 一组权限：read / write / admin
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Permissions(u8);
+
+impl Permissions {
+    pub const NONE: Permissions = Permissions(0);
+    /// HGET / HGETALL / HMGET / HEXIST / HMEXIST
+    pub const READ: Permissions = Permissions(1);
+    /// HSET / HMSET / HDEL / HMDEL
+    pub const WRITE: Permissions = Permissions(1 << 1);
+    /// 自定义命令
+    pub const ADMIN: Permissions = Permissions(1 << 2);
+    pub const ALL: Permissions = Permissions(0b111);
+
+    pub fn contains(self, other: Permissions) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Permissions;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Permissions(self.0 | rhs.0)
+    }
+}
+
+/// 从 "read,write" 这样的字符串解析，"all" 表示所有权限
+impl FromStr for Permissions {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .try_fold(Permissions::NONE, |acc, p| {
+                let p = match p.to_ascii_lowercase().as_str() {
+                    "read" => Permissions::READ,
+                    "write" => Permissions::WRITE,
+                    "admin" => Permissions::ADMIN,
+                    "all" => Permissions::ALL,
+                    _ => {
+                        return Err(KvError::InvalidCommand(format!(
+                            "Unknown permission: {}",
+                            p
+                        )))
+                    }
+                };
+                Ok(acc | p)
+            })
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = [
+            (Permissions::READ, "read"),
+            (Permissions::WRITE, "write"),
+            (Permissions::ADMIN, "admin"),
+        ]
+        .into_iter()
+        .filter(|(p, _)| self.contains(*p))
+        .map(|(_, name)| name)
+        .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+/// 一条 ACL 规则：user 对匹配 table 这个 glob 的表拥有 permissions
+///
+/// user 为 `*` 时对所有已认证的身份生效；table 支持 `*` 和 `?` 通配符。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AclRule {
+    pub user: String,
+    pub table: String,
+    pub permissions: Permissions,
+}
+
+impl AclRule {
+    pub fn new(
+        user: impl Into<String>,
+        table: impl Into<String>,
+        permissions: Permissions,
+    ) -> Self {
+        Self {
+            user: user.into(),
+            table: table.into(),
+            permissions,
+        }
+    }
+
+    fn matches(&self, user: &str, table: &str) -> bool {
+        (self.user == "*" || self.user == user) && glob_match(&self.table, table)
+    }
+}
+
+/// 访问控制列表，在 Service::execute 里 dispatch 之前检查
+#[derive(Debug, Default)]
+pub struct Acl {
+    rules: RwLock<Vec<AclRule>>,
+}
+
+impl Acl {
+    pub fn new(rules: Vec<AclRule>) -> Self {
+        Self {
+            rules: RwLock::new(rules),
+        }
+    }
+
+    /// 整体替换规则，不需要重启服务
+    pub fn set_rules(&self, rules: Vec<AclRule>) {
+        *self.rules.write().unwrap_or_else(|e| e.into_inner()) = rules;
+    }
+
+    /// user 是否对 table 拥有 permissions
+    pub fn allows(&self, user: &str, table: &str, permissions: Permissions) -> bool {
+        let granted = self
+            .rules
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|r| r.matches(user, table))
+            .fold(Permissions::NONE, |acc, r| acc | r.permissions);
+        granted.contains(permissions)
+    }
+
+    /// 检查 user 能否执行 cmd；没有身份的连接返回 401，没有权限返回 403
+    pub fn check(&self, user: Option<&str>, cmd: &CommandRequest) -> Result<(), KvError> {
+        let requirements = required_permissions(cmd);
+        if requirements.is_empty() {
+            return Ok(());
+        }
+        let user = user
+            .ok_or_else(|| KvError::Unauthenticated("Connection has no identity for ACL".into()))?;
+        for (table, permissions) in requirements {
+            if !self.allows(user, table, permissions) {
+                return Err(KvError::PermissionDenied(
+                    user.into(),
+                    table.into(),
+                    permissions.to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 命令需要的权限：(table, permissions)
+///
+/// 自定义命令不知道会访问哪些 table，所以要求对 `*` 有 admin 权限。
+fn required_permissions(cmd: &CommandRequest) -> Vec<(&str, Permissions)> {
+    match &cmd.request_data {
+        Some(RequestData::Hget(p)) => vec![(&p.table, Permissions::READ)],
+        Some(RequestData::Hgetall(p)) => vec![(&p.table, Permissions::READ)],
+        Some(RequestData::Hmget(p)) => vec![(&p.table, Permissions::READ)],
+        Some(RequestData::Hexist(p)) => vec![(&p.table, Permissions::READ)],
+        Some(RequestData::Hmexist(p)) => vec![(&p.table, Permissions::READ)],
+        Some(RequestData::Hset(p)) => vec![(&p.table, Permissions::WRITE)],
+        Some(RequestData::Hmset(p)) => vec![(&p.table, Permissions::WRITE)],
+        Some(RequestData::Hdel(p)) => vec![(&p.table, Permissions::WRITE)],
+        Some(RequestData::Hmdel(p)) => vec![(&p.table, Permissions::WRITE)],
+        Some(RequestData::Eval(p)) => p
+            .tables
+            .iter()
+            .map(|t| (t.as_str(), Permissions::READ | Permissions::WRITE))
+            .collect(),
+        Some(RequestData::Custom(_)) => vec![("*", Permissions::ADMIN)],
+        Some(RequestData::Auth(_))
+        | Some(RequestData::Hello(_))
+        | Some(RequestData::Ping(_))
+        | Some(RequestData::Pong(_))
+        | None => vec![],
+    }
+}
+
+/// 简单的 glob 匹配，支持 `*`（任意个字符）和 `?`（一个字符）
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // 上一个 `*` 在 pattern 中的位置，以及当时 text 的位置
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            // 让上一个 `*` 多吃掉一个字符
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        hash_password,
+        service::command_services::{assert_res_error, assert_res_ok},
+        Authenticator, MemTable, Service, ServiceInner, Value,
+    };
+
+    #[test]
+    fn glob_match_should_work() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "users"));
+        assert!(glob_match("user*", "users"));
+        assert!(glob_match("u?ers", "users"));
+        assert!(glob_match("*:orders", "shop:orders"));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(!glob_match("user*", "orders"));
+        assert!(!glob_match("u?ers", "uers"));
+        assert!(!glob_match("a*b*c", "aXXbYY"));
+    }
+
+    #[test]
+    fn permissions_should_parse() {
+        let p: Permissions = "read, write".parse().unwrap();
+        assert!(p.contains(Permissions::READ));
+        assert!(p.contains(Permissions::WRITE));
+        assert!(!p.contains(Permissions::ADMIN));
+        assert_eq!(p.to_string(), "read,write");
+        assert_eq!("all".parse::<Permissions>().unwrap(), Permissions::ALL);
+        assert!("delete".parse::<Permissions>().is_err());
+    }
+
+    #[test]
+    fn acl_should_be_checked_before_dispatch() {
+        let auth = Authenticator::default();
+        auth.set_user("alice", hash_password("secret").unwrap());
+        let acl = Acl::new(vec![
+            AclRule::new("alice", "users*", Permissions::READ | Permissions::WRITE),
+            AclRule::new("alice", "orders", Permissions::READ),
+        ]);
+        let service: Service = ServiceInner::new(MemTable::new())
+            .authenticator(Arc::new(auth))
+            .acl(Arc::new(acl))
+            .into();
+        service.execute(CommandRequest::new_auth("alice", "secret"));
+
+        let res = service.execute(CommandRequest::new_hset("users", "k1", "v1".into()));
+        assert_res_ok(res, &[Value::default()], &[]);
+        let res = service.execute(CommandRequest::new_hget("orders", "k1"));
+        assert_res_error(res, 404, "Not found");
+
+        let res = service.execute(CommandRequest::new_hset("orders", "k1", "v1".into()));
+        assert_res_error(res, 403, "Permission denied");
+        let res = service.execute(CommandRequest::new_hget("admin", "k1"));
+        assert_res_error(res, 403, "Permission denied");
+        let res = service.execute(CommandRequest::new_custom("INCR", vec![]));
+        assert_res_error(res, 403, "Permission denied");
+    }
+
+    #[test]
+    fn acl_should_use_peer_identity() {
+        let acl = Acl::new(vec![AclRule::new("device-1", "*", Permissions::READ)]);
+        let service: Service = ServiceInner::new(MemTable::new()).acl(Arc::new(acl)).into();
+
+        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_res_error(res, 401, "no identity");
+
+        service.context().set_peer_identity("device-1");
+        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_res_error(res, 404, "Not found");
+        let res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_res_error(res, 403, "Permission denied");
+    }
+}
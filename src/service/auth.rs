@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use tracing::{info, warn};
+
+use crate::KvError;
+
+/// 用户认证，保存 用户名 -> argon2 hash（PHC 格式）
+///
+/// 凭证文件每行一个用户：`username:$argon2id$v=19$...`，`#` 开头的行是注释。
+/// 调用 reload（或者用 watch 定期检查文件）就可以在不重启服务的情况下更换凭证。
+#[derive(Debug, Default)]
+pub struct Authenticator {
+    users: RwLock<HashMap<String, String>>,
+    path: Option<PathBuf>,
+}
+
+impl Authenticator {
+    /// 用内存里的 用户名 -> hash 创建
+    pub fn new(users: HashMap<String, String>) -> Self {
+        Self {
+            users: RwLock::new(users),
+            path: None,
+        }
+    }
+
+    /// 从凭证文件加载
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        let path = path.as_ref().to_path_buf();
+        let users = load_credentials(&path)?;
+        Ok(Self {
+            users: RwLock::new(users),
+            path: Some(path),
+        })
+    }
+
+    /// 重新加载凭证文件，文件有错误时保留原来的凭证
+    pub fn reload(&self) -> Result<(), KvError> {
+        if let Some(path) = &self.path {
+            let users = load_credentials(path)?;
+            info!("Reloaded {} credentials from {:?}", users.len(), path);
+            *self.users.write().unwrap_or_else(|e| e.into_inner()) = users;
+        }
+        Ok(())
+    }
+
+    /// 添加或者替换一个用户的凭证
+    pub fn set_user(&self, username: impl Into<String>, hash: impl Into<String>) {
+        self.users
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(username.into(), hash.into());
+    }
+
+    pub fn remove_user(&self, username: &str) {
+        self.users
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(username);
+    }
+
+    /// 校验用户名密码
+    ///
+    /// 用户不存在时也会用一个固定的 hash 校验一遍，响应时间不会暴露哪些用户名存在。
+    /// argon2 很慢，在 async 代码里要放到 blocking 线程池里调用（`Service::execute_async` 会这样做）。
+    pub fn verify(&self, username: &str, password: &str) -> Result<(), KvError> {
+        let hash = self
+            .users
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(username)
+            .cloned();
+        let known = hash.is_some();
+        let hash = hash.unwrap_or_else(|| dummy_hash().to_string());
+        let hash = PasswordHash::new(&hash).map_err(|e| KvError::Internal(e.to_string()))?;
+        let verified = Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+        if known && verified {
+            Ok(())
+        } else {
+            Err(invalid_credentials())
+        }
+    }
+
+    /// 每隔 interval 检查一次凭证文件，文件修改后自动 reload
+    pub fn watch(self: &Arc<Self>, interval: Duration) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        let auth = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut last = modified(&path);
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(auth) = auth.upgrade() else {
+                    break;
+                };
+                let current = modified(&path);
+                if current != last {
+                    last = current;
+                    if let Err(e) = auth.reload() {
+                        warn!("Failed to reload credentials from {:?}: {}", path, e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// 生成密码的 argon2 hash，用来写入凭证文件
+pub fn hash_password(password: &str) -> Result<String, KvError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| KvError::Internal(e.to_string()))
+}
+
+fn load_credentials(path: &Path) -> Result<HashMap<String, String>, KvError> {
+    let content = std::fs::read_to_string(path)?;
+    let mut users = HashMap::new();
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(':') {
+            Some((user, hash)) if PasswordHash::new(hash).is_ok() => {
+                users.insert(user.trim().to_string(), hash.to_string());
+            }
+            _ => {
+                return Err(KvError::Internal(format!(
+                    "Invalid credential at {:?} line {}",
+                    path,
+                    n + 1
+                )))
+            }
+        }
+    }
+    Ok(users)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// 校验不存在的用户时使用的 hash，参数和真实用户的 hash 一样
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash_password("kv-dummy-password").unwrap_or_default())
+}
+
+fn invalid_credentials() -> KvError {
+    KvError::Unauthenticated("Invalid username or password".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        service::command_services::{assert_res_error, assert_res_ok},
+        CommandRequest, MemTable, Service, ServiceInner, Value,
+    };
+
+    fn service_with_user(user: &str, password: &str) -> Service {
+        let auth = Authenticator::default();
+        auth.set_user(user, hash_password(password).unwrap());
+        ServiceInner::new(MemTable::new())
+            .authenticator(Arc::new(auth))
+            .into()
+    }
+
+    #[test]
+    fn unauthenticated_connection_should_be_rejected() {
+        let service = service_with_user("alice", "secret");
+        let res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_res_error(res, 401, "Unauthenticated");
+    }
+
+    #[test]
+    fn auth_should_work_per_connection() {
+        let service = service_with_user("alice", "secret");
+
+        let res = service.execute(CommandRequest::new_auth("alice", "wrong"));
+        assert_res_error(res, 401, "Invalid username or password");
+
+        let res = service.execute(CommandRequest::new_auth("alice", "secret"));
+        assert_res_ok(res, &[true.into()], &[]);
+        assert_eq!(service.context().user(), Some("alice".into()));
+
+        let res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        // 新的连接需要重新认证，clone 出来的 Service 也是一个新的连接
+        for conn in [service.new_connection(), service.clone()] {
+            let res = conn.execute(CommandRequest::new_hget("t1", "k1"));
+            assert_res_error(res, 401, "Unauthenticated");
+        }
+    }
+
+    #[test]
+    fn unknown_user_should_take_as_long_as_wrong_password() {
+        let auth = Authenticator::default();
+        auth.set_user("alice", hash_password("secret").unwrap());
+        // 先生成固定的 hash，不把它算进第一次校验的时间里
+        dummy_hash();
+
+        let start = std::time::Instant::now();
+        assert!(auth.verify("alice", "wrong").is_err());
+        let known = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let err = auth.verify("bob", "wrong").unwrap_err();
+        let unknown = start.elapsed();
+
+        assert_eq!(err.to_string(), invalid_credentials().to_string());
+        // 两次都跑了一遍 argon2，时间在同一个量级
+        assert!(unknown * 4 > known, "{:?} vs {:?}", unknown, known);
+    }
+
+    #[test]
+    fn reload_should_rotate_credentials() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials");
+        let line = |pw: &str| format!("# kv users\nalice:{}\n", hash_password(pw).unwrap());
+
+        std::fs::write(&path, line("old")).unwrap();
+        let auth = Authenticator::from_file(&path).unwrap();
+        assert!(auth.verify("alice", "old").is_ok());
+
+        std::fs::write(&path, line("new")).unwrap();
+        auth.reload().unwrap();
+        assert!(auth.verify("alice", "old").is_err());
+        assert!(auth.verify("alice", "new").is_ok());
+
+        // 错误的文件不会影响现有的凭证
+        std::fs::write(&path, "alice").unwrap();
+        assert!(auth.reload().is_err());
+        assert!(auth.verify("alice", "new").is_ok());
+    }
+}
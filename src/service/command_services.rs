@@ -106,7 +106,7 @@ impl CommandService for Hmexist {
 
 #[cfg(test)]
 mod tests {
-    use std::thread;
+    use std::{sync::Mutex, thread};
 
     use super::*;
     use crate::{MemTable, Value};
@@ -130,6 +130,34 @@ mod tests {
         let res = service.execute(CommandRequest::new_hget("t1", "k1"));
         assert_res_ok(res, &["v1".into()], &[]);
     }
+
+    #[test]
+    fn hooks_should_see_connection_identity() {
+        static SEEN: Mutex<Option<String>> = Mutex::new(None);
+
+        let service: Service = ServiceInner::new(MemTable::default())
+            .fn_received_with_context(|ctx, _| *SEEN.lock().unwrap() = ctx.identity())
+            .into();
+        let conn = service.new_connection();
+        conn.context().set_peer_identity("awesome-device-id");
+        conn.execute(CommandRequest::new_hget("t1", "k1"));
+
+        assert_eq!(SEEN.lock().unwrap().as_deref(), Some("awesome-device-id"));
+    }
+
+    #[test]
+    fn hooks_should_not_see_passwords() {
+        static SEEN: Mutex<Option<CommandRequest>> = Mutex::new(None);
+
+        let service: Service = ServiceInner::new(MemTable::default())
+            .fn_received(|cmd| *SEEN.lock().unwrap() = Some(cmd.clone()))
+            .into();
+        service.execute(CommandRequest::new_auth("alice", "secret"));
+
+        let seen = SEEN.lock().unwrap().take().unwrap();
+        assert_ne!(seen, CommandRequest::new_auth("alice", "secret"));
+        assert_eq!(seen, CommandRequest::new_auth("alice", "******"));
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,63 @@
+use std::sync::{Arc, RwLock};
+
+/// 每个连接自己的上下文，记录这个连接的身份等信息
+///
+/// clone 出来的 context 共享同一份数据。`Service` 的 clone 不会 clone context，而是创建一个新的。
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionContext {
+    inner: Arc<RwLock<ContextInner>>,
+}
+
+#[derive(Debug, Default)]
+struct ContextInner {
+    /// 通过 Auth 命令登录的用户
+    user: Option<String>,
+    /// 传输层（如 mTLS 的客户端证书）确认过的身份
+    peer_identity: Option<String>,
+}
+
+impl ConnectionContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 当前连接的用户，未认证时为 None
+    pub fn user(&self) -> Option<String> {
+        self.inner
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .user
+            .clone()
+    }
+
+    /// 传输层确认过的身份，如 mTLS 客户端证书的 CN
+    pub fn peer_identity(&self) -> Option<String> {
+        self.inner
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .peer_identity
+            .clone()
+    }
+
+    /// 用于 ACL 判断的身份：优先使用 Auth 登录的用户，其次是传输层的身份
+    pub fn identity(&self) -> Option<String> {
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        inner.user.clone().or_else(|| inner.peer_identity.clone())
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.user().is_some()
+    }
+
+    pub(crate) fn set_user(&self, user: impl Into<String>) {
+        self.inner.write().unwrap_or_else(|e| e.into_inner()).user = Some(user.into());
+    }
+
+    /// 由传输层在连接建立时设置
+    pub fn set_peer_identity(&self, identity: impl Into<String>) {
+        self.inner
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .peer_identity = Some(identity.into());
+    }
+}
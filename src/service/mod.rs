@@ -1,12 +1,39 @@
+mod acl;
+mod auth;
 mod command_services;
+mod context;
+mod registry;
+mod response;
+mod script;
+mod tower_impl;
+mod watch;
 
-use std::{ops::Deref, sync::Arc};
+pub use acl::{Acl, AclRule, Permissions};
+pub use auth::{hash_password, Authenticator};
+pub use context::ConnectionContext;
+pub use registry::{CommandRegistry, CustomCommand};
+pub use response::ResponseStream;
+pub use script::{ScriptEngine, DEFAULT_MAX_OPERATIONS};
+pub(crate) use tower_impl::layer_error;
+pub use tower_impl::{ConnectionService, LayeredConnections, LayeredService};
+pub use watch::WATCH_CAPACITY;
 
-use tracing::debug;
+#[cfg(test)]
+pub(crate) use command_services::assert_res_ok;
+
+use std::{
+    ops::Deref,
+    sync::{Arc, RwLock},
+};
+
+use tokio::sync::broadcast;
+use tracing::{debug, info};
 
 use crate::{
-    command_request::RequestData, CommandRequest, CommandResponse, KvError, MemTable, Storage,
+    command_request::RequestData, Auth, Change, CommandRequest, CommandResponse, KvError, MemTable,
+    Storage, Value,
 };
+use watch::Watched;
 
 // 事件通知
 pub trait Notify<Arg> {
@@ -26,6 +53,20 @@ impl<Arg> Notify<Arg> for Vec<fn(&Arg)> {
     }
 }
 
+/// 带上连接上下文的事件通知，hook 可以据此拿到连接的身份
+pub trait NotifyWithContext<Arg> {
+    fn notify_with(&self, ctx: &ConnectionContext, arg: &Arg);
+}
+
+impl<Arg> NotifyWithContext<Arg> for Vec<fn(&ConnectionContext, &Arg)> {
+    #[inline]
+    fn notify_with(&self, ctx: &ConnectionContext, arg: &Arg) {
+        for f in self {
+            f(ctx, arg)
+        }
+    }
+}
+
 impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
     #[inline]
     fn notify(&self, arg: &mut Arg) {
@@ -41,14 +82,20 @@ pub trait CommandService {
 }
 
 /// Service 数据结构
+///
+/// 每个 Service 代表一个连接，有自己的连接上下文（身份、Auth 登录的状态）。
+/// clone 和 `new_connection` 一样，得到的是共享存储、但上下文全新的 Service，不会继承 Auth 登录的状态；
+/// 同一个连接上的请求需要共用同一个 Service（`ProstServerStream` 会这样做）。
 pub struct Service<Store = MemTable> {
     inner: Arc<ServiceInner<Store>>,
+    ctx: ConnectionContext,
 }
 
 impl<Store> Clone for Service<Store> {
     fn clone(&self) -> Self {
         Self {
             inner: Arc::clone(&self.inner),
+            ctx: ConnectionContext::new(),
         }
     }
 }
@@ -57,6 +104,7 @@ impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
     fn from(value: ServiceInner<Store>) -> Self {
         Self {
             inner: Arc::new(value),
+            ctx: ConnectionContext::new(),
         }
     }
 }
@@ -65,9 +113,20 @@ impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
 pub struct ServiceInner<Store> {
     store: Store,
     on_received: Vec<fn(&CommandRequest)>,
+    on_received_with_context: Vec<fn(&ConnectionContext, &CommandRequest)>,
     on_executed: Vec<fn(&CommandResponse)>,
     on_before_send: Vec<fn(&mut CommandResponse)>,
     on_after_send: Vec<fn()>,
+    commands: CommandRegistry,
+    scripts: ScriptEngine,
+    /// 普通命令拿读锁，Eval 拿写锁，保证脚本执行的原子性
+    lock: RwLock<()>,
+    /// 设置之后，连接必须先通过 Auth 认证才能执行其他命令
+    auth: Option<Arc<Authenticator>>,
+    /// 设置之后，每个命令执行前都要检查 ACL
+    acl: Option<Arc<Acl>>,
+    /// 数据变化的广播，由 Watch 订阅
+    changes: broadcast::Sender<Change>,
 }
 
 impl<Store: Storage> ServiceInner<Store> {
@@ -75,9 +134,16 @@ impl<Store: Storage> ServiceInner<Store> {
         Self {
             store,
             on_received: Vec::new(),
+            on_received_with_context: Vec::new(),
             on_executed: Vec::new(),
             on_before_send: Vec::new(),
             on_after_send: Vec::new(),
+            commands: CommandRegistry::new(),
+            scripts: ScriptEngine::default(),
+            lock: RwLock::new(()),
+            auth: None,
+            acl: None,
+            changes: broadcast::channel(WATCH_CAPACITY).0,
         }
     }
 
@@ -86,6 +152,12 @@ impl<Store: Storage> ServiceInner<Store> {
         self
     }
 
+    /// 和 fn_received 一样，但可以拿到连接上下文（身份等）
+    pub fn fn_received_with_context(mut self, f: fn(&ConnectionContext, &CommandRequest)) -> Self {
+        self.on_received_with_context.push(f);
+        self
+    }
+
     pub fn fn_executed(mut self, f: fn(&CommandResponse)) -> Self {
         self.on_executed.push(f);
         self
@@ -100,6 +172,46 @@ impl<Store: Storage> ServiceInner<Store> {
         self.on_after_send.push(f);
         self
     }
+
+    /// 注册一个自定义命令，客户端通过 `Custom { name, args }` 调用
+    pub fn register_command(
+        mut self,
+        name: impl Into<String>,
+        handler: impl CustomCommand + 'static,
+    ) -> Self {
+        self.commands.register(name, handler);
+        self
+    }
+
+    /// 开启认证
+    pub fn authenticator(mut self, auth: Arc<Authenticator>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// 开启访问控制，身份来自 Auth 登录或者 mTLS 客户端证书
+    pub fn acl(mut self, acl: Arc<Acl>) -> Self {
+        self.acl = Some(acl);
+        self
+    }
+
+    /// 设置 Eval 脚本最多执行的步数
+    pub fn script_max_operations(mut self, n: u64) -> Self {
+        self.scripts = ScriptEngine::new(n);
+        self
+    }
+
+    /// 给响应带上请求的 id，并触发 on_executed / on_before_send
+    fn finish(&self, id: u64, mut res: CommandResponse) -> CommandResponse {
+        res.id = id;
+        debug!("Executed response: {:?}", res);
+        self.on_executed.notify(&res);
+        self.on_before_send.notify(&mut res);
+        if !self.on_before_send.is_empty() {
+            debug!("Modified response: {:?}", res);
+        }
+        res
+    }
 }
 
 impl<Store: Storage> Deref for Service<Store> {
@@ -114,21 +226,176 @@ impl<Store: Storage> Service<Store> {
         ServiceInner::new(store).into()
     }
 
+    /// 为一个新的连接创建 Service，各个连接的上下文（认证状态等）互不影响
+    pub fn new_connection(&self) -> Self {
+        self.clone()
+    }
+
+    /// 当前连接的上下文
+    pub fn context(&self) -> &ConnectionContext {
+        &self.ctx
+    }
+
+    /// 同一个连接的另一个句柄，和 clone 不同，共享这个连接的上下文
+    pub(crate) fn same_connection(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+            ctx: self.ctx.clone(),
+        }
+    }
+
+    /// 执行一个命令，返回一个响应
+    ///
+    /// 带 chunk_size 的 Hgetall 在这里也会一次返回全部结果，需要分块返回时用 `execute_stream`。
     pub fn execute(&self, cmd: CommandRequest) -> CommandResponse {
-        debug!("Got request: {:?}", cmd);
+        self.received(&cmd);
+        let id = cmd.id;
+        let res = self.handle(cmd);
+        self.inner.finish(id, res)
+    }
+
+    /// 执行一个命令，返回它的所有响应
+    ///
+    /// 带 chunk_size 的 Hgetall 会分块返回，响应在迭代时才从存储中读取，不持有 Eval 的锁，
+    /// 所以不保证看到的是同一时刻的数据。其他命令和 `execute` 一样只有一个响应。
+    pub fn execute_stream(&self, cmd: CommandRequest) -> ResponseStream
+    where
+        Store: Send + Sync + 'static,
+    {
+        let (table, chunk_size) = match &cmd.request_data {
+            Some(RequestData::Hgetall(param)) if param.chunk_size > 0 => {
+                (param.table.clone(), param.chunk_size as usize)
+            }
+            _ => return self.execute(cmd).into(),
+        };
+
+        self.received(&cmd);
+        let id = cmd.id;
+        let pairs = self.authorize(&cmd).and_then(|()| {
+            let _guard = self.lock.read().unwrap_or_else(|e| e.into_inner());
+            self.inner.store.get_iter(&table)
+        });
+        match pairs {
+            Ok(pairs) => {
+                let inner = Arc::clone(&self.inner);
+                ResponseStream::new(
+                    ResponseStream::chunked(pairs, chunk_size)
+                        .map(move |res| inner.finish(id, res)),
+                )
+            }
+            Err(e) => self.inner.finish(id, e.into()).into(),
+        }
+    }
+
+    /// 和 `execute_stream` 一样，但耗时的 Eval 和 Auth（argon2）放到 blocking 线程池里执行，
+    /// 不会阻塞 tokio 的 worker，外层的超时也能在执行过程中生效
+    pub async fn execute_async(&self, cmd: CommandRequest) -> ResponseStream
+    where
+        Store: Send + Sync + 'static,
+    {
+        match &cmd.request_data {
+            Some(RequestData::Eval(_) | RequestData::Auth(_)) => {
+                let id = cmd.id;
+                let svc = self.same_connection();
+                match tokio::task::spawn_blocking(move || svc.execute(cmd)).await {
+                    Ok(res) => res.into(),
+                    Err(e) => self
+                        .inner
+                        .finish(id, KvError::Internal(e.to_string()).into())
+                        .into(),
+                }
+            }
+            _ => self.execute_stream(cmd),
+        }
+    }
+
+    /// 订阅一个 table 的数据变化，需要有这个 table 的读权限
+    ///
+    /// 返回的 Receiver 会收到所有 table 的变化，由调用者过滤。
+    /// 读取太慢、积压超过 `WATCH_CAPACITY` 个变化时，会收到 `RecvError::Lagged`。
+    pub fn watch(&self, table: &str) -> Result<broadcast::Receiver<Change>, KvError> {
+        if table.is_empty() {
+            return Err(KvError::InvalidCommand("Table is required to watch".into()));
+        }
+        self.authorize(&CommandRequest::new_hgetall(table))?;
+        Ok(self.changes.subscribe())
+    }
+
+    /// 把存储中缓存的数据写到磁盘，服务器退出前调用
+    pub fn flush(&self) -> Result<(), KvError> {
+        self.inner.store.flush()
+    }
+
+    /// 写操作都通过它访问存储，这样 Watch 才能收到变化
+    fn store(&self) -> Watched<'_, Store> {
+        Watched::new(&self.inner.store, &self.changes)
+    }
+
+    fn received(&self, cmd: &CommandRequest) {
+        // 不要把密码打到日志里，也不要交给 hook
+        let cmd = cmd.redacted();
+        debug!("Got request from {:?}: {:?}", self.ctx.identity(), cmd);
         self.on_received.notify(&cmd);
-        let mut res = dispatch(cmd, &self.inner.store);
-        debug!("Executed response: {:?}", res);
-        self.on_executed.notify(&res);
-        self.on_before_send.notify(&mut res);
-        if !self.on_before_send.is_empty() {
-            debug!("Modified response: {:?}", res);
+        self.on_received_with_context.notify_with(&self.ctx, &cmd);
+    }
+
+    /// 检查当前连接是否已经认证，以及 ACL 是否允许执行这个命令
+    fn authorize(&self, cmd: &CommandRequest) -> Result<(), KvError> {
+        if self.auth.is_some() && !self.ctx.is_authenticated() {
+            return Err(KvError::Unauthenticated(
+                "Please login with Auth first".into(),
+            ));
+        }
+        if let Some(acl) = &self.acl {
+            acl.check(self.ctx.identity().as_deref(), cmd)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&self, cmd: CommandRequest) -> CommandResponse {
+        match cmd.request_data {
+            Some(RequestData::Auth(param)) => return self.authenticate(param),
+            // 负载均衡器之类的存活检查不需要认证
+            Some(RequestData::Ping(_)) => return Value::from("PONG").into(),
+            _ => {}
+        }
+        if let Err(e) = self.authorize(&cmd) {
+            return e.into();
+        }
+
+        match cmd.request_data {
+            Some(RequestData::Eval(param)) => {
+                let _guard = self.lock.write().unwrap_or_else(|e| e.into_inner());
+                self.scripts.execute(param, &self.store())
+            }
+            Some(RequestData::Custom(param)) => {
+                let _guard = self.lock.read().unwrap_or_else(|e| e.into_inner());
+                self.commands.execute(param, &self.store())
+            }
+            _ => {
+                let _guard = self.lock.read().unwrap_or_else(|e| e.into_inner());
+                dispatch(cmd, &self.store())
+            }
+        }
+    }
+
+    fn authenticate(&self, param: Auth) -> CommandResponse {
+        let Some(auth) = &self.auth else {
+            return KvError::InvalidCommand("Authentication is not enabled".into()).into();
+        };
+        match auth.verify(&param.username, &param.password) {
+            Ok(()) => {
+                info!("User {} authenticated", param.username);
+                self.ctx.set_user(param.username);
+                Value::from(true).into()
+            }
+            Err(e) => e.into(),
         }
-        res
     }
 }
 
 // 从 Request 中得到 Response，目前处理 HGET/HGETALL/HSET
+// 自定义命令、Eval 和 Auth 需要 Service 里的状态，由 Service::execute 处理
 pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
     match cmd.request_data {
         Some(RequestData::Hget(param)) => param.execute(store),
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{CommandResponse, Custom, KvError, Storage, Value};
+
+/// 自定义命令的处理逻辑，和内置的 CommandService 一样直接操作 Storage
+pub trait CustomCommand: Send + Sync {
+    /// 处理自定义命令的参数，返回 Response
+    fn execute(&self, args: Vec<Value>, store: &dyn Storage) -> CommandResponse;
+}
+
+impl<F> CustomCommand for F
+where
+    F: Fn(Vec<Value>, &dyn Storage) -> CommandResponse + Send + Sync,
+{
+    fn execute(&self, args: Vec<Value>, store: &dyn Storage) -> CommandResponse {
+        self(args, store)
+    }
+}
+
+/// 自定义命令的注册表，命令名 -> 处理逻辑
+#[derive(Clone, Default)]
+pub struct CommandRegistry {
+    handlers: HashMap<String, Arc<dyn CustomCommand>>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册一个自定义命令，同名的命令会被覆盖
+    pub fn register(&mut self, name: impl Into<String>, handler: impl CustomCommand + 'static) {
+        self.handlers.insert(name.into(), Arc::new(handler));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.handlers.contains_key(name)
+    }
+
+    /// 找到对应的处理逻辑并执行，找不到返回 400
+    pub fn execute(&self, cmd: Custom, store: &dyn Storage) -> CommandResponse {
+        match self.handlers.get(&cmd.name) {
+            Some(handler) => handler.execute(cmd.args, store),
+            None => KvError::InvalidCommand(format!("Unknown command: {}", cmd.name)).into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        service::command_services::{assert_res_error, assert_res_ok},
+        value, CommandRequest, MemTable, Service, ServiceInner,
+    };
+
+    /// INCR table key [delta]：把 key 的整数值加上 delta，返回新的值
+    fn incr(args: Vec<Value>, store: &dyn Storage) -> CommandResponse {
+        let (table, key, delta) = match args.as_slice() {
+            [table, key, rest @ ..] => match (as_str(table), as_str(key)) {
+                (Some(table), Some(key)) => {
+                    let delta = match rest.first().and_then(|v| v.value.as_ref()) {
+                        Some(value::Value::Integer(i)) => *i,
+                        _ => 1,
+                    };
+                    (table, key, delta)
+                }
+                _ => {
+                    return KvError::InvalidCommand("INCR needs string table and key".into()).into()
+                }
+            },
+            _ => return KvError::InvalidCommand("INCR needs table and key".into()).into(),
+        };
+
+        let current = match store.get(table, key) {
+            Ok(Some(Value {
+                value: Some(value::Value::Integer(i)),
+            })) => i,
+            Ok(_) => 0,
+            Err(e) => return e.into(),
+        };
+        let value: Value = (current + delta).into();
+        match store.set(table, key, value.clone()) {
+            Ok(_) => value.into(),
+            Err(e) => e.into(),
+        }
+    }
+
+    fn as_str(v: &Value) -> Option<&str> {
+        match v.value.as_ref() {
+            Some(value::Value::String(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn custom_command_should_work() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .register_command("INCR", incr)
+            .into();
+
+        let cmd = CommandRequest::new_custom("INCR", vec!["t1".into(), "k1".into()]);
+        assert_res_ok(service.execute(cmd), &[1.into()], &[]);
+
+        let cmd = CommandRequest::new_custom("INCR", vec!["t1".into(), "k1".into(), 10.into()]);
+        assert_res_ok(service.execute(cmd), &[11.into()], &[]);
+
+        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_res_ok(res, &[11.into()], &[]);
+    }
+
+    #[test]
+    fn unknown_custom_command_should_return_400() {
+        let service = Service::new(MemTable::new());
+        let res = service.execute(CommandRequest::new_custom("NOPE", vec![]));
+        assert_res_error(res, 400, "Unknown command: NOPE");
+    }
+}
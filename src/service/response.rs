@@ -0,0 +1,114 @@
+use crate::{CommandResponse, KvError, Kvpair};
+
+/// 一个请求对应的所有响应
+///
+/// 大部分命令只有一个响应。分块的 Hgetall 会在迭代时才从 `Storage::get_iter` 取数据，
+/// 每次生成一个最多 chunk_size 个 kv pair 的响应（more 为 true），
+/// 最后用一个 more 为 false 的空响应表示结束。
+pub struct ResponseStream(Box<dyn Iterator<Item = CommandResponse> + Send>);
+
+impl ResponseStream {
+    pub fn new(iter: impl Iterator<Item = CommandResponse> + Send + 'static) -> Self {
+        Self(Box::new(iter))
+    }
+
+    /// 把 kv pair 按 chunk_size 分块，生成一组响应
+    pub fn chunked(pairs: Box<dyn Iterator<Item = Kvpair> + Send>, chunk_size: usize) -> Self {
+        Self::new(Chunks {
+            pairs: Some(pairs),
+            chunk_size: chunk_size.max(1),
+        })
+    }
+
+    /// 把所有响应合并成一个，给一个请求只能返回一个响应的协议（HTTP、gRPC）使用
+    ///
+    /// 分块的 Hgetall 会把各块的 pairs 合并起来，中途出错时返回出错的那个响应。
+    pub fn merge(mut self) -> CommandResponse {
+        let Some(mut merged) = self.next() else {
+            return KvError::Internal("Got no response".into()).into();
+        };
+        while merged.more {
+            let Some(res) = self.next() else {
+                return KvError::Internal("Response stream ended early".into()).into();
+            };
+            if res.error.is_some() {
+                return res;
+            }
+            merged.values.extend(res.values);
+            merged.pairs.extend(res.pairs);
+            merged.more = res.more;
+        }
+        merged
+    }
+}
+
+impl Iterator for ResponseStream {
+    type Item = CommandResponse;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl From<CommandResponse> for ResponseStream {
+    fn from(res: CommandResponse) -> Self {
+        Self::new(std::iter::once(res))
+    }
+}
+
+struct Chunks {
+    /// 数据取完、发出结束标记之后为 None
+    pairs: Option<Box<dyn Iterator<Item = Kvpair> + Send>>,
+    chunk_size: usize,
+}
+
+impl Iterator for Chunks {
+    type Item = CommandResponse;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pairs = self.pairs.as_mut()?;
+        let chunk: Vec<_> = pairs.take(self.chunk_size).collect();
+        if chunk.is_empty() {
+            self.pairs = None;
+            return Some(Vec::<Kvpair>::new().into());
+        }
+        let mut res = CommandResponse::from(chunk);
+        res.more = true;
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunked_response_should_end_with_marker() {
+        let pairs = (0..5).map(|i| Kvpair::new(format!("k{}", i), i.into()));
+        let responses: Vec<_> = ResponseStream::chunked(Box::new(pairs), 2).collect();
+
+        let sizes: Vec<_> = responses.iter().map(|r| (r.pairs.len(), r.more)).collect();
+        assert_eq!(sizes, [(2, true), (2, true), (1, true), (0, false)]);
+
+        let responses: Vec<_> = ResponseStream::chunked(Box::new(std::iter::empty()), 2).collect();
+        assert_eq!(responses.len(), 1);
+        assert!(!responses[0].more);
+    }
+
+    #[test]
+    fn merge_should_gather_all_chunks() {
+        let pairs = (0..5).map(|i| Kvpair::new(format!("k{}", i), i.into()));
+        let res = ResponseStream::chunked(Box::new(pairs), 2).merge();
+        assert_eq!(res.status, 200);
+        assert!(!res.more);
+        assert_eq!(res.pairs.len(), 5);
+
+        let res = ResponseStream::from(CommandResponse::from(vec![Kvpair::new("k", 1.into())]));
+        assert_eq!(res.merge().pairs.len(), 1);
+
+        let mut first = CommandResponse::from(vec![Kvpair::new("k", 1.into())]);
+        first.more = true;
+        let res = ResponseStream::new(vec![first].into_iter()).merge();
+        assert_eq!(res.status, 500);
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use rhai::{
+    module_resolvers::DummyModuleResolver, Array, Blob, Dynamic, Engine, EvalAltResult, Scope, AST,
+};
+use tracing::debug;
+
+use crate::{value, CommandResponse, Eval, KvError, Storage, Value};
+
+/// 缺省情况下，一个脚本最多执行的步数
+pub const DEFAULT_MAX_OPERATIONS: u64 = 100_000;
+/// 最多缓存的编译好的脚本数量
+const MAX_CACHED_SCRIPTS: usize = 1024;
+
+/// 执行 Eval 命令的脚本引擎（基于 rhai）
+///
+/// 脚本运行前，tables x keys 中声明的 key 会被读入一个快照，脚本里的 get/set/del 只操作这个快照，
+/// 脚本成功结束后再把改动一次性写回 Storage；脚本出错则所有改动都被丢弃。
+/// 配合 Service 里的锁，整个脚本相对于其他命令是原子的。
+pub struct ScriptEngine {
+    cache: Mutex<ScriptCache>,
+    max_operations: u64,
+}
+
+/// 编译好的脚本，按脚本内容的 blake3 摘要索引，满了之后淘汰最久没用过的
+///
+/// 用 256 位的密码学摘要做 key，找不到两个摘要相同的脚本，不会执行到别人的脚本。
+#[derive(Default)]
+struct ScriptCache {
+    scripts: HashMap<[u8; 32], CachedScript>,
+    /// 每次访问加一，用来找出最久没用过的脚本
+    clock: u64,
+}
+
+struct CachedScript {
+    ast: Arc<AST>,
+    last_used: u64,
+}
+
+impl Default for ScriptEngine {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_OPERATIONS)
+    }
+}
+
+/// 脚本可见的数据快照
+#[derive(Default)]
+struct Snapshot {
+    /// (table, key) -> 当前的值，None 表示不存在
+    values: HashMap<(String, String), Option<Value>>,
+    /// 被脚本改动过的 (table, key)
+    dirty: Vec<(String, String)>,
+}
+
+type SharedSnapshot = Arc<Mutex<Snapshot>>;
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+impl ScriptEngine {
+    pub fn new(max_operations: u64) -> Self {
+        Self {
+            cache: Mutex::default(),
+            max_operations,
+        }
+    }
+
+    /// 执行 Eval 命令，调用者需要保证执行期间没有其他命令修改 store
+    pub fn execute(&self, cmd: Eval, store: &dyn Storage) -> CommandResponse {
+        match self.eval(cmd, store) {
+            Ok(values) => values.into(),
+            Err(e) => e.into(),
+        }
+    }
+
+    fn eval(&self, cmd: Eval, store: &dyn Storage) -> Result<Vec<Value>, KvError> {
+        let snapshot = Arc::new(Mutex::new(load_snapshot(&cmd, store)?));
+        let engine = self.engine(snapshot.clone());
+        let ast = self.compile(&engine, &cmd.script)?;
+
+        let mut scope = Scope::new();
+        let args: Array = cmd.args.into_iter().map(to_dynamic).collect();
+        scope.push_constant("ARGS", args);
+
+        let result = engine
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &ast)
+            .map_err(|e| KvError::ScriptError(e.to_string()))?;
+        // 脚本返回数组时，每个元素对应 response 里的一个 value
+        let values = if result.is_array() {
+            let arr: Array = result.cast();
+            arr.into_iter()
+                .map(from_dynamic)
+                .collect::<Result<_, _>>()?
+        } else {
+            vec![from_dynamic(result)?]
+        };
+
+        // 脚本成功执行完毕，把改动写回 store
+        drop(engine);
+        let snapshot = Arc::try_unwrap(snapshot)
+            .map_err(|_| KvError::Internal("Script snapshot is still in use".into()))?
+            .into_inner()
+            .map_err(|e| KvError::Internal(e.to_string()))?;
+        commit_snapshot(snapshot, store)?;
+
+        Ok(values)
+    }
+
+    /// 编译脚本，按脚本的摘要缓存编译结果
+    fn compile(&self, engine: &Engine, script: &str) -> Result<Arc<AST>, KvError> {
+        let digest = *blake3::hash(script.as_bytes()).as_bytes();
+        if let Some(ast) = self.cache().get(&digest) {
+            return Ok(ast);
+        }
+
+        // 编译时不持有锁，同一个脚本同时第一次执行时可能会编译两次
+        let ast = Arc::new(
+            engine
+                .compile(script)
+                .map_err(|e| KvError::InvalidCommand(format!("Invalid script: {}", e)))?,
+        );
+        debug!("Compiled script of {} bytes", script.len());
+        self.cache().insert(digest, ast.clone());
+        Ok(ast)
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, ScriptCache> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 创建一个沙箱化的 rhai 引擎，只暴露操作快照的 get/set/del
+    ///
+    /// `import` 不能加载任何模块，脚本读不到服务器上的文件。
+    fn engine(&self, snapshot: SharedSnapshot) -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .set_module_resolver(DummyModuleResolver::new())
+            .set_max_operations(self.max_operations)
+            .set_max_call_levels(32)
+            .set_max_expr_depths(64, 32)
+            .disable_symbol("eval")
+            .on_print(|_| {})
+            .on_debug(|_, _, _| {});
+
+        let data = snapshot.clone();
+        engine.register_fn(
+            "get",
+            move |table: &str, key: &str| -> ScriptResult<Dynamic> {
+                let data = data.lock().unwrap();
+                match data.values.get(&full_key(table, key)) {
+                    Some(v) => Ok(v.clone().map(to_dynamic).unwrap_or(Dynamic::UNIT)),
+                    None => Err(undeclared(table, key)),
+                }
+            },
+        );
+
+        let data = snapshot.clone();
+        engine.register_fn(
+            "set",
+            move |table: &str, key: &str, value: Dynamic| -> ScriptResult<Dynamic> {
+                let value = from_dynamic(value).map_err(|e| e.to_string())?;
+                let mut data = data.lock().unwrap();
+                data.update(table, key, Some(value))
+            },
+        );
+
+        let data = snapshot;
+        engine.register_fn(
+            "del",
+            move |table: &str, key: &str| -> ScriptResult<Dynamic> {
+                let mut data = data.lock().unwrap();
+                data.update(table, key, None)
+            },
+        );
+
+        engine
+    }
+}
+
+impl ScriptCache {
+    fn get(&mut self, digest: &[u8; 32]) -> Option<Arc<AST>> {
+        self.clock += 1;
+        let script = self.scripts.get_mut(digest)?;
+        script.last_used = self.clock;
+        Some(script.ast.clone())
+    }
+
+    fn insert(&mut self, digest: [u8; 32], ast: Arc<AST>) {
+        if self.scripts.len() >= MAX_CACHED_SCRIPTS && !self.scripts.contains_key(&digest) {
+            // 只在缓存满了又遇到新脚本时才扫描一遍，比给每次访问维护顺序简单
+            let oldest = self
+                .scripts
+                .iter()
+                .min_by_key(|(_, script)| script.last_used)
+                .map(|(digest, _)| *digest);
+            if let Some(oldest) = oldest {
+                self.scripts.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        let last_used = self.clock;
+        self.scripts.insert(digest, CachedScript { ast, last_used });
+    }
+}
+
+impl Snapshot {
+    /// 修改快照中的一个 key，返回它之前的值
+    fn update(&mut self, table: &str, key: &str, value: Option<Value>) -> ScriptResult<Dynamic> {
+        let k = full_key(table, key);
+        match self.values.get_mut(&k) {
+            Some(v) => {
+                let old = std::mem::replace(v, value);
+                if !self.dirty.contains(&k) {
+                    self.dirty.push(k);
+                }
+                Ok(old.map(to_dynamic).unwrap_or(Dynamic::UNIT))
+            }
+            None => Err(undeclared(table, key)),
+        }
+    }
+}
+
+fn load_snapshot(cmd: &Eval, store: &dyn Storage) -> Result<Snapshot, KvError> {
+    let mut snapshot = Snapshot::default();
+    for table in &cmd.tables {
+        for key in &cmd.keys {
+            let value = store.get(table, key)?;
+            snapshot.values.insert(full_key(table, key), value);
+        }
+    }
+    Ok(snapshot)
+}
+
+fn commit_snapshot(mut snapshot: Snapshot, store: &dyn Storage) -> Result<(), KvError> {
+    for k in snapshot.dirty.drain(..) {
+        match snapshot.values.remove(&k) {
+            Some(Some(value)) => store.set(&k.0, &k.1, value)?,
+            _ => store.del(&k.0, &k.1)?,
+        };
+    }
+    Ok(())
+}
+
+fn full_key(table: &str, key: &str) -> (String, String) {
+    (table.into(), key.into())
+}
+
+fn undeclared(table: &str, key: &str) -> Box<EvalAltResult> {
+    format!(
+        "Key is not declared in Eval: table: {}, key: {}",
+        table, key
+    )
+    .into()
+}
+
+fn to_dynamic(v: Value) -> Dynamic {
+    match v.value {
+        Some(value::Value::String(s)) => s.into(),
+        Some(value::Value::Binary(b)) => Dynamic::from_blob(b.to_vec()),
+        Some(value::Value::Integer(i)) => i.into(),
+        Some(value::Value::Float(f)) => f.into(),
+        Some(value::Value::Bool(b)) => b.into(),
+        None => Dynamic::UNIT,
+    }
+}
+
+fn from_dynamic(v: Dynamic) -> Result<Value, KvError> {
+    if v.is_unit() {
+        return Ok(Value::default());
+    }
+    let type_name = v.type_name();
+    if v.is_string() {
+        return Ok(v.into_string().unwrap_or_default().into());
+    }
+    if v.is_int() {
+        return Ok(v.as_int().unwrap_or_default().into());
+    }
+    if v.is_float() {
+        return Ok(Value {
+            value: Some(value::Value::Float(v.as_float().unwrap_or_default())),
+        });
+    }
+    if v.is_bool() {
+        return Ok(v.as_bool().unwrap_or_default().into());
+    }
+    if v.is_blob() {
+        let blob: Blob = v.cast();
+        return Ok(bytes::Bytes::from(blob).into());
+    }
+    Err(KvError::ScriptError(format!(
+        "Cannot convert script value of type {} to Value",
+        type_name
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        service::command_services::{assert_res_error, assert_res_ok},
+        CommandRequest, MemTable, Service, ServiceInner,
+    };
+
+    #[test]
+    fn eval_should_read_modify_write() {
+        let service = Service::new(MemTable::new());
+        service.execute(CommandRequest::new_hset("t1", "counter", 10.into()));
+
+        let script = r#"
+            let v = get("t1", "counter");
+            set("t1", "counter", v + ARGS[0]);
+            set("t1", "flag", true);
+            [v, get("t1", "counter")]
+        "#;
+        let cmd = CommandRequest::new_eval(
+            script,
+            vec!["t1".into()],
+            vec!["counter".into(), "flag".into()],
+            vec![5.into()],
+        );
+        assert_res_ok(service.execute(cmd), &[10.into(), 15.into()], &[]);
+
+        let res = service.execute(CommandRequest::new_hget("t1", "counter"));
+        assert_res_ok(res, &[15.into()], &[]);
+        let res = service.execute(CommandRequest::new_hget("t1", "flag"));
+        assert_res_ok(res, &[true.into()], &[]);
+    }
+
+    #[test]
+    fn eval_error_should_discard_changes() {
+        let service = Service::new(MemTable::new());
+        let script = r#"
+            set("t1", "k1", "v1");
+            get("t1", "undeclared")
+        "#;
+        let cmd = CommandRequest::new_eval(script, vec!["t1".into()], vec!["k1".into()], vec![]);
+        assert_res_error(service.execute(cmd), 500, "not declared");
+
+        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn eval_should_respect_operation_limit() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .script_max_operations(1000)
+            .into();
+        let cmd = CommandRequest::new_eval("loop {}", vec![], vec![], vec![]);
+        assert_res_error(service.execute(cmd), 500, "operations");
+    }
+
+    #[test]
+    fn eval_invalid_script_should_return_400() {
+        let service = Service::new(MemTable::new());
+        let cmd = CommandRequest::new_eval("let = ;", vec![], vec![], vec![]);
+        assert_res_error(service.execute(cmd), 400, "Invalid script");
+    }
+
+    #[test]
+    fn compiled_script_should_be_cached() {
+        let engine = ScriptEngine::default();
+        let store = MemTable::new();
+        let cmd = Eval {
+            script: "ARGS[0] * 2".into(),
+            args: vec![21.into()],
+            ..Default::default()
+        };
+        assert_res_ok(engine.execute(cmd.clone(), &store), &[42.into()], &[]);
+        assert_res_ok(engine.execute(cmd, &store), &[42.into()], &[]);
+        assert_eq!(engine.cache().scripts.len(), 1);
+
+        // 不同的脚本使用各自的编译结果
+        let cmd = Eval {
+            script: "ARGS[0] * 3".into(),
+            args: vec![21.into()],
+            ..Default::default()
+        };
+        assert_res_ok(engine.execute(cmd, &store), &[63.into()], &[]);
+        assert_eq!(engine.cache().scripts.len(), 2);
+    }
+
+    #[test]
+    fn script_cache_should_evict_least_recently_used() {
+        let engine = ScriptEngine::default();
+        let store = MemTable::new();
+        let eval = |i: usize| Eval {
+            script: format!("{}", i),
+            ..Default::default()
+        };
+        for i in 0..MAX_CACHED_SCRIPTS {
+            engine.execute(eval(i), &store);
+        }
+        // 用过第一个脚本之后，缓存满时淘汰的是第二个
+        engine.execute(eval(0), &store);
+        engine.execute(eval(MAX_CACHED_SCRIPTS), &store);
+
+        let mut cache = engine.cache();
+        assert_eq!(cache.scripts.len(), MAX_CACHED_SCRIPTS);
+        let digest = |i: usize| *blake3::hash(eval(i).script.as_bytes()).as_bytes();
+        assert!(cache.get(&digest(0)).is_some());
+        assert!(cache.get(&digest(1)).is_none());
+        assert!(cache.get(&digest(MAX_CACHED_SCRIPTS)).is_some());
+    }
+
+    #[test]
+    fn eval_should_not_import_modules() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.rhai");
+        std::fs::write(&path, "export const SECRET = 42;").unwrap();
+
+        let service = Service::new(MemTable::new());
+        let script = format!("import {:?} as m; m::SECRET", path.with_extension(""));
+        let cmd = CommandRequest::new_eval(script, vec![], vec![], vec![]);
+        assert_res_error(service.execute(cmd), 500, "Module not found");
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+use tower::{
+    limit::ConcurrencyLimit,
+    load_shed::{error::Overloaded, LoadShed},
+    timeout::{error::Elapsed, Timeout},
+    BoxError, ServiceBuilder,
+};
+
+use crate::{CommandRequest, KvError, LimitsConfig, ResponseStream, Service, Storage};
+
+/// 服务器给每个连接的 Service 套上的 layer：请求超时、过载时直接拒绝、限制同时处理的请求数
+pub type LayeredService<Store> = Timeout<LoadShed<ConcurrencyLimit<Service<Store>>>>;
+
+/// 套上和 `LayeredService` 一样 layer 的 `ConnectionService`，clone 之后共用同一个并发限制
+pub type LayeredConnections = Timeout<LoadShed<ConcurrencyLimit<ConnectionService>>>;
+
+impl<Store> Service<Store> {
+    /// 按配置套上超时和并发限制，服务器的各个 listener 都这样处理请求
+    pub fn layered(self, limits: &LimitsConfig) -> LayeredService<Store> {
+        layers(self, limits)
+    }
+}
+
+/// 在请求带着的连接上执行命令
+///
+/// HTTP、gRPC 的每个请求都单独认证，是 Service 的一个新连接；
+/// 它们用这个 Service 在所有请求之间共用一套 layer，而不是每个请求都重新套一次。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConnectionService;
+
+impl ConnectionService {
+    /// 按配置套上超时和并发限制，和 `Service::layered` 一样
+    pub fn layered(limits: &LimitsConfig) -> LayeredConnections {
+        layers(ConnectionService, limits)
+    }
+}
+
+impl<Store> tower::Service<(Service<Store>, CommandRequest)> for ConnectionService
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    type Response = ResponseStream;
+    type Error = KvError;
+    type Future = BoxFuture<'static, Result<ResponseStream, KvError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, (mut conn, req): (Service<Store>, CommandRequest)) -> Self::Future {
+        tower::Service::call(&mut conn, req)
+    }
+}
+
+fn layers<S>(svc: S, limits: &LimitsConfig) -> Timeout<LoadShed<ConcurrencyLimit<S>>> {
+    ServiceBuilder::new()
+        .timeout(Duration::from_millis(limits.request_timeout_ms))
+        .load_shed()
+        .concurrency_limit(limits.max_concurrent_requests)
+        .service(svc)
+}
+
+/// 把 layer 返回的错误转换成 KvError：超时是 504，过载是 503，其他错误是 500
+pub(crate) fn layer_error(e: BoxError) -> KvError {
+    if e.is::<Elapsed>() {
+        return KvError::Timeout(e.to_string());
+    }
+    if e.is::<Overloaded>() {
+        return KvError::Overloaded(e.to_string());
+    }
+    match e.downcast::<KvError>() {
+        Ok(e) => *e,
+        Err(e) => KvError::Internal(e.to_string()),
+    }
+}
+
+/// 让 Service 可以直接放进 tower 的 layer 栈里（timeout、并发限制、限流、重试等）
+///
+/// Service 永远是 ready 的。命令在返回的 future 被 poll 时才执行（见 `Service::execute_async`），
+/// 所以超时和并发限制的 layer 覆盖的是命令真正执行的过程。
+/// 命令执行中的错误已经编码在 CommandResponse 的 status 里，这里的 Error 只会来自外层 layer。
+/// 分块返回的 Hgetall 会在迭代 ResponseStream 时才读取数据，见 `Service::execute_stream`。
+impl<Store> tower::Service<CommandRequest> for Service<Store>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    type Response = ResponseStream;
+    type Error = KvError;
+    type Future = BoxFuture<'static, Result<ResponseStream, KvError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: CommandRequest) -> Self::Future {
+        let svc = self.same_connection();
+        Box::pin(async move { Ok(svc.execute_async(req).await) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        service::command_services::assert_res_ok, CommandResponse, Kvpair, MemTable, ServiceInner,
+        Value,
+    };
+
+    #[tokio::test]
+    async fn layered_service_should_work() {
+        let service = Service::new(MemTable::new());
+        let svc = ServiceBuilder::new()
+            .timeout(Duration::from_secs(1))
+            .concurrency_limit(16)
+            .service(service.clone());
+
+        let mut res = svc
+            .oneshot(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await
+            .unwrap();
+        assert_res_ok(res.next().unwrap(), &[Value::default()], &[]);
+        assert!(res.next().is_none());
+
+        let mut res = service
+            .oneshot(CommandRequest::new_hget("t1", "k1"))
+            .await
+            .unwrap();
+        assert_res_ok(res.next().unwrap(), &["v1".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn layered_service_should_time_out_slow_commands() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .script_max_operations(u64::MAX)
+            .into();
+        let limits = LimitsConfig {
+            request_timeout_ms: 10,
+            ..Default::default()
+        };
+        let script = "let n = 0; for i in 0..2000000 { n += i; } n";
+        let res = service
+            .layered(&limits)
+            .oneshot(CommandRequest::new_eval(script, vec![], vec![], vec![]))
+            .await;
+        let e = layer_error(res.err().unwrap());
+        assert!(matches!(e, KvError::Timeout(_)));
+        assert_eq!(CommandResponse::from(e).status, 504);
+    }
+
+    #[tokio::test]
+    async fn layered_connections_should_share_concurrency_limit() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .script_max_operations(u64::MAX)
+            .into();
+        let limits = LimitsConfig {
+            max_concurrent_requests: 1,
+            ..Default::default()
+        };
+        let svc = ConnectionService::layered(&limits);
+
+        let script = "let n = 0; for i in 0..2000000 { n += i; } n";
+        let slow = tokio::spawn(svc.clone().oneshot((
+            service.new_connection(),
+            CommandRequest::new_eval(script, vec![], vec![], vec![]),
+        )));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let res = svc
+            .oneshot((
+                service.new_connection(),
+                CommandRequest::new_hget("t1", "k1"),
+            ))
+            .await;
+        assert!(matches!(
+            layer_error(res.err().unwrap()),
+            KvError::Overloaded(_)
+        ));
+        let mut res = slow.await.unwrap().unwrap();
+        assert_eq!(res.next().unwrap().status, 200);
+    }
+
+    #[test]
+    fn layer_error_should_keep_overload_apart_from_server_errors() {
+        let e = layer_error(Box::new(Overloaded::new()));
+        let res = CommandResponse::from(e);
+        assert_eq!(res.status, 503);
+        assert!(matches!(res.into_result(), Err(KvError::Overloaded(_))));
+
+        let e = layer_error(Box::new(KvError::Internal("oops".into())));
+        assert_eq!(CommandResponse::from(e).status, 500);
+    }
+
+    #[tokio::test]
+    async fn chunked_hgetall_should_return_many_responses() {
+        let service = Service::new(MemTable::new());
+        for i in 0..5 {
+            service.execute(CommandRequest::new_hset("t1", format!("k{}", i), i.into()));
+        }
+
+        let mut cmd = CommandRequest::new_hgetall_chunked("t1", 2);
+        cmd.id = 7;
+        let responses: Vec<_> = service.oneshot(cmd).await.unwrap().collect();
+        assert_eq!(responses.len(), 4);
+        assert!(responses.iter().all(|r| r.id == 7 && r.status == 200));
+        assert!(!responses[3].more && responses[3].pairs.is_empty());
+
+        let mut pairs: Vec<_> = responses.into_iter().flat_map(|r| r.pairs).collect();
+        pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let expected: Vec<_> = (0..5)
+            .map(|i| Kvpair::new(format!("k{}", i), i.into()))
+            .collect();
+        assert_eq!(pairs, expected);
+    }
+}
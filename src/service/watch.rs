@@ -0,0 +1,107 @@
+use tokio::sync::broadcast;
+
+use crate::{Change, KvError, Kvpair, Storage, Value};
+
+/// 每个订阅者最多缓存多少个还没读取的变化，超过之后订阅者会丢失数据
+pub const WATCH_CAPACITY: usize = 1024;
+
+/// 包装一个 Storage，写入成功之后把变化广播给订阅者
+///
+/// Hset / Hdel、Eval 提交的脚本结果和自定义命令都通过它访问存储，所以不会漏掉变化。
+pub(crate) struct Watched<'a, S> {
+    store: &'a S,
+    changes: &'a broadcast::Sender<Change>,
+}
+
+impl<'a, S: Storage> Watched<'a, S> {
+    pub(crate) fn new(store: &'a S, changes: &'a broadcast::Sender<Change>) -> Self {
+        Self { store, changes }
+    }
+
+    fn notify(&self, table: &str, key: &str, value: Option<Value>) {
+        // 没有订阅者的时候 send 会返回错误，直接忽略
+        let _ = self.changes.send(Change {
+            table: table.into(),
+            key: key.into(),
+            deleted: value.is_none(),
+            value,
+        });
+    }
+}
+
+impl<'a, S: Storage> Storage for Watched<'a, S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.store.get(table, key)
+    }
+
+    fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        if self.changes.receiver_count() == 0 {
+            return self.store.set(table, key, value);
+        }
+        let old = self.store.set(table, key, value.clone())?;
+        self.notify(table, key, Some(value));
+        Ok(old)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.store.contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let old = self.store.del(table, key)?;
+        if old.is_some() {
+            self.notify(table, key, None);
+        }
+        Ok(old)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.store.get_all(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError> {
+        self.store.get_iter(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    use super::*;
+    use crate::{CommandRequest, MemTable, Service};
+
+    fn change(key: &str, value: Option<Value>) -> Change {
+        Change {
+            table: "t1".into(),
+            key: key.into(),
+            deleted: value.is_none(),
+            value,
+        }
+    }
+
+    #[test]
+    fn watch_should_receive_changes() {
+        let service = Service::new(MemTable::new());
+        let mut rx = service.watch("t1").unwrap();
+
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        service.execute(CommandRequest::new_hdel("t1", "k1"));
+        // 删除不存在的 key 不算变化
+        service.execute(CommandRequest::new_hdel("t1", "k1"));
+        let script = r#"set("t1", "k2", 42)"#;
+        let cmd = CommandRequest::new_eval(script, vec!["t1".into()], vec!["k2".into()], vec![]);
+        service.execute(cmd);
+
+        assert_eq!(rx.try_recv().unwrap(), change("k1", Some("v1".into())));
+        assert_eq!(rx.try_recv().unwrap(), change("k1", None));
+        assert_eq!(rx.try_recv().unwrap(), change("k2", Some(42.into())));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn watch_should_require_table() {
+        let service = Service::new(MemTable::new());
+        assert!(matches!(service.watch(""), Err(KvError::InvalidCommand(_))));
+    }
+}
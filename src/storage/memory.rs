@@ -1,10 +1,26 @@
+use std::sync::Arc;
+
 use crate::{KvError, Kvpair, Storage, Value};
 use dashmap::{mapref::one::Ref, DashMap};
 
+type Table = Arc<DashMap<String, Value>>;
+
 /// 使用 DashMap 构建的 MemTable，实现了 Storage trait
-#[derive(Clone, Debug, Default)]
+#[derive(Debug, Default)]
 pub struct MemTable {
-    tables: DashMap<String, DashMap<String, Value>>,
+    tables: DashMap<String, Table>,
+}
+
+impl Clone for MemTable {
+    /// 复制所有数据，clone 出来的 MemTable 和原来的互不影响
+    fn clone(&self) -> Self {
+        let tables = self
+            .tables
+            .iter()
+            .map(|t| (t.key().clone(), Arc::new(t.value().as_ref().clone())))
+            .collect();
+        Self { tables }
+    }
 }
 
 impl MemTable {
@@ -14,7 +30,7 @@ impl MemTable {
     }
 
     /// 如果名为 name 的 hash table 不存在，则创建，否则返回
-    fn get_or_create_table(&self, name: &str) -> Ref<String, DashMap<String, Value>> {
+    fn get_or_create_table(&self, name: &str) -> Ref<String, Table> {
         match self.tables.get(name) {
             Some(table) => table,
             None => {
@@ -54,9 +70,41 @@ impl Storage for MemTable {
             .collect())
     }
 
-    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError> {
         let table = self.get_or_create_table(table).clone();
-        let iter = table.into_iter().map(|v| v.into());
-        Ok(Box::new(iter))
+        Ok(Box::new(TableIter {
+            table,
+            shard: 0,
+            current: Vec::new().into_iter(),
+        }))
+    }
+}
+
+/// 逐个 shard 遍历 table，每次只在读锁内复制一个 shard 的数据，而不是复制整个 table
+///
+/// 遍历期间其他请求写入的数据可能看得到，也可能看不到。
+struct TableIter {
+    table: Table,
+    shard: usize,
+    current: std::vec::IntoIter<Kvpair>,
+}
+
+impl Iterator for TableIter {
+    type Item = Kvpair;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(pair) = self.current.next() {
+                return Some(pair);
+            }
+            let shard = self.table.shards().get(self.shard)?;
+            self.shard += 1;
+            let pairs: Vec<_> = shard
+                .read()
+                .iter()
+                .map(|(k, v)| Kvpair::new(k, v.get().clone()))
+                .collect();
+            self.current = pairs.into_iter();
+        }
     }
 }
@@ -19,7 +19,13 @@ pub trait Storage {
     /// 遍历 HashTable，返回所有 kv pair（这个接口不好）
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
     /// 遍历 HashTable，返回 kv pair 的 Iterator
-    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError>;
+    ///
+    /// Iterator 不借用 Storage，可以交给其他线程慢慢消费（比如分块返回的 Hgetall）
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair> + Send>, KvError>;
+    /// 把缓存中的数据写到磁盘，服务器退出前调用；纯内存的存储不需要实现
+    fn flush(&self) -> Result<(), KvError> {
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -41,6 +47,19 @@ mod tests {
         test_get_iter(store);
     }
 
+    #[test]
+    fn sleddb_flush_should_persist_data() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(&dir);
+        store.set("t1", "k1", "v1".into()).unwrap();
+        store.flush().unwrap();
+        drop(store);
+
+        let store = SledDb::new(&dir);
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert!(MemTable::new().flush().is_ok());
+    }
+
     fn test_get_iter(store: impl Storage) {
         store.set("t2", "k1", "v1".into()).unwrap();
         store.set("t2", "k2", "v2".into()).unwrap();
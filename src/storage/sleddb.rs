@@ -9,7 +9,12 @@ pub struct SledDb(Db);
 
 impl SledDb {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(sled::open(path).unwrap())
+        Self::try_new(path).unwrap()
+    }
+
+    /// 打开 sled 数据库，失败时返回错误而不是 panic
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self, crate::KvError> {
+        Ok(Self(sled::open(path)?))
     }
 
     fn get_full_key(table: &str, key: &str) -> String {
@@ -68,11 +73,16 @@ impl Storage for SledDb {
     fn get_iter(
         &self,
         table: &str,
-    ) -> Result<Box<dyn Iterator<Item = crate::Kvpair>>, crate::KvError> {
+    ) -> Result<Box<dyn Iterator<Item = crate::Kvpair> + Send>, crate::KvError> {
         let prefix = SledDb::get_table_prefix(table);
         let iter = self.0.scan_prefix(prefix).into_iter().map(|v| v.into());
         Ok(Box::new(iter))
     }
+
+    fn flush(&self) -> Result<(), crate::KvError> {
+        self.0.flush()?;
+        Ok(())
+    }
 }
 
 impl From<Result<(IVec, IVec), sled::Error>> for Kvpair {